@@ -0,0 +1,237 @@
+use crate::failure::Failure;
+use crate::file::STDIO;
+use crate::global::Parameters;
+use crate::key::get_user_key;
+use crate::report;
+use anyhow::{Context, Result};
+use dexios::KeyCache;
+use serde_json::json;
+use std::path::Path;
+use std::sync::Arc;
+
+// this file handles the `batch` subcommands, which encrypt or decrypt many files in one run
+// the key is only read once, and it's only hashed with argon2id once per run (see `dexios::KeyCache`)
+// each file still gets its own master key, along with its own subkey of the argon2id hash
+// a failure doesn't stop the batch - every file is attempted, and a summary is printed at the end
+
+// this encrypts every input, and names each output by adding the suffix to the input's name
+// files are encrypted to the recipients if any were specified, otherwise the key is read once and cached
+pub fn encrypt(
+    inputs: &[&str],
+    suffix: &str,
+    output_dir: Option<&str>,
+    keyfile: &str,
+    memory: bool,
+    mut params: Parameters,
+) -> Result<()> {
+    let files = expand_inputs(inputs)?;
+    prepare_output(suffix, output_dir)?;
+
+    if params.recipients.is_empty() {
+        let raw_key = get_user_key(keyfile, true, params.password)?;
+        params.key_cache = Some(Arc::new(KeyCache::new(raw_key)));
+    }
+
+    run("Encrypted", &files, |input| {
+        let output = output_name(
+            input,
+            &format!("{}{}", file_name(input)?, suffix),
+            output_dir,
+        )?;
+
+        if memory {
+            crate::encrypt::memory_mode(input, &output, keyfile, &params)?;
+        } else {
+            crate::encrypt::stream_mode(input, &output, keyfile, &params)?;
+        }
+
+        Ok(output)
+    })
+}
+
+// this decrypts every input, and names each output by removing the suffix from the input's name
+// the key is read once and cached, unless an identity was specified
+pub fn decrypt(
+    inputs: &[&str],
+    suffix: &str,
+    output_dir: Option<&str>,
+    keyfile: &str,
+    memory: bool,
+    mut params: Parameters,
+) -> Result<()> {
+    let files = expand_inputs(inputs)?;
+    prepare_output(suffix, output_dir)?;
+
+    if params.identity.is_none() {
+        let raw_key = get_user_key(keyfile, false, params.password)?;
+        params.key_cache = Some(Arc::new(KeyCache::new(raw_key)));
+    }
+
+    run("Decrypted", &files, |input| {
+        let name = file_name(input)?;
+        let stripped = name
+            .strip_suffix(suffix)
+            .filter(|stripped| !stripped.is_empty())
+            .with_context(|| {
+                format!(
+                    "{} doesn't end with {}, so the output can't be named",
+                    input, suffix
+                )
+            })?;
+        let output = output_name(input, stripped, output_dir)?;
+
+        crate::decrypt::decrypt(input, &output, keyfile, memory, &params)?;
+
+        Ok(output)
+    })
+}
+
+// this runs the operation on every file, and then prints a summary of the results
+// it returns an error if any of the files failed, so that dexios exits with a non-zero code
+fn run(
+    verb: &str,
+    files: &[String],
+    mut operation: impl FnMut(&str) -> Result<String>,
+) -> Result<()> {
+    let mut results = Vec::new();
+    let mut entries = Vec::new();
+
+    for input in files {
+        let result = operation(input);
+
+        // the json report gets the fields that were recorded for this file, along with the result
+        let mut entry = report::take_fields();
+        entry["input"] = json!(input);
+        match &result {
+            Ok(output) => {
+                entry["output"] = json!(output);
+                entry["success"] = json!(true);
+            }
+            Err(err) => {
+                eprintln!("Error: {:#}", err);
+                entry["success"] = json!(false);
+                entry["error"] = json!({
+                    "kind": Failure::of(err).name(),
+                    "message": format!("{:#}", err),
+                });
+            }
+        }
+
+        entries.push(entry);
+        results.push((input, result));
+    }
+
+    let failed = results.iter().filter(|(_, result)| result.is_err()).count();
+
+    report::print_result(&format!(
+        "{} {} of {} file(s):",
+        verb,
+        files.len() - failed,
+        files.len()
+    ));
+    for (input, result) in &results {
+        match result {
+            Ok(output) => report::print_result(&format!("  ok      {} -> {}", input, output)),
+            Err(err) => report::print_result(&format!("  failed  {}: {:#}", input, err)),
+        }
+    }
+
+    report::record("files", entries);
+    report::record("succeeded", files.len() - failed);
+    report::record("failed", failed);
+
+    if failed > 0 {
+        return Err(anyhow::anyhow!(
+            "{} of {} file(s) failed",
+            failed,
+            files.len()
+        ));
+    }
+
+    Ok(())
+}
+
+// this expands any glob patterns within the inputs (e.g. for shells that don't expand them, or to avoid argument limits)
+// inputs that exist (or that aren't patterns) are used as-is, so missing files are reported in the summary
+fn expand_inputs(inputs: &[&str]) -> Result<Vec<String>> {
+    let mut files = Vec::new();
+
+    for input in inputs {
+        if *input == STDIO {
+            return Err(anyhow::anyhow!("stdin can't be used in batch mode")
+                .context(Failure::InvalidArguments));
+        }
+
+        if Path::new(input).exists() || !input.contains(['*', '?', '[']) {
+            files.push(input.to_string());
+            continue;
+        }
+
+        let paths = glob::glob(input)
+            .with_context(|| format!("Invalid glob pattern: {}", input))
+            .context(Failure::InvalidArguments)?;
+
+        let mut matches = 0;
+        for path in paths.filter_map(Result::ok).filter(|path| path.is_file()) {
+            let path = path
+                .to_str()
+                .context("Error converting file path to string")?;
+            files.push(path.to_string());
+            matches += 1;
+        }
+
+        if matches == 0 {
+            return Err(
+                anyhow::anyhow!("No files matched {}", input).context(Failure::InvalidArguments)
+            );
+        }
+    }
+
+    Ok(files)
+}
+
+// this checks that the outputs can be named, and creates the output directory if it doesn't exist yet
+fn prepare_output(suffix: &str, output_dir: Option<&str>) -> Result<()> {
+    if suffix.is_empty() && output_dir.is_none() {
+        return Err(anyhow::anyhow!(
+            "The suffix can't be empty unless an output directory is specified, as each output would overwrite its input"
+        )
+        .context(Failure::InvalidArguments));
+    }
+
+    if let Some(output_dir) = output_dir {
+        std::fs::create_dir_all(output_dir)
+            .with_context(|| format!("Unable to create the output directory: {}", output_dir))?;
+    }
+
+    Ok(())
+}
+
+fn file_name(input: &str) -> Result<&str> {
+    Path::new(input)
+        .file_name()
+        .and_then(std::ffi::OsStr::to_str)
+        .with_context(|| format!("Unable to get the file name of {}", input))
+}
+
+// the output is written next to the input, unless an output directory was specified
+fn output_name(input: &str, file_name: &str, output_dir: Option<&str>) -> Result<String> {
+    let path = match output_dir {
+        Some(output_dir) => Path::new(output_dir).join(file_name),
+        None => Path::new(input).with_file_name(file_name),
+    };
+
+    let output = path
+        .to_str()
+        .context("Error converting file path to string")?
+        .to_string();
+
+    if output == input {
+        return Err(anyhow::anyhow!(
+            "The output for {} would overwrite it - use a different suffix or output directory",
+            input
+        ));
+    }
+
+    Ok(output)
+}
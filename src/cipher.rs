@@ -0,0 +1,325 @@
+use crate::error::{Error, Result};
+use aead::{
+    stream::{DecryptorLE31, EncryptorLE31, NewStream, StreamLE31, StreamPrimitive},
+    Aead, NewAead, Payload,
+};
+use aes_gcm::{Aes256Gcm, Nonce};
+use chacha20poly1305::{ChaCha20Poly1305, XChaCha20Poly1305, XNonce};
+use secrecy::{ExposeSecret, Secret};
+
+// this file contains the ciphers that dexios supports, along with the modes they can be used in
+// the enums here allow the rest of the code to use either cipher without caring which one it is
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum CipherType {
+    AesGcm,
+    XChaCha20Poly1305,
+}
+
+impl std::fmt::Display for CipherType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            CipherType::AesGcm => write!(f, "AES-256-GCM"),
+            CipherType::XChaCha20Poly1305 => write!(f, "XChaCha20-Poly1305"),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum CipherMode {
+    MemoryMode,
+    StreamMode,
+}
+
+impl std::fmt::Display for CipherMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            CipherMode::MemoryMode => write!(f, "memory mode"),
+            CipherMode::StreamMode => write!(f, "stream mode"),
+        }
+    }
+}
+
+pub const WRAPPED_KEY_LEN: usize = 48; // 32 byte key + 16 byte AEAD tag
+
+// this returns the length of the nonce that is stored within the header
+// stream mode nonces are shorter, as the LE31 stream uses the last 4 bytes as a counter
+pub fn nonce_len(cipher_type: CipherType, cipher_mode: CipherMode) -> usize {
+    match (cipher_type, cipher_mode) {
+        (CipherType::AesGcm, CipherMode::MemoryMode) => 12,
+        (CipherType::AesGcm, CipherMode::StreamMode) => 8,
+        (CipherType::XChaCha20Poly1305, CipherMode::MemoryMode) => 24,
+        (CipherType::XChaCha20Poly1305, CipherMode::StreamMode) => 20,
+    }
+}
+
+pub enum MemoryCiphers {
+    AesGcm(Box<Aes256Gcm>),
+    XChaCha(Box<XChaCha20Poly1305>),
+}
+
+pub enum EncryptStreamCiphers {
+    AesGcm(Box<EncryptorLE31<Aes256Gcm>>),
+    XChaCha(Box<EncryptorLE31<XChaCha20Poly1305>>),
+}
+
+pub enum DecryptStreamCiphers {
+    AesGcm(Box<DecryptorLE31<Aes256Gcm>>),
+    XChaCha(Box<DecryptorLE31<XChaCha20Poly1305>>),
+}
+
+// unlike the encryptor/decryptor above, this doesn't keep track of the block counter itself
+// any block can be encrypted or decrypted by its position, so blocks can be handled on separate threads (or out of order)
+// the output is identical to that of the encryptor/decryptor, so the two can be mixed freely
+pub enum StreamCiphers {
+    AesGcm(Box<StreamLE31<Aes256Gcm>>),
+    XChaCha(Box<StreamLE31<XChaCha20Poly1305>>),
+}
+
+impl MemoryCiphers {
+    // this creates the cipher from the hashed key
+    // the key is dropped as soon as the cipher has been created
+    pub fn initialize(key: Secret<[u8; 32]>, cipher_type: CipherType) -> Result<Self> {
+        let cipher = match cipher_type {
+            CipherType::AesGcm => {
+                let cipher = Aes256Gcm::new_from_slice(key.expose_secret())
+                    .map_err(|_| Error::CipherInit)?;
+                MemoryCiphers::AesGcm(Box::new(cipher))
+            }
+            CipherType::XChaCha20Poly1305 => {
+                let cipher = XChaCha20Poly1305::new_from_slice(key.expose_secret())
+                    .map_err(|_| Error::CipherInit)?;
+                MemoryCiphers::XChaCha(Box::new(cipher))
+            }
+        };
+        drop(key);
+
+        Ok(cipher)
+    }
+
+    // the nonce must be the correct length for the cipher, or this will panic
+    pub fn encrypt<'msg, 'aad>(
+        &self,
+        nonce: &[u8],
+        payload: impl Into<Payload<'msg, 'aad>>,
+    ) -> aead::Result<Vec<u8>> {
+        match self {
+            MemoryCiphers::AesGcm(c) => c.encrypt(Nonce::from_slice(nonce), payload),
+            MemoryCiphers::XChaCha(c) => c.encrypt(XNonce::from_slice(nonce), payload),
+        }
+    }
+
+    pub fn decrypt<'msg, 'aad>(
+        &self,
+        nonce: &[u8],
+        payload: impl Into<Payload<'msg, 'aad>>,
+    ) -> aead::Result<Vec<u8>> {
+        match self {
+            MemoryCiphers::AesGcm(c) => c.decrypt(Nonce::from_slice(nonce), payload),
+            MemoryCiphers::XChaCha(c) => c.decrypt(XNonce::from_slice(nonce), payload),
+        }
+    }
+}
+
+impl EncryptStreamCiphers {
+    // this creates the LE31 stream from the hashed key and the 8/20 byte nonce
+    pub fn initialize(
+        key: Secret<[u8; 32]>,
+        nonce: &[u8],
+        cipher_type: CipherType,
+    ) -> Result<Self> {
+        if nonce.len() != nonce_len(cipher_type, CipherMode::StreamMode) {
+            return Err(Error::InvalidHeader("the nonce is the wrong length"));
+        }
+
+        let streams = match cipher_type {
+            CipherType::AesGcm => {
+                let cipher = Aes256Gcm::new_from_slice(key.expose_secret())
+                    .map_err(|_| Error::CipherInit)?;
+                let stream = EncryptorLE31::from_aead(cipher, nonce.into());
+                EncryptStreamCiphers::AesGcm(Box::new(stream))
+            }
+            CipherType::XChaCha20Poly1305 => {
+                let cipher = XChaCha20Poly1305::new_from_slice(key.expose_secret())
+                    .map_err(|_| Error::CipherInit)?;
+                let stream = EncryptorLE31::from_aead(cipher, nonce.into());
+                EncryptStreamCiphers::XChaCha(Box::new(stream))
+            }
+        };
+        drop(key);
+
+        Ok(streams)
+    }
+
+    pub fn encrypt_next<'msg, 'aad>(
+        &mut self,
+        payload: impl Into<Payload<'msg, 'aad>>,
+    ) -> aead::Result<Vec<u8>> {
+        match self {
+            EncryptStreamCiphers::AesGcm(s) => s.encrypt_next(payload),
+            EncryptStreamCiphers::XChaCha(s) => s.encrypt_next(payload),
+        }
+    }
+
+    pub fn encrypt_last<'msg, 'aad>(
+        self,
+        payload: impl Into<Payload<'msg, 'aad>>,
+    ) -> aead::Result<Vec<u8>> {
+        match self {
+            EncryptStreamCiphers::AesGcm(s) => s.encrypt_last(payload),
+            EncryptStreamCiphers::XChaCha(s) => s.encrypt_last(payload),
+        }
+    }
+}
+
+impl DecryptStreamCiphers {
+    // this creates the LE31 stream from the hashed key and the 8/20 byte nonce
+    pub fn initialize(
+        key: Secret<[u8; 32]>,
+        nonce: &[u8],
+        cipher_type: CipherType,
+    ) -> Result<Self> {
+        if nonce.len() != nonce_len(cipher_type, CipherMode::StreamMode) {
+            return Err(Error::InvalidHeader("the nonce is the wrong length"));
+        }
+
+        let streams = match cipher_type {
+            CipherType::AesGcm => {
+                let cipher = Aes256Gcm::new_from_slice(key.expose_secret())
+                    .map_err(|_| Error::CipherInit)?;
+                let stream = DecryptorLE31::from_aead(cipher, nonce.into());
+                DecryptStreamCiphers::AesGcm(Box::new(stream))
+            }
+            CipherType::XChaCha20Poly1305 => {
+                let cipher = XChaCha20Poly1305::new_from_slice(key.expose_secret())
+                    .map_err(|_| Error::CipherInit)?;
+                let stream = DecryptorLE31::from_aead(cipher, nonce.into());
+                DecryptStreamCiphers::XChaCha(Box::new(stream))
+            }
+        };
+        drop(key);
+
+        Ok(streams)
+    }
+
+    pub fn decrypt_next<'msg, 'aad>(
+        &mut self,
+        payload: impl Into<Payload<'msg, 'aad>>,
+    ) -> aead::Result<Vec<u8>> {
+        match self {
+            DecryptStreamCiphers::AesGcm(s) => s.decrypt_next(payload),
+            DecryptStreamCiphers::XChaCha(s) => s.decrypt_next(payload),
+        }
+    }
+
+    pub fn decrypt_last<'msg, 'aad>(
+        self,
+        payload: impl Into<Payload<'msg, 'aad>>,
+    ) -> aead::Result<Vec<u8>> {
+        match self {
+            DecryptStreamCiphers::AesGcm(s) => s.decrypt_last(payload),
+            DecryptStreamCiphers::XChaCha(s) => s.decrypt_last(payload),
+        }
+    }
+}
+
+impl StreamCiphers {
+    // this creates the LE31 stream from the hashed key and the 8/20 byte nonce
+    pub fn initialize(
+        key: Secret<[u8; 32]>,
+        nonce: &[u8],
+        cipher_type: CipherType,
+    ) -> Result<Self> {
+        if nonce.len() != nonce_len(cipher_type, CipherMode::StreamMode) {
+            return Err(Error::InvalidHeader("the nonce is the wrong length"));
+        }
+
+        let streams = match cipher_type {
+            CipherType::AesGcm => {
+                let cipher = Aes256Gcm::new_from_slice(key.expose_secret())
+                    .map_err(|_| Error::CipherInit)?;
+                let stream = StreamLE31::from_aead(cipher, nonce.into());
+                StreamCiphers::AesGcm(Box::new(stream))
+            }
+            CipherType::XChaCha20Poly1305 => {
+                let cipher = XChaCha20Poly1305::new_from_slice(key.expose_secret())
+                    .map_err(|_| Error::CipherInit)?;
+                let stream = StreamLE31::from_aead(cipher, nonce.into());
+                StreamCiphers::XChaCha(Box::new(stream))
+            }
+        };
+        drop(key);
+
+        Ok(streams)
+    }
+
+    // this encrypts a block in place, appending the AEAD tag
+    // the position is the block's index within the stream, and the last block must be flagged as such
+    pub fn encrypt_block(
+        &self,
+        position: u32,
+        last_block: bool,
+        aad: &[u8],
+        buffer: &mut Vec<u8>,
+    ) -> aead::Result<()> {
+        match self {
+            StreamCiphers::AesGcm(s) => s.encrypt_in_place(position, last_block, aad, buffer),
+            StreamCiphers::XChaCha(s) => s.encrypt_in_place(position, last_block, aad, buffer),
+        }
+    }
+
+    // this decrypts a block (including the AEAD tag) in place, removing the tag
+    pub fn decrypt_block(
+        &self,
+        position: u32,
+        last_block: bool,
+        aad: &[u8],
+        buffer: &mut Vec<u8>,
+    ) -> aead::Result<()> {
+        match self {
+            StreamCiphers::AesGcm(s) => s.decrypt_in_place(position, last_block, aad, buffer),
+            StreamCiphers::XChaCha(s) => s.decrypt_in_place(position, last_block, aad, buffer),
+        }
+    }
+}
+
+// this wraps (encrypts) a master key, so that it can be stored within a key slot
+// ChaCha20-Poly1305 is always used for this, regardless of the cipher that encrypts the data
+// the wrapping key must only ever be used once (it's derived with a unique salt or ephemeral key), so a zero nonce is safe
+pub fn wrap_key(
+    wrapping_key: &Secret<[u8; 32]>,
+    master_key: &Secret<[u8; 32]>,
+) -> Result<[u8; WRAPPED_KEY_LEN]> {
+    let cipher = ChaCha20Poly1305::new_from_slice(wrapping_key.expose_secret())
+        .map_err(|_| Error::CipherInit)?;
+
+    let encrypted = cipher
+        .encrypt(
+            &chacha20poly1305::Nonce::default(),
+            master_key.expose_secret().as_slice(),
+        )
+        .map_err(|_| Error::Encrypt)?;
+
+    let mut wrapped_key = [0u8; WRAPPED_KEY_LEN];
+    wrapped_key.copy_from_slice(&encrypted);
+    Ok(wrapped_key)
+}
+
+// this unwraps a master key from a key slot
+// it returns None if the wrapping key is incorrect (or the key slot has been tampered with)
+pub fn unwrap_key(
+    wrapping_key: &Secret<[u8; 32]>,
+    wrapped_key: &[u8; WRAPPED_KEY_LEN],
+) -> Option<Secret<[u8; 32]>> {
+    let cipher = ChaCha20Poly1305::new_from_slice(wrapping_key.expose_secret()).ok()?;
+
+    let decrypted = Secret::new(
+        cipher
+            .decrypt(&chacha20poly1305::Nonce::default(), wrapped_key.as_slice())
+            .ok()?,
+    );
+
+    let mut master_key = [0u8; 32];
+    master_key.copy_from_slice(decrypted.expose_secret());
+    Some(Secret::new(master_key))
+}
@@ -13,14 +13,14 @@ pub fn get_matches() -> clap::ArgMatches {
                 .value_name("input")
                 .takes_value(true)
                 .required(true)
-                .help("the input file"),
+                .help("the input file (\"-\" for stdin)"),
         )
         .arg(
             Arg::new("output")
                 .value_name("output")
                 .takes_value(true)
                 .required(true)
-                .help("the output file"),
+                .help("the output file (\"-\" for stdout)"),
         )
         .arg(
             Arg::new("keyfile")
@@ -40,6 +40,20 @@ pub fn get_matches() -> clap::ArgMatches {
                 .min_values(0)
                 .default_missing_value("16"),
         )
+        .arg(
+            Arg::new("verify")
+                .long("verify")
+                .takes_value(false)
+                .help("read the output back and authenticate every block once it has been written (default with --erase)"),
+        )
+        .arg(
+            Arg::new("no-verify")
+                .long("no-verify")
+                .takes_value(false)
+                .help("erase the input without verifying the output first")
+                .requires("erase")
+                .conflicts_with("verify"),
+        )
         .arg(
             Arg::new("hash")
                 .short('H')
@@ -98,6 +112,63 @@ pub fn get_matches() -> clap::ArgMatches {
                 .takes_value(false)
                 .help("use xchacha20-poly1305 (default)")
                 .conflicts_with("gcm"),
+        )
+        .arg(
+            Arg::new("kdf-preset")
+                .long("kdf-preset")
+                .value_name("preset")
+                .takes_value(true)
+                .possible_values(["interactive", "moderate", "paranoid"])
+                .help("use a preset for the argon2id cost parameters (the defaults are used if this isn't specified)"),
+        )
+        .arg(
+            Arg::new("kdf-memory")
+                .long("kdf-memory")
+                .value_name("KiB")
+                .takes_value(true)
+                .help("set the argon2id memory cost in KiB, up to 4194304 (4GiB) (overrides the preset)"),
+        )
+        .arg(
+            Arg::new("kdf-iterations")
+                .long("kdf-iterations")
+                .value_name("iterations")
+                .takes_value(true)
+                .help("set the number of argon2id iterations, up to 64 (overrides the preset)"),
+        )
+        .arg(
+            Arg::new("kdf-parallelism")
+                .long("kdf-parallelism")
+                .value_name("lanes")
+                .takes_value(true)
+                .help("set the argon2id parallelism, up to 16 (overrides the preset)"),
+        )
+        .arg(
+            Arg::new("chunk-size")
+                .long("chunk-size")
+                .value_name("size")
+                .takes_value(true)
+                .help("the size of each block in stream mode, between 64K and 64M (default is 1M), this is stored in the header")
+                .conflicts_with("memory"),
+        )
+        .arg(
+            Arg::new("threads")
+                .short('t')
+                .long("threads")
+                .value_name("# of threads")
+                .takes_value(true)
+                .require_equals(true)
+                .help("encrypt several blocks at once in stream mode, on this many threads (default is 1, or all cpu cores if no value is given)")
+                .min_values(0)
+                .default_missing_value("0"),
+        )
+        .arg(
+            Arg::new("recipient")
+                .long("recipient")
+                .value_name("public key or file")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .help("encrypt to a public key (or a file of public keys) from `dexios keygen`, instead of using a password (may be used multiple times)")
+                .conflicts_with_all(&["keyfile", "password", "kdf-preset", "kdf-memory", "kdf-iterations", "kdf-parallelism"]),
         );
 
     let decrypt = Command::new("decrypt")
@@ -108,14 +179,14 @@ pub fn get_matches() -> clap::ArgMatches {
                 .value_name("input")
                 .takes_value(true)
                 .required(true)
-                .help("the input file"),
+                .help("the input file (\"-\" for stdin)"),
         )
         .arg(
             Arg::new("output")
                 .value_name("output")
                 .takes_value(true)
                 .required(true)
-                .help("the output file"),
+                .help("the output file (\"-\" for stdout)"),
         )
         .arg(
             Arg::new("keyfile")
@@ -161,7 +232,7 @@ pub fn get_matches() -> clap::ArgMatches {
                 .short('s')
                 .long("stream")
                 .takes_value(false)
-                .help("use stream decryption (default)")
+                .help("use stream decryption (legacy files only, as the mode is read from the header)")
                 .conflicts_with("memory"),
         )
         .arg(
@@ -169,7 +240,7 @@ pub fn get_matches() -> clap::ArgMatches {
                 .short('m')
                 .long("memory")
                 .takes_value(false)
-                .help("load the file into memory before decrypting"),
+                .help("load the file into memory before decrypting (legacy files only, as the mode is read from the header)"),
         )
         .arg(
             Arg::new("password")
@@ -184,24 +255,291 @@ pub fn get_matches() -> clap::ArgMatches {
                 .short('g')
                 .long("gcm")
                 .takes_value(false)
-                .help("use aes-256-gcm"),
+                .help("use aes-256-gcm (legacy files only, as the cipher is read from the header)"),
         )
         .arg(
             Arg::new("xchacha")
                 .short('x')
                 .long("xchacha")
                 .takes_value(false)
-                .help("use xchacha20-poly1305 (default)")
+                .help("use xchacha20-poly1305 (legacy files only, as the cipher is read from the header)")
                 .conflicts_with("gcm"),
+        )
+        .arg(
+            Arg::new("legacy")
+                .long("legacy")
+                .takes_value(false)
+                .help("decrypt a headerless file created by an older version of dexios (use -g for aes-256-gcm)"),
+        )
+        .arg(
+            Arg::new("unverified")
+                .long("unverified")
+                .takes_value(false)
+                .help("write decrypted data straight to the output file, without a temporary file (the output is left in place, unverified, if decryption fails)"),
+        )
+        .arg(
+            Arg::new("threads")
+                .short('t')
+                .long("threads")
+                .value_name("# of threads")
+                .takes_value(true)
+                .require_equals(true)
+                .help("decrypt several blocks at once in stream mode, on this many threads (default is 1, or all cpu cores if no value is given)")
+                .min_values(0)
+                .default_missing_value("0"),
+        )
+        .arg(
+            Arg::new("range")
+                .long("range")
+                .value_name("start-end")
+                .takes_value(true)
+                .help("only decrypt this range of bytes (e.g. 1048576-2097152, or 1048576- to read until the end), for files encrypted in stream mode")
+                .conflicts_with_all(&["hash", "erase", "memory", "threads"]),
+        )
+        .arg(
+            Arg::new("identity")
+                .long("identity")
+                .value_name("file")
+                .takes_value(true)
+                .help("decrypt with an identity from `dexios keygen`, for files that were encrypted to a recipient")
+                .conflicts_with_all(&["keyfile", "password"]),
+        );
+
+    let key = Command::new("key")
+        .about("manage the key slots of an encrypted file, without re-encrypting it")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("add")
+                .about("add a new password, keyfile or recipient to an encrypted file")
+                .arg(
+                    Arg::new("input")
+                        .value_name("input")
+                        .takes_value(true)
+                        .required(true)
+                        .help("the encrypted file"),
+                )
+                .arg(
+                    Arg::new("keyfile")
+                        .short('k')
+                        .long("keyfile")
+                        .value_name("file")
+                        .takes_value(true)
+                        .help("unlock the file with a keyfile instead of a password"),
+                )
+                .arg(
+                    Arg::new("password")
+                        .short('p')
+                        .long("password")
+                        .takes_value(false)
+                        .help("interactively ask for your password")
+                        .conflicts_with("keyfile"),
+                )
+                .arg(
+                    Arg::new("identity")
+                        .long("identity")
+                        .value_name("file")
+                        .takes_value(true)
+                        .help("unlock the file with an identity from `dexios keygen`")
+                        .conflicts_with_all(&["keyfile", "password"]),
+                )
+                .arg(
+                    Arg::new("new-keyfile")
+                        .short('n')
+                        .long("new-keyfile")
+                        .value_name("file")
+                        .takes_value(true)
+                        .help("add a keyfile instead of a password"),
+                )
+                .arg(
+                    Arg::new("recipient")
+                        .long("recipient")
+                        .value_name("public key or file")
+                        .takes_value(true)
+                        .multiple_occurrences(true)
+                        .help("add a public key (or a file of public keys) from `dexios keygen`, instead of a password (may be used multiple times)")
+                        .conflicts_with_all(&["new-keyfile", "kdf-preset", "kdf-memory", "kdf-iterations", "kdf-parallelism"]),
+                )
+                .arg(
+                    Arg::new("kdf-preset")
+                        .long("kdf-preset")
+                        .value_name("preset")
+                        .takes_value(true)
+                        .possible_values(["interactive", "moderate", "paranoid"])
+                        .help("use a preset for the new key's argon2id cost parameters (the defaults are used if this isn't specified)"),
+                )
+                .arg(
+                    Arg::new("kdf-memory")
+                        .long("kdf-memory")
+                        .value_name("KiB")
+                        .takes_value(true)
+                        .help("set the argon2id memory cost in KiB, up to 4194304 (4GiB) (overrides the preset)"),
+                )
+                .arg(
+                    Arg::new("kdf-iterations")
+                        .long("kdf-iterations")
+                        .value_name("iterations")
+                        .takes_value(true)
+                        .help("set the number of argon2id iterations, up to 64 (overrides the preset)"),
+                )
+                .arg(
+                    Arg::new("kdf-parallelism")
+                        .long("kdf-parallelism")
+                        .value_name("lanes")
+                        .takes_value(true)
+                        .help("set the argon2id parallelism, up to 16 (overrides the preset)"),
+                ),
+        )
+        .subcommand(
+            Command::new("change")
+                .about("change a password or keyfile of an encrypted file")
+                .arg(
+                    Arg::new("input")
+                        .value_name("input")
+                        .takes_value(true)
+                        .required(true)
+                        .help("the encrypted file"),
+                )
+                .arg(
+                    Arg::new("keyfile")
+                        .short('k')
+                        .long("keyfile")
+                        .value_name("file")
+                        .takes_value(true)
+                        .help("the old keyfile, if the file was encrypted with one"),
+                )
+                .arg(
+                    Arg::new("password")
+                        .short('p')
+                        .long("password")
+                        .takes_value(false)
+                        .help("interactively ask for your old password")
+                        .conflicts_with("keyfile"),
+                )
+                .arg(
+                    Arg::new("new-keyfile")
+                        .short('n')
+                        .long("new-keyfile")
+                        .value_name("file")
+                        .takes_value(true)
+                        .help("change to a keyfile instead of a password"),
+                )
+                .arg(
+                    Arg::new("kdf-preset")
+                        .long("kdf-preset")
+                        .value_name("preset")
+                        .takes_value(true)
+                        .possible_values(["interactive", "moderate", "paranoid"])
+                        .help("use a preset for the new key's argon2id cost parameters (the defaults are used if this isn't specified)"),
+                )
+                .arg(
+                    Arg::new("kdf-memory")
+                        .long("kdf-memory")
+                        .value_name("KiB")
+                        .takes_value(true)
+                        .help("set the argon2id memory cost in KiB, up to 4194304 (4GiB) (overrides the preset)"),
+                )
+                .arg(
+                    Arg::new("kdf-iterations")
+                        .long("kdf-iterations")
+                        .value_name("iterations")
+                        .takes_value(true)
+                        .help("set the number of argon2id iterations, up to 64 (overrides the preset)"),
+                )
+                .arg(
+                    Arg::new("kdf-parallelism")
+                        .long("kdf-parallelism")
+                        .value_name("lanes")
+                        .takes_value(true)
+                        .help("set the argon2id parallelism, up to 16 (overrides the preset)"),
+                ),
+        )
+        .subcommand(
+            Command::new("del")
+                .about("remove a key slot from an encrypted file")
+                .arg(
+                    Arg::new("input")
+                        .value_name("input")
+                        .takes_value(true)
+                        .required(true)
+                        .help("the encrypted file"),
+                )
+                .arg(
+                    Arg::new("slot")
+                        .long("slot")
+                        .value_name("index")
+                        .takes_value(true)
+                        .required(true)
+                        .help("the key slot to remove (as shown by `dexios key list`)"),
+                )
+                .arg(
+                    Arg::new("keyfile")
+                        .short('k')
+                        .long("keyfile")
+                        .value_name("file")
+                        .takes_value(true)
+                        .help("unlock the file with a keyfile instead of a password"),
+                )
+                .arg(
+                    Arg::new("password")
+                        .short('p')
+                        .long("password")
+                        .takes_value(false)
+                        .help("interactively ask for your password")
+                        .conflicts_with("keyfile"),
+                )
+                .arg(
+                    Arg::new("identity")
+                        .long("identity")
+                        .value_name("file")
+                        .takes_value(true)
+                        .help("unlock the file with an identity from `dexios keygen`")
+                        .conflicts_with_all(&["keyfile", "password"]),
+                ),
+        )
+        .subcommand(
+            Command::new("list")
+                .about("list the key slots of an encrypted file")
+                .arg(
+                    Arg::new("input")
+                        .value_name("input")
+                        .takes_value(true)
+                        .required(true)
+                        .help("the encrypted file"),
+                ),
         );
 
+    let batch = Command::new("batch")
+        .about("encrypt/decrypt many files at once")
+        .subcommand_required(true)
+        .subcommand(batch_command(
+            &encrypt,
+            "encrypt many files, naming each output by adding a suffix to its input",
+        ))
+        .subcommand(batch_command(
+            &decrypt,
+            "decrypt many files, naming each output by removing the suffix from its input",
+        ));
+
     Command::new("dexios")
         .version(clap::crate_version!())
         .author("brxken128 <brxken128@tutanota.com>")
         .about("Secure command-line encryption of files.")
         .subcommand_required(true)
-        .subcommand(encrypt.clone())
-        .subcommand(decrypt.clone())
+        .arg(
+            Arg::new("json")
+                .long("json")
+                .global(true)
+                .takes_value(false)
+                .help("print a json report of the operation's result to stdout (all other messages are written to stderr, and prompts fail instead of waiting for input)"),
+        )
+        .subcommand(in_place_command(
+            encrypt.clone(),
+            "replace the input file with its encrypted contents (the original is kept until the encrypted file has been safely written)",
+        ))
+        .subcommand(in_place_command(
+            decrypt.clone(),
+            "replace the input file with its decrypted contents (the original is kept until the decrypted file has been safely written)",
+        ))
         .subcommand(
             Command::new("erase")
                 .about("erase a file completely")
@@ -218,9 +556,134 @@ pub fn get_matches() -> clap::ArgMatches {
                         .value_name("# of passes")
                         .takes_value(true)
                         .require_equals(true)
-                        .help("specify the number of passes (default is 16)")
+                        .help("specify the number of passes (default is 16, only used by the random and zeros patterns)")
                         .min_values(0)
                         .default_missing_value("16"),
+                )
+                .arg(
+                    Arg::new("pattern")
+                        .long("pattern")
+                        .value_name("pattern")
+                        .takes_value(true)
+                        .possible_values(["random", "zeros", "dod", "gutmann"])
+                        .default_value("random")
+                        .help("what to overwrite the file with (dod is DoD 5220.22-M's 3 passes, and gutmann is Gutmann's 35 passes)"),
+                )
+                .arg(
+                    Arg::new("verify")
+                        .long("verify")
+                        .takes_value(false)
+                        .help("read each pass back after it has been written, to check that it was written correctly"),
+                )
+                .arg(
+                    Arg::new("recursive")
+                        .short('r')
+                        .long("recursive")
+                        .takes_value(false)
+                        .help("erase a directory, along with every file and directory inside of it (symlinks are removed, but never followed)"),
+                )
+                .arg(
+                    Arg::new("exclude")
+                        .long("exclude")
+                        .value_name("pattern to exclude")
+                        .takes_value(true)
+                        .require_equals(true)
+                        .help("exclude a pattern (e.g. --exclude=\".*\") (recursive mode only)")
+                        .min_values(0)
+                        .multiple_occurrences(true)
+                        .requires("recursive"),
+                ),
+        )
+        .subcommand(
+            Command::new("keygen")
+                .about("generate an identity (keypair) for encrypting files to a recipient")
+                .arg(
+                    Arg::new("output")
+                        .value_name("output")
+                        .takes_value(true)
+                        .required(true)
+                        .help("the file to save the identity (secret key) to"),
+                )
+                .arg(
+                    Arg::new("skip")
+                        .short('y')
+                        .long("skip")
+                        .takes_value(false)
+                        .help("skip all prompts"),
+                )
+                .arg(
+                    Arg::new("pq")
+                        .long("pq")
+                        .takes_value(false)
+                        .help("generate a post-quantum hybrid (X25519 + ML-KEM-768) identity, for files that need to stay secret for a long time"),
+                ),
+        )
+        .subcommand(key)
+        .subcommand(batch)
+        .subcommand(
+            Command::new("info")
+                .about("show an encrypted file's metadata (no key is required)")
+                .arg(
+                    Arg::new("input")
+                        .value_name("input")
+                        .takes_value(true)
+                        .required(true)
+                        .help("the encrypted file"),
+                )
+        )
+        .subcommand(
+            Command::new("verify")
+                .about("check that an encrypted file is intact, without writing the decrypted data anywhere")
+                .arg(
+                    Arg::new("input")
+                        .value_name("input")
+                        .takes_value(true)
+                        .required(true)
+                        .help("the encrypted file (\"-\" for stdin)"),
+                )
+                .arg(
+                    Arg::new("keyfile")
+                        .short('k')
+                        .long("keyfile")
+                        .value_name("file")
+                        .takes_value(true)
+                        .help("use a keyfile instead of a password"),
+                )
+                .arg(
+                    Arg::new("password")
+                        .short('p')
+                        .long("password")
+                        .takes_value(false)
+                        .help("interactively ask for your password")
+                        .conflicts_with("keyfile"),
+                )
+                .arg(
+                    Arg::new("identity")
+                        .long("identity")
+                        .value_name("file")
+                        .takes_value(true)
+                        .help("use an identity from `dexios keygen`, for files that were encrypted to a recipient")
+                        .conflicts_with_all(&["keyfile", "password"]),
+                )
+                .arg(
+                    Arg::new("legacy")
+                        .long("legacy")
+                        .takes_value(false)
+                        .help("verify a headerless file created by an older version of dexios (use -g for aes-256-gcm)"),
+                )
+                .arg(
+                    Arg::new("gcm")
+                        .short('g')
+                        .long("gcm")
+                        .takes_value(false)
+                        .help("use aes-256-gcm (legacy files only, as the cipher is read from the header)"),
+                )
+                .arg(
+                    Arg::new("memory")
+                        .short('m')
+                        .long("memory")
+                        .takes_value(false)
+                        .help("the file was encrypted in memory mode (legacy files only, as the mode is read from the header)"),
                 ),
         )
         .subcommand(
@@ -279,8 +742,99 @@ pub fn get_matches() -> clap::ArgMatches {
                         .min_values(0)
                         .default_missing_value("6"),
                 )
-                .subcommand(encrypt.clone())
-                .subcommand(decrypt.clone()),
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_name("format")
+                        .takes_value(true)
+                        .possible_values(["zip", "tar"])
+                        .default_value("zip")
+                        .help("the archive format - tar keeps permissions, timestamps, ownership, symlinks, hard links and xattrs (encrypt mode only, the format is detected when decrypting)"),
+                )
+                .arg(
+                    Arg::new("preserve-owner")
+                        .long("preserve-owner")
+                        .takes_value(false)
+                        .help("restore the owner and group of each file from a tar archive, this usually needs root (decrypt mode only)"),
+                )
+                .subcommand(pack_command(&encrypt))
+                .subcommand(pack_command(&decrypt)),
         )
         .get_matches()
 }
+
+// this creates a pack subcommand from the encrypt/decrypt subcommand
+// the options that only apply to a single file (erasing, verifying, threads and ranges) aren't supported when packing, so they're left out
+fn pack_command<'a>(command: &Command<'a>) -> Command<'a> {
+    let pack = Command::new(command.get_name())
+        .about(command.get_about().unwrap_or_default())
+        .args(command.get_arguments().filter(|arg| {
+            ![
+                "help",
+                "erase",
+                "verify",
+                "no-verify",
+                "unverified",
+                "threads",
+                "range",
+            ]
+            .contains(&arg.get_id())
+        }));
+
+    match command.get_short_flag() {
+        Some(flag) => pack.short_flag(flag),
+        None => pack,
+    }
+}
+
+// this creates a batch subcommand from the encrypt/decrypt subcommand, so that it takes all of the same options
+// the single input and output are replaced with many inputs, and a rule for naming each output
+fn batch_command<'a>(command: &Command<'a>, about: &'a str) -> Command<'a> {
+    Command::new(command.get_name())
+        .about(about)
+        .args(
+            command
+                .get_arguments()
+                .filter(|arg| !["help", "input", "output", "range"].contains(&arg.get_id())),
+        )
+        .arg(
+            Arg::new("input")
+                .value_name("input")
+                .takes_value(true)
+                .multiple_values(true)
+                .required(true)
+                .help("the input files (glob patterns such as \"*.txt\" are also expanded)"),
+        )
+        .arg(
+            Arg::new("suffix")
+                .long("suffix")
+                .value_name("suffix")
+                .takes_value(true)
+                .default_value(".enc")
+                .help("the suffix that's added to (or removed from) each file's name"),
+        )
+        .arg(
+            Arg::new("output-dir")
+                .short('o')
+                .long("output-dir")
+                .value_name("directory")
+                .takes_value(true)
+                .help("write the outputs to this directory, instead of next to each input"),
+        )
+}
+
+// this adds --in-place to the encrypt/decrypt subcommand, which makes the output optional
+// it's not added to the pack/batch subcommands, as they have their own outputs
+fn in_place_command<'a>(command: Command<'a>, help: &'a str) -> Command<'a> {
+    command
+        .mut_arg("output", |arg| {
+            arg.required(false).required_unless_present("in-place")
+        })
+        .arg(
+            Arg::new("in-place")
+                .long("in-place")
+                .takes_value(false)
+                .help(help)
+                .conflicts_with_all(&["output", "bench"]),
+        )
+}
@@ -1,49 +1,153 @@
-use crate::decrypt::crypto::decrypt_bytes_memory_mode;
-use crate::decrypt::crypto::decrypt_bytes_stream_mode;
-use crate::file::get_encrypted_data;
+use crate::failure::Failure;
+use crate::file::create_output;
+use crate::file::create_temp_output;
+use crate::file::open_input;
+use crate::file::persist_temp_output;
 use crate::file::write_bytes;
+use crate::file::STDIO;
 use crate::global::BenchMode;
 use crate::global::EraseMode;
 use crate::global::HashMode;
 use crate::global::OutputFile;
 use crate::global::Parameters;
 use crate::global::SkipMode;
-use crate::global::BLOCK_SIZE;
-use crate::global::SALT_LEN;
-use crate::hashing::hash_data_blake3;
-use crate::key::get_user_key;
+use crate::global::ThreadMode;
+use crate::global::VerifyMode;
+use crate::hashing::HashReader;
+use crate::hashing::HashWriter;
+use crate::key::get_decryption_key;
+use crate::progress_bar;
 use crate::prompt::get_answer;
 use crate::prompt::overwrite_check;
+use crate::prompt::stdin_check;
+use crate::report;
 use anyhow::{Context, Ok, Result};
+use dexios::{CipherMode, Header, HeaderMode, ProgressReader, BLOCK_SIZE, SALT_LEN};
 use std::fs::File;
+use std::io::Read;
+use std::ops::Range;
 
-use std::process::exit;
 use std::time::Instant;
-mod crypto;
 
-// this function is for decrypting a file in memory mode
-// it's responsible for  handling user-facing interactiveness, and calling the correct functions where appropriate
-pub fn memory_mode(input: &str, output: &str, keyfile: &str, params: &Parameters) -> Result<()> {
+// this function opens the input (a file, or stdin), reads the header and decrypts it in the correct mode
+// files with a header record the mode they were encrypted in, so we just read it from there
+// legacy files don't contain this information, so we rely on the user's choice and the size of the file instead
+// status messages are written to stderr, so stdout can be used for the decrypted data
+pub fn decrypt(
+    input: &str,
+    output: &str,
+    keyfile: &str,
+    memory: bool,
+    params: &Parameters,
+) -> Result<()> {
+    report::record("input", input);
+    report::record("output", output);
+
+    stdin_check(input, output, keyfile, params)?;
     if !overwrite_check(output, params.skip, params.bench)? {
-        exit(0);
+        return Err(Failure::Aborted.into());
+    }
+
+    if input == output && input != STDIO {
+        return Err(
+            anyhow::anyhow!("Input and output files cannot have the same name.")
+                .context(Failure::InvalidArguments),
+        );
+    }
+
+    if let Some(range) = &params.range {
+        return range_mode(input, output, keyfile, range.clone(), memory, params);
+    }
+
+    // the header is read through the hasher too, so the hash covers the entire file
+    let mut reader = HashReader::new(open_input(input)?, params.hash_mode);
+    let header = read_header(input, &mut reader, memory, params)?;
+    record_header(&header);
+
+    match header.cipher_mode {
+        CipherMode::MemoryMode => memory_mode(input, output, reader, &header, keyfile, params),
+        CipherMode::StreamMode => stream_mode(input, output, reader, &header, keyfile, params),
+    }
+}
+
+// this reads the header from the start of the input
+// legacy files don't have a header, so just the salt and nonce are read
+pub fn read_header(
+    input: &str,
+    reader: &mut impl Read,
+    memory: bool,
+    params: &Parameters,
+) -> Result<Header> {
+    match params.header_mode {
+        HeaderMode::ReadHeader => Header::deserialize(reader)
+            .map_err(|err| match err {
+                dexios::Error::NoHeader => anyhow::Error::new(err).context(format!("No dexios header was found in {}. If this file was encrypted with an older version of dexios, try again with --legacy (and -g if it was encrypted with AES-256-GCM)", input)),
+                err => anyhow::Error::new(err)
+                    .context(format!("Unable to read the header from file: {}", input)),
+            }),
+        HeaderMode::LegacyHeaderless => {
+            let cipher_mode = get_legacy_mode(input, memory)?;
+            Header::deserialize_legacy(reader, params.cipher_type, cipher_mode)
+                .with_context(|| format!("Unable to read the salt and nonce from file: {}", input))
+        }
+    }
+}
+
+// this decides which mode a legacy file should be decrypted in
+// if the input isn't a regular file (e.g. stdin or a pipe) we can't know the size, so stream mode is used
+fn get_legacy_mode(input: &str, memory: bool) -> Result<CipherMode> {
+    if memory {
+        return Ok(CipherMode::MemoryMode);
+    }
+
+    if input == STDIO {
+        return Ok(CipherMode::StreamMode);
+    }
+
+    let metadata = std::fs::metadata(input)
+        .with_context(|| format!("Unable to get input file metadata: {}", input))?;
+
+    // +16 for AEAD tag, +SALT_LEN to account for salt, +4 for the extra 4 bytes of nonce stored with each block
+    // +8 to account for nonce itself (assuming the smallest nonce, which is aes-256-gcm's)
+    if metadata.is_file()
+        && metadata.len()
+            <= (BLOCK_SIZE + 24 + SALT_LEN)
+                .try_into()
+                .context("Unable to parse stream block size as u64")?
+    {
+        eprintln!(
+            "Encrypted data size is less than the stream block size - redirecting to memory mode"
+        );
+        Ok(CipherMode::MemoryMode)
+    } else {
+        Ok(CipherMode::StreamMode)
     }
+}
 
+// this function is for decrypting a file in memory mode
+// it's responsible for  handling user-facing interactiveness, and calling the correct functions where appropriate
+fn memory_mode(
+    input: &str,
+    output: &str,
+    mut reader: HashReader<Box<dyn Read>>,
+    header: &Header,
+    keyfile: &str,
+    params: &Parameters,
+) -> Result<()> {
     let read_start_time = Instant::now();
-    let (salt, nonce, encrypted_data) = get_encrypted_data(input, params.cipher_type)?;
+    let mut encrypted_data = Vec::new();
+    reader
+        .read_to_end(&mut encrypted_data)
+        .with_context(|| format!("Unable to read data from file: {}", input))?;
     let read_duration = read_start_time.elapsed();
-    println!("Read {} [took {:.2}s]", input, read_duration.as_secs_f32());
-
-    if params.hash_mode == HashMode::CalculateHash {
-        let start_time = Instant::now();
-        let hash = hash_data_blake3(&salt, &nonce, &encrypted_data)?;
-        let duration = start_time.elapsed();
-        println!(
-            "Hash of the encrypted file is: {} [took {:.2}s]",
-            hash,
-            duration.as_secs_f32()
-        );
+    eprintln!("Read {} [took {:.2}s]", input, read_duration.as_secs_f32());
 
-        let skip_if_hidden = params.skip == SkipMode::HidePrompts;
+    if let Some(hash) = reader.finalize() {
+        eprintln!("Hash of the encrypted file is: {}", hash);
+        report::record("hash", hash.as_str());
+
+        // the answer can't be read from stdin if that's where the encrypted data came from
+        let skip_if_hidden = params.skip == SkipMode::HidePrompts || input == STDIO;
 
         let answer = get_answer(
             "Would you like to continue with the decryption?",
@@ -51,21 +155,21 @@ pub fn memory_mode(input: &str, output: &str, keyfile: &str, params: &Parameters
             skip_if_hidden,
         )?;
         if !answer {
-            exit(0);
+            return Err(Failure::Aborted.into());
         }
     }
 
-    let raw_key = get_user_key(keyfile, false, params.password)?;
+    let key = get_decryption_key(keyfile, header, params)?;
 
-    println!(
-        "Decrypting {} in memory mode (this may take a while)",
-        input
+    eprintln!(
+        "Decrypting {} in memory mode with {} ({} header, {})",
+        input, header.cipher_type, header.version, header.key_material
     );
     let decrypt_start_time = Instant::now();
-    let decrypted_bytes =
-        decrypt_bytes_memory_mode(salt, &nonce, &encrypted_data, raw_key, params.cipher_type)?;
+    let decrypted_bytes = dexios::memory::decrypt_bytes(header, &encrypted_data, key)?;
     let decrypt_duration = decrypt_start_time.elapsed();
-    println!(
+    report::record("bytes", decrypted_bytes.len());
+    eprintln!(
         "Decryption successful! [took {:.2}s]",
         decrypt_duration.as_secs_f32()
     );
@@ -74,96 +178,230 @@ pub fn memory_mode(input: &str, output: &str, keyfile: &str, params: &Parameters
         let write_start_time = Instant::now();
         write_bytes(output, &decrypted_bytes)?;
         let write_duration = write_start_time.elapsed();
-        println!(
+        eprintln!(
             "Wrote to {} [took {:.2}s]",
             output,
             write_duration.as_secs_f32()
         );
     }
 
-    if params.erase != EraseMode::IgnoreFile(0) {
-        crate::erase::secure_erase(input, params.erase.get_passes())?;
-    }
-
-    Ok(())
+    erase_input(input, params)
 }
 
 // this function is for decrypting a file in stream mode
-// it handles any user-facing interactiveness, opening files, or redirecting to memory mode if the input file isn't large enough
-pub fn stream_mode(input: &str, output: &str, keyfile: &str, params: &Parameters) -> Result<()> {
-    let mut input_file =
-        File::open(input).with_context(|| format!("Unable to open input file: {}", input))?;
-    let file_size = input_file
-        .metadata()
-        .with_context(|| format!("Unable to get input file metadata: {}", input))?
-        .len();
+// it handles any user-facing interactiveness, and creating the output file
+// the decrypted data is written to a temporary file, which is only renamed to the output once the final block has been verified
+// if decryption fails, the temporary file is erased so no unauthenticated plaintext is left behind
+fn stream_mode(
+    input: &str,
+    output: &str,
+    mut reader: HashReader<Box<dyn Read>>,
+    header: &Header,
+    keyfile: &str,
+    params: &Parameters,
+) -> Result<()> {
+    // the key is read first, so a temporary file isn't left behind if that fails
+    let key = get_decryption_key(keyfile, header, params)?;
 
-    // +16 for AEAD tag, +SALT_LEN to account for salt, +4 for the extra 4 bytes of nonce stored with each block
-    // +8 to account for nonce itself (assuming the smallest nonce, which is aes-256-gcm's)
-    if file_size
-        <= (BLOCK_SIZE + 24 + SALT_LEN)
-            .try_into()
-            .context("Unable to parse stream block size as u64")?
-    {
-        println!(
-            "Encrypted data size is less than the stream block size - redirecting to memory mode"
-        );
-        return memory_mode(input, output, keyfile, params);
+    let (mut output_file, temp_name) = if params.bench == BenchMode::BenchmarkInMemory {
+        (OutputFile::None, None)
+    } else if output == STDIO || params.verify == VerifyMode::WriteUnverified {
+        eprintln!("Warning: decrypted data will be written to {} before it has been verified. If decryption fails, the output should not be trusted.", output);
+        (create_output(output)?, None)
+    } else {
+        let (temp_name, file) = create_temp_output(output)?;
+        (OutputFile::Some(file), Some(temp_name))
+    };
+
+    eprintln!(
+        "Decrypting {} in stream mode with {} ({} header, {}) (this may take a while)",
+        input, header.cipher_type, header.version, header.key_material
+    );
+    let decrypt_start_time = Instant::now();
+
+    // the header has already been read, so the progress bar only covers the encrypted data
+    let bar = progress_bar::for_input(input, header.serialize().len() as u64);
+    let mut progress_reader = ProgressReader::new(&mut reader, |bytes| bar.set_position(bytes));
+
+    // the output is only wrapped so that the bytes can be counted
+    let mut writer = HashWriter::new(&mut output_file, HashMode::NoHash);
+    let result = match params.threads {
+        ThreadMode::SingleThreaded => {
+            dexios::stream::decrypt(&mut progress_reader, &mut writer, header, key)
+        }
+        ThreadMode::MultiThreaded(threads) => dexios::stream::decrypt_parallel(
+            &mut progress_reader,
+            &mut writer,
+            header,
+            key,
+            threads,
+        ),
+    }
+    .context("Unable to decrypt the file");
+    bar.finish_and_clear();
+    let decrypt_duration = decrypt_start_time.elapsed();
+    let bytes = writer.bytes();
+
+    finish_temp_output(temp_name, output_file, output, result.is_ok())?;
+    result?;
+    report::record("bytes", bytes);
+
+    if let Some(hash) = reader.finalize() {
+        eprintln!("Hash of the encrypted file is: {}. If this doesn't match with the original, something very bad has happened.", hash);
+        report::record("hash", hash);
     }
 
-    if !overwrite_check(output, params.skip, params.bench)? {
-        exit(0);
+    match params.bench {
+        BenchMode::WriteToFilesystem => {
+            eprintln!(
+                "Decryption successful! File saved as {} [took {:.2}s]",
+                output,
+                decrypt_duration.as_secs_f32(),
+            );
+        }
+        BenchMode::BenchmarkInMemory => {
+            eprintln!(
+                "Decryption successful! [took {:.2}s]",
+                decrypt_duration.as_secs_f32(),
+            );
+        }
     }
 
-    if input == output {
+    erase_input(input, params)
+}
+
+// this function is for decrypting part of a file that was encrypted in stream mode
+// it seeks straight to the blocks that cover the range, so the input must be a regular file
+// the range is written to a temporary file, which is only renamed to the output once every block that covers it has been authenticated
+fn range_mode(
+    input: &str,
+    output: &str,
+    keyfile: &str,
+    range: Range<u64>,
+    memory: bool,
+    params: &Parameters,
+) -> Result<()> {
+    if input == STDIO {
         return Err(anyhow::anyhow!(
-            "Input and output files cannot have the same name in stream mode."
-        ));
+            "A range can't be decrypted from stdin, as the input needs to be seekable"
+        )
+        .context(Failure::InvalidArguments));
     }
 
-    let mut output_file = if params.bench == BenchMode::WriteToFilesystem {
-        OutputFile::Some(
-            File::create(output)
-                .with_context(|| format!("Unable to open output file: {}", output))?,
+    let mut input_file =
+        File::open(input).with_context(|| format!("Unable to open input file: {}", input))?;
+    let header = read_header(input, &mut input_file, memory, params)?;
+    record_header(&header);
+
+    if header.cipher_mode == CipherMode::MemoryMode {
+        return Err(anyhow::anyhow!(
+            "{} was encrypted in memory mode, so a range can't be decrypted from it",
+            input
         )
+        .context(Failure::InvalidArguments));
+    }
+
+    let key = get_decryption_key(keyfile, &header, params)?;
+
+    let (mut output_file, temp_name) = if params.bench == BenchMode::BenchmarkInMemory {
+        (OutputFile::None, None)
+    } else if output == STDIO {
+        (create_output(output)?, None)
     } else {
-        OutputFile::None
+        let (temp_name, file) = create_temp_output(output)?;
+        (OutputFile::Some(file), Some(temp_name))
     };
 
-    let raw_key = get_user_key(keyfile, false, params.password)?;
-
-    println!(
-        "Decrypting {} in stream mode with {} (this may take a while)",
-        input, params.cipher_type,
+    eprintln!(
+        "Decrypting bytes {}-{} of {} in stream mode with {} ({} header, {})",
+        range.start,
+        if range.end == u64::MAX {
+            String::from("end")
+        } else {
+            range.end.to_string()
+        },
+        input,
+        header.cipher_type,
+        header.version,
+        header.key_material
     );
     let decrypt_start_time = Instant::now();
-    decrypt_bytes_stream_mode(
-        &mut input_file,
-        &mut output_file,
-        raw_key,
-        params.bench,
-        params.hash_mode,
-        params.cipher_type,
-    )?;
+    let mut writer = HashWriter::new(&mut output_file, HashMode::NoHash);
+    let result = dexios::stream::decrypt_range(&mut input_file, &mut writer, &header, key, range)
+        .context("Unable to decrypt the range");
     let decrypt_duration = decrypt_start_time.elapsed();
+    let bytes = writer.bytes();
+
+    finish_temp_output(temp_name, output_file, output, result.is_ok())?;
+    result?;
+    report::record("bytes", bytes);
+
     match params.bench {
         BenchMode::WriteToFilesystem => {
-            println!(
+            eprintln!(
                 "Decryption successful! File saved as {} [took {:.2}s]",
                 output,
                 decrypt_duration.as_secs_f32(),
             );
         }
         BenchMode::BenchmarkInMemory => {
-            println!(
+            eprintln!(
                 "Decryption successful! [took {:.2}s]",
                 decrypt_duration.as_secs_f32(),
             );
         }
     }
 
+    Ok(())
+}
+
+// the temporary file is only renamed to the output if every block was successfully authenticated, otherwise it's erased
+fn finish_temp_output(
+    temp_name: Option<String>,
+    output_file: OutputFile,
+    output: &str,
+    succeeded: bool,
+) -> Result<()> {
+    if let (Some(temp_name), OutputFile::Some(file)) = (temp_name, output_file) {
+        if succeeded {
+            persist_temp_output(&temp_name, file, output)?;
+        } else {
+            drop(file);
+            // the decryption error is the one that matters, so a failure to erase the temporary file is only logged
+            if let Err(err) = crate::erase::secure_erase(&temp_name, 1) {
+                eprintln!(
+                    "Warning: unable to erase the temporary file {}: {:#}",
+                    temp_name, err
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// this records the details of the header for the json report
+fn record_header(header: &Header) {
+    report::record("cipher", header.cipher_type.to_string());
+    report::record(
+        "mode",
+        match header.cipher_mode {
+            CipherMode::MemoryMode => "memory",
+            CipherMode::StreamMode => "stream",
+        },
+    );
+    report::record("header_version", header.version.to_string());
+}
+
+// this erases the input file, if the user requested it
+// stdin can't be erased, so it's skipped
+fn erase_input(input: &str, params: &Parameters) -> Result<()> {
     if params.erase != EraseMode::IgnoreFile(0) {
-        crate::erase::secure_erase(input, params.erase.get_passes())?;
+        if input == STDIO {
+            eprintln!("Skipping erasure as the input was read from stdin");
+        } else {
+            crate::erase::secure_erase(input, params.erase.get_passes())?;
+        }
     }
 
     Ok(())
@@ -1,56 +1,71 @@
-use crate::encrypt::crypto::encrypt_bytes_memory_mode;
-use crate::encrypt::crypto::encrypt_bytes_stream_mode;
+use crate::failure::Failure;
+use crate::file::create_output;
 use crate::file::get_bytes;
+use crate::file::open_input;
 use crate::file::write_encrypted_data;
+use crate::file::STDIO;
 use crate::global::BenchMode;
 use crate::global::EraseMode;
 use crate::global::HashMode;
 use crate::global::OutputFile;
+use crate::global::OutputVerifyMode;
 use crate::global::Parameters;
-use crate::global::BLOCK_SIZE;
+use crate::global::ThreadMode;
 use crate::hashing::hash_data_blake3;
-use crate::key::get_user_key;
+use crate::hashing::HashWriter;
+use crate::key::get_encryption_key;
+use crate::progress_bar;
 use crate::prompt::overwrite_check;
+use crate::prompt::stdin_check;
+use crate::report;
 use anyhow::Context;
 use anyhow::{Ok, Result};
+use dexios::keys::unlock_keyslot;
+use dexios::{CipherMode, DecryptionKey, EncryptionKey, Header, ProgressReader};
+use secrecy::{ExposeSecret, Secret};
 use std::fs::File;
-use std::process::exit;
 use std::time::Instant;
 
-mod crypto;
-
 // this function is for encrypting a file in memory mode
 // it's responsible for  handling user-facing interactiveness, and calling the correct functions where appropriate
+// status messages are written to stderr, so stdout can be used for the encrypted data
 pub fn memory_mode(input: &str, output: &str, keyfile: &str, params: &Parameters) -> Result<()> {
+    record_params(input, output, "memory", params);
+
+    stdin_check(input, output, keyfile, params)?;
     if !overwrite_check(output, params.skip, params.bench)? {
-        exit(0);
+        return Err(Failure::Aborted.into());
     }
 
-    let raw_key = get_user_key(keyfile, true, params.password)?;
+    check_verifiable(output, params)?;
+    let key = get_encryption_key(keyfile, params)?;
+    let verify_key = verification_key(&key, params);
 
     let read_start_time = Instant::now();
     let file_contents = get_bytes(input)?;
+    report::record("bytes", file_contents.expose_secret().len());
     let read_duration = read_start_time.elapsed();
-    println!("Read {} [took {:.2}s]", input, read_duration.as_secs_f32());
+    eprintln!("Read {} [took {:.2}s]", input, read_duration.as_secs_f32());
 
-    println!(
-        "Encrypting {} in memory mode (this may take a while)",
-        input
+    eprintln!(
+        "Encrypting {} in memory mode with {} (this may take a while)",
+        input, params.cipher_type
     );
     let encrypt_start_time = Instant::now();
-    let (salt, nonce, data) =
-        encrypt_bytes_memory_mode(file_contents, raw_key, params.cipher_type)?;
+    let (header, data, master_key) =
+        dexios::memory::encrypt_bytes(file_contents.expose_secret(), key, params.cipher_type)?;
+    drop(file_contents);
     let encrypt_duration = encrypt_start_time.elapsed();
-    println!(
+    eprintln!(
         "Encryption successful! [took {:.2}s]",
         encrypt_duration.as_secs_f32()
     );
 
     if params.bench == BenchMode::WriteToFilesystem {
         let write_start_time = Instant::now();
-        write_encrypted_data(output, &salt, &nonce, &data)?;
+        write_encrypted_data(output, &header, &data)?;
         let write_duration = write_start_time.elapsed();
-        println!(
+        eprintln!(
             "Wrote to {} [took {:.2}s]",
             output,
             write_duration.as_secs_f32()
@@ -59,95 +74,270 @@ pub fn memory_mode(input: &str, output: &str, keyfile: &str, params: &Parameters
 
     if params.hash_mode == HashMode::CalculateHash {
         let hash_start_time = Instant::now();
-        let hash = hash_data_blake3(&salt, &nonce, &data)?;
+        let hash = hash_data_blake3(&header.serialize(), &data)?;
         let hash_duration = hash_start_time.elapsed();
-        println!(
+        report::record("hash", hash.as_str());
+        eprintln!(
             "Hash of the encrypted file is: {} [took {:.2}s]",
             hash,
             hash_duration.as_secs_f32()
         );
     }
 
-    if params.erase != EraseMode::IgnoreFile(0) {
-        crate::erase::secure_erase(input, params.erase.get_passes())?;
-    }
-
-    Ok(())
+    verify_output(input, output, &header, master_key, verify_key, params)?;
+    erase_input(input, params)
 }
 
 // this function is for encrypting a file in stream mode
 // it handles any user-facing interactiveness, opening files, or redirecting to memory mode if the input file isn't large enough
+// the input and output may be stdin/stdout, in which case we can't know the size and stream mode is always used
 pub fn stream_mode(input: &str, output: &str, keyfile: &str, params: &Parameters) -> Result<()> {
-    let mut input_file =
-        File::open(input).with_context(|| format!("Unable to open input file: {}", input))?;
-    let file_size = input_file
-        .metadata()
-        .with_context(|| format!("Unable to get input file metadata: {}", input))?
-        .len();
-
-    if file_size
-        <= BLOCK_SIZE
-            .try_into()
-            .context("Unable to parse stream block size as u64")?
-    {
-        println!("Input file size is less than the stream block size - redirecting to memory mode");
-        return memory_mode(input, output, keyfile, params);
+    if input != STDIO {
+        let metadata = std::fs::metadata(input)
+            .with_context(|| format!("Unable to get input file metadata: {}", input))?;
+
+        // pipes and other special files report a size of 0, so only regular files are redirected
+        if metadata.is_file()
+            && metadata.len()
+                <= params
+                    .block_size
+                    .try_into()
+                    .context("Unable to parse stream block size as u64")?
+        {
+            eprintln!(
+                "Input file size is less than the stream block size - redirecting to memory mode"
+            );
+            return memory_mode(input, output, keyfile, params);
+        }
     }
 
+    record_params(input, output, "stream", params);
+
+    stdin_check(input, output, keyfile, params)?;
     if !overwrite_check(output, params.skip, params.bench)? {
-        exit(0);
+        return Err(Failure::Aborted.into());
     }
 
-    if input == output {
+    if input == output && input != STDIO {
         return Err(anyhow::anyhow!(
             "Input and output files cannot have the same name in stream mode."
-        ));
+        )
+        .context(Failure::InvalidArguments));
     }
 
+    check_verifiable(output, params)?;
+
+    let mut input_file = open_input(input)?;
+
     let mut output_file = if params.bench == BenchMode::WriteToFilesystem {
-        OutputFile::Some(
-            File::create(output)
-                .with_context(|| format!("Unable to open output file: {}", output))?,
-        )
+        create_output(output)?
     } else {
         OutputFile::None
     };
 
-    let raw_key = get_user_key(keyfile, true, params.password)?;
+    let key = get_encryption_key(keyfile, params)?;
+    let verify_key = verification_key(&key, params);
 
-    println!(
+    eprintln!(
         "Encrypting {} in stream mode with {} (this may take a while)",
         input, params.cipher_type
     );
     let encrypt_start_time = Instant::now();
 
-    encrypt_bytes_stream_mode(
-        &mut input_file,
-        &mut output_file,
-        raw_key,
-        params.bench,
-        params.hash_mode,
-        params.cipher_type,
-    )?;
+    let bar = progress_bar::for_input(input, 0);
+    let mut reader = ProgressReader::new(&mut input_file, |bytes| bar.set_position(bytes));
+    let mut writer = HashWriter::new(&mut output_file, params.hash_mode);
+    let result = match params.threads {
+        ThreadMode::SingleThreaded => dexios::stream::encrypt(
+            &mut reader,
+            &mut writer,
+            key,
+            params.cipher_type,
+            params.block_size,
+        ),
+        ThreadMode::MultiThreaded(threads) => dexios::stream::encrypt_parallel(
+            &mut reader,
+            &mut writer,
+            key,
+            params.cipher_type,
+            params.block_size,
+            threads,
+        ),
+    };
+    bar.finish_and_clear();
+    let (header, master_key) = result.context("Unable to encrypt the file")?;
     let encrypt_duration = encrypt_start_time.elapsed();
+    report::record("bytes", reader.bytes());
+
+    if let Some(hash) = writer.finalize() {
+        eprintln!("Hash of the encrypted file is: {}", hash);
+        report::record("hash", hash);
+    }
+
     match params.bench {
         BenchMode::WriteToFilesystem => {
-            println!(
+            eprintln!(
                 "Encryption successful! File saved as {} [took {:.2}s]",
                 output,
                 encrypt_duration.as_secs_f32(),
             );
         }
         BenchMode::BenchmarkInMemory => {
-            println!(
+            eprintln!(
                 "Encryption successful! [took {:.2}s]",
                 encrypt_duration.as_secs_f32(),
             );
         }
     }
 
+    drop(output_file);
+    verify_output(input, output, &header, master_key, verify_key, params)?;
+    erase_input(input, params)
+}
+
+// this records the details of the encryption for the json report
+fn record_params(input: &str, output: &str, mode: &str, params: &Parameters) {
+    report::record("input", input);
+    report::record("output", output);
+    report::record("cipher", params.cipher_type.to_string());
+    report::record("mode", mode);
+}
+
+// the output can only be verified if it's written to a file
+// this is checked before anything is encrypted, so the user isn't left waiting for an error
+fn check_verifiable(output: &str, params: &Parameters) -> Result<()> {
+    if params.output_verify == OutputVerifyMode::SkipVerification {
+        return Ok(());
+    }
+
+    let reason = if params.bench == BenchMode::BenchmarkInMemory {
+        "it isn't written to the disk in benchmark mode"
+    } else if output == STDIO {
+        "it's written to stdout"
+    } else {
+        return Ok(());
+    };
+
+    Err(anyhow::anyhow!(
+        "The output can't be verified, as {} (use --no-verify to erase the input without verifying the output)",
+        reason
+    )
+    .context(Failure::InvalidArguments))
+}
+
+// this copies the key that a key slot in the output should be unlocked with, before encrypting uses it up
+// recipient key slots can only be unwrapped with an identity, so files that are only encrypted to recipients are verified with the master key alone
+fn verification_key(key: &EncryptionKey, params: &Parameters) -> Option<DecryptionKey> {
+    if params.output_verify == OutputVerifyMode::SkipVerification {
+        return None;
+    }
+
+    match key {
+        EncryptionKey::Password { raw_key, .. } => Some(DecryptionKey::Password(Secret::new(
+            raw_key.expose_secret().clone(),
+        ))),
+        EncryptionKey::Cached { cache, .. } => Some(DecryptionKey::Cached(cache.clone())),
+        EncryptionKey::Recipients(_) => None,
+    }
+}
+
+// this reads the output back from the disk, and checks that it's exactly what was encrypted
+// the header (including the key slots, which aren't authenticated with the data) must match the one that was written, byte for byte
+// a key slot is then unlocked with the user's key, and every block (along with the header) is authenticated with the master key that it unwraps
+// so a file that passes can be decrypted with the same key that it was encrypted with, and verifying doesn't change its key slots
+// it's synced first, so any errors while writing it are reported too
+// if anything fails, the input is left intact, as this runs before it's erased
+fn verify_output(
+    input: &str,
+    output: &str,
+    written: &Header,
+    master_key: Secret<[u8; 32]>,
+    verify_key: Option<DecryptionKey>,
+    params: &Parameters,
+) -> Result<()> {
+    if params.output_verify == OutputVerifyMode::SkipVerification {
+        return Ok(());
+    }
+
+    let failed = || {
+        format!(
+            "Unable to verify {}, so {} has been left intact",
+            output, input
+        )
+    };
+
+    let mut file = File::open(output)
+        .with_context(|| format!("Unable to open output file: {}", output))
+        .with_context(failed)?;
+    file.sync_all()
+        .with_context(|| format!("Unable to sync output file: {}", output))
+        .with_context(failed)?;
+
+    let header = Header::deserialize(&mut file)
+        .with_context(|| format!("Unable to read the header from file: {}", output))
+        .with_context(failed)?;
+    if header.serialize() != written.serialize() {
+        return Err(anyhow::anyhow!(
+            "The header in {} doesn't match the one that was written",
+            output
+        )
+        .context(Failure::Corrupt))
+        .with_context(failed);
+    }
+
+    eprintln!("Verifying {}", output);
+    let verify_start_time = Instant::now();
+
+    let key = match verify_key {
+        Some(verify_key) => {
+            let (_, unlocked) = unlock_keyslot(&header, verify_key)
+                .context("Unable to unlock a key slot in the output with your key")
+                .with_context(failed)?;
+            if unlocked.expose_secret() != master_key.expose_secret() {
+                return Err(
+                    anyhow::anyhow!("The key slot in {} unwraps the wrong key", output)
+                        .context(Failure::Corrupt),
+                )
+                .with_context(failed);
+            }
+
+            DecryptionKey::MasterKey(unlocked)
+        }
+        None => DecryptionKey::MasterKey(master_key),
+    };
+
+    let result = match header.cipher_mode {
+        CipherMode::MemoryMode => dexios::memory::verify(&mut file, &header, key),
+        CipherMode::StreamMode => {
+            let bar = progress_bar::for_input(output, header.serialize().len() as u64);
+            let mut reader = ProgressReader::new(&mut file, |bytes| bar.set_position(bytes));
+            let result = dexios::stream::verify(&mut reader, &header, key);
+            bar.finish_and_clear();
+            result
+        }
+    };
+    result.with_context(failed)?;
+    let verify_duration = verify_start_time.elapsed();
+
+    report::record("verified", true);
+    eprintln!(
+        "Verified {} [took {:.2}s]",
+        output,
+        verify_duration.as_secs_f32()
+    );
+
+    Ok(())
+}
+
+// this erases the input file, if the user requested it
+// stdin can't be erased, so it's skipped
+fn erase_input(input: &str, params: &Parameters) -> Result<()> {
     if params.erase != EraseMode::IgnoreFile(0) {
-        crate::erase::secure_erase(input, params.erase.get_passes())?;
+        if input == STDIO {
+            eprintln!("Skipping erasure as the input was read from stdin");
+        } else {
+            crate::erase::secure_erase(input, params.erase.get_passes())?;
+        }
     }
 
     Ok(())
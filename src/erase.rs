@@ -1,69 +1,289 @@
+use crate::failure::Failure;
+use crate::file::{exclude_set, get_paths_in_dir, is_excluded};
+use crate::global::{DirectoryMode, ErasePattern, EraseVerifyMode};
+use crate::progress_bar;
+use crate::report;
 use anyhow::{Context, Result};
-use rand::RngCore;
+use globset::GlobSet;
+use rand::{prelude::StdRng, RngCore, SeedableRng};
 use std::{
-    fs::File,
-    io::{BufWriter, Write},
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
     time::Instant,
 };
 
-// this function securely erases a file
-// read the wiki for some caveats with file-erasure on flash storage
+// this file securely erases files, by overwriting every byte of them in place before they're removed
+// read the wiki for some caveats with file-erasure on flash storage (and copy-on-write filesystems)
+//
+// the file is never truncated before it's overwritten, as that would free its blocks without overwriting them
+// each pass is synced to the disk before the next one starts, so the writes can't just be merged in the page cache
+// once every pass is complete, the file is truncated, renamed to a random name (so the name is hidden too), and removed
+
+const BUFFER_SIZE: usize = 1_048_576; // 1MiB
+
+// these are the fixed patterns from Peter Gutmann's 35-pass method (passes 5 to 31)
+// the first and last four passes are random
+const GUTMANN_PATTERNS: [[u8; 3]; 27] = [
+    [0x55, 0x55, 0x55],
+    [0xAA, 0xAA, 0xAA],
+    [0x92, 0x49, 0x24],
+    [0x49, 0x24, 0x92],
+    [0x24, 0x92, 0x49],
+    [0x00, 0x00, 0x00],
+    [0x11, 0x11, 0x11],
+    [0x22, 0x22, 0x22],
+    [0x33, 0x33, 0x33],
+    [0x44, 0x44, 0x44],
+    [0x55, 0x55, 0x55],
+    [0x66, 0x66, 0x66],
+    [0x77, 0x77, 0x77],
+    [0x88, 0x88, 0x88],
+    [0x99, 0x99, 0x99],
+    [0xAA, 0xAA, 0xAA],
+    [0xBB, 0xBB, 0xBB],
+    [0xCC, 0xCC, 0xCC],
+    [0xDD, 0xDD, 0xDD],
+    [0xEE, 0xEE, 0xEE],
+    [0xFF, 0xFF, 0xFF],
+    [0x92, 0x49, 0x24],
+    [0x49, 0x24, 0x92],
+    [0x24, 0x92, 0x49],
+    [0x6D, 0xB6, 0xDB],
+    [0xB6, 0xDB, 0x6D],
+    [0xDB, 0x6D, 0xB6],
+];
+
+// this is what a single pass overwrites the file with
+// random passes are generated from a seed, so they can be generated again to verify them
+#[derive(Clone, Copy)]
+enum Pass {
+    Random(u64),
+    Pattern([u8; 3]),
+}
+
+// this is the `erase` subcommand, which erases either a single file or an entire directory
+// symlinks are refused, as erasing one would overwrite whatever it points to
+pub fn erase(
+    input: &str,
+    mode: DirectoryMode,
+    exclude: &[&str],
+    pattern: ErasePattern,
+    verify: EraseVerifyMode,
+) -> Result<()> {
+    let metadata = std::fs::symlink_metadata(input)
+        .with_context(|| format!("Unable to get input file metadata: {}", input))?;
+
+    if metadata.file_type().is_symlink() {
+        return Err(anyhow::anyhow!(
+            "{} is a symlink - erase the file that it points to instead",
+            input
+        )
+        .context(Failure::InvalidArguments));
+    }
+
+    if metadata.is_dir() {
+        if mode != DirectoryMode::Recursive {
+            return Err(anyhow::anyhow!(
+                "{} is a directory - use -r to erase it, along with everything inside of it",
+                input
+            )
+            .context(Failure::InvalidArguments));
+        }
+
+        return erase_directory(input, exclude, pattern, verify);
+    }
+
+    erase_file(input, pattern, verify)?;
+    report::record("files", 1);
+    report::record("bytes", metadata.len());
+    Ok(())
+}
+
+// this erases every regular file within a directory (and its subdirectories), and then removes the directories from the bottom up
+// it uses the same traversal as pack, so anything that matches an exclude pattern is left alone (along with the directories that contain it)
+// symlinks are never followed - the links themselves are removed, and whatever they point to is left intact
+fn erase_directory(
+    input: &str,
+    exclude: &[&str],
+    pattern: ErasePattern,
+    verify: EraseVerifyMode,
+) -> Result<()> {
+    let (files, dirs) = get_paths_in_dir(input, DirectoryMode::Recursive, exclude)?;
+    let set = exclude_set(exclude)?;
+
+    let mut erased_files = 0;
+    let mut erased_bytes = 0;
+    let mut skipped = 0;
+
+    for path in &files {
+        let name = path
+            .to_str()
+            .context("Error converting file path to string")?;
+        let metadata = std::fs::symlink_metadata(path)
+            .with_context(|| format!("Unable to get input file metadata: {}", name))?;
+
+        // pipes, sockets and devices can't be overwritten, so they're left alone
+        if !metadata.is_file() {
+            eprintln!("Skipping {} as it's not a regular file", name);
+            skipped += 1;
+            continue;
+        }
+
+        erase_file(name, pattern, verify)?;
+        erased_files += 1;
+        erased_bytes += metadata.len();
+    }
+
+    // the directories are listed parents-first, so they're reversed to remove each one after its subdirectories
+    let mut removed_dirs = 0;
+    let mut removed_links = 0;
+    let mut kept_dirs = 0;
+
+    for dir in dirs
+        .unwrap_or_default()
+        .iter()
+        .rev()
+        .map(PathBuf::as_path)
+        .chain(std::iter::once(Path::new(input)))
+    {
+        removed_links += remove_links(dir, &set)?;
+
+        // this only fails if the directory isn't empty, e.g. if it contains excluded files
+        if std::fs::remove_dir(dir).is_ok() {
+            removed_dirs += 1;
+        } else {
+            eprintln!(
+                "Kept {} as it still contains excluded (or skipped) files",
+                dir.display()
+            );
+            kept_dirs += 1;
+        }
+    }
+
+    report::record("files", erased_files);
+    report::record("bytes", erased_bytes);
+    report::record("directories", removed_dirs);
+    report::record("symlinks", removed_links);
+    report::record("kept_directories", kept_dirs);
+    report::record("skipped", skipped);
+
+    report::print_result(&format!(
+        "Erased {} file(s) ({} bytes), and removed {} directory(ies) and {} symlink(s) from {}",
+        erased_files, erased_bytes, removed_dirs, removed_links, input
+    ));
+    if kept_dirs > 0 || skipped > 0 {
+        report::print_result(&format!(
+            "Kept {} directory(ies), and skipped {} file(s) that aren't regular files",
+            kept_dirs, skipped
+        ));
+    }
+
+    Ok(())
+}
+
+// this removes the (non-excluded) symlinks within a directory, without following them
+// it returns the number of symlinks that were removed
+fn remove_links(dir: &Path, set: &GlobSet) -> Result<usize> {
+    let mut removed = 0;
+
+    for entry in std::fs::read_dir(dir)
+        .with_context(|| format!("Unable to open the directory: {}", dir.display()))?
+    {
+        let entry =
+            entry.with_context(|| format!("Unable to get the item's path: {}", dir.display()))?;
+        let path = entry.path();
+
+        if entry.file_type()?.is_symlink() && !is_excluded(set, &path) {
+            std::fs::remove_file(&path)
+                .with_context(|| format!("Unable to remove symlink: {}", path.display()))?;
+            removed += 1;
+        }
+    }
+
+    Ok(removed)
+}
+
+// this function securely erases a file, by overwriting it with random data
+// it's used for erasing input files and temporary files, so the erasure isn't verified
 // it takes the file name/relative path, and the number of times to go over the file's contents with random bytes
 #[allow(clippy::module_name_repetitions)]
-pub fn secure_erase(input: &str, passes: i32) -> Result<()> {
+pub fn secure_erase(input: &str, passes: u32) -> Result<()> {
+    erase_file(
+        input,
+        ErasePattern::Random(passes),
+        EraseVerifyMode::SkipVerification,
+    )
+}
+
+// this function securely erases a file with the chosen pattern
+// if verification was requested, each pass is read back after it has been synced, and compared with what was written
+pub fn erase_file(input: &str, pattern: ErasePattern, verify: EraseVerifyMode) -> Result<()> {
     let start_time = Instant::now();
-    let file = File::open(input).with_context(|| format!("Unable to open file: {}", input))?;
-    let data = file
+
+    let mut file = open_no_follow(input)?;
+    let metadata = file
         .metadata()
         .with_context(|| format!("Unable to get input file metadata: {}", input))?;
 
-    let file = File::create(input).with_context(|| format!("Unable to open file: {}", input))?;
-    let mut writer = BufWriter::new(file);
+    // this is checked on the file that was opened, so it can't be swapped out for something else in the meantime
+    if !metadata.is_file() {
+        return Err(
+            anyhow::anyhow!("{} is not a regular file", input).context(Failure::InvalidArguments)
+        );
+    }
+    let len = metadata.len();
+
+    let passes = get_passes(pattern);
+    if passes.is_empty() {
+        return Err(
+            anyhow::anyhow!("At least one pass is required to erase {}", input)
+                .context(Failure::InvalidArguments),
+        );
+    }
 
-    println!(
-        "Erasing {} with {} passes (this may take a while)",
-        input, passes
+    eprintln!(
+        "Erasing {} with {} ({} passes) (this may take a while)",
+        input,
+        pattern,
+        passes.len()
     );
 
-    for _ in 0..passes {
-        for _ in 0..data.len() / 512 {
-            let mut buf = Vec::with_capacity(512);
-            rand::thread_rng().fill_bytes(&mut buf);
-            writer
-                .write_all(&buf)
-                .with_context(|| format!("Unable to overwrite with random bytes: {}", input))?;
-        }
+    let bar = progress_bar::new(Some(len * passes.len() as u64));
 
-        writer
-            .flush()
-            .with_context(|| format!("Unable to flush file: {}", input))?;
-    }
+    for (index, pass) in passes.iter().enumerate() {
+        overwrite(&mut file, len, *pass, &|bytes| bar.inc(bytes))
+            .with_context(|| format!("Unable to overwrite {} (pass {})", input, index + 1))?;
+
+        file.sync_all()
+            .with_context(|| format!("Unable to sync file: {}", input))?;
 
-    // overwrite with zeros for good measure
-    let file = File::create(input).with_context(|| format!("Unable to open file: {}", input))?;
-    let mut writer = BufWriter::new(file);
-    for _ in 0..data.len() {
-        writer
-            .write(&[0])
-            .with_context(|| format!("Unable to overwrite with zeros: {}", input))?;
+        if verify == EraseVerifyMode::VerifyPasses {
+            check(&mut file, len, *pass).with_context(|| {
+                format!(
+                    "Unable to verify the erasure of {} (pass {})",
+                    input,
+                    index + 1
+                )
+            })?;
+        }
     }
-    writer
-        .flush()
-        .with_context(|| format!("Unable to flush file: {}", input))?;
-    drop(writer);
+    bar.finish_and_clear();
 
-    let mut file = File::create(input).context("Unable to open the input file")?;
     file.set_len(0)
         .with_context(|| format!("Unable to truncate file: {}", input))?;
-    file.flush()
-        .with_context(|| format!("Unable to flush file: {}", input))?;
+    file.sync_all()
+        .with_context(|| format!("Unable to sync file: {}", input))?;
     drop(file);
 
-    std::fs::remove_file(input).with_context(|| format!("Unable to remove file: {}", input))?;
+    // the file is renamed before it's removed, so the name doesn't linger in the directory either
+    let hidden_name = hide_name(input)?;
+    std::fs::remove_file(&hidden_name)
+        .with_context(|| format!("Unable to remove file: {}", input))?;
 
     let duration = start_time.elapsed();
 
-    println!(
+    eprintln!(
         "Erased {} successfully [took {:.2}s]",
         input,
         duration.as_secs_f32()
@@ -71,3 +291,370 @@ pub fn secure_erase(input: &str, passes: i32) -> Result<()> {
 
     Ok(())
 }
+
+// this opens a file for overwriting, without following a symlink
+// the path may have been checked beforehand, but it could have been replaced by a symlink since then, and we must never overwrite whatever that points to
+// O_NONBLOCK stops the open from hanging if it has been replaced by a fifo (it has no effect on regular files)
+#[cfg(unix)]
+fn open_no_follow(input: &str) -> Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOFOLLOW | libc::O_NONBLOCK)
+        .open(input)
+        .with_context(|| {
+            format!(
+                "Unable to open file (symlinks are never followed): {}",
+                input
+            )
+        })
+}
+
+#[cfg(not(unix))]
+fn open_no_follow(input: &str) -> Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(input)
+        .with_context(|| format!("Unable to open file: {}", input))
+}
+
+// this returns the passes that make up each pattern
+// DoD 5220.22-M overwrites the file with zeros, then ones, and then random data
+fn get_passes(pattern: ErasePattern) -> Vec<Pass> {
+    let mut rng = StdRng::from_entropy();
+
+    match pattern {
+        ErasePattern::Random(passes) => (0..passes).map(|_| Pass::Random(rng.next_u64())).collect(),
+        ErasePattern::Zeros(passes) => (0..passes).map(|_| Pass::Pattern([0x00; 3])).collect(),
+        ErasePattern::Dod => vec![
+            Pass::Pattern([0x00; 3]),
+            Pass::Pattern([0xFF; 3]),
+            Pass::Random(rng.next_u64()),
+        ],
+        ErasePattern::Gutmann => {
+            let mut passes: Vec<Pass> = (0..4).map(|_| Pass::Random(rng.next_u64())).collect();
+            passes.extend(
+                GUTMANN_PATTERNS
+                    .iter()
+                    .map(|pattern| Pass::Pattern(*pattern)),
+            );
+            passes.extend((0..4).map(|_| Pass::Random(rng.next_u64())));
+            passes
+        }
+    }
+}
+
+// this fills the buffer with the pass's data, starting at `offset` bytes into the file
+// patterns are aligned to the start of the file, so they're continuous across buffers
+fn fill(buffer: &mut [u8], pass: Pass, offset: u64, rng: &mut StdRng) {
+    match pass {
+        Pass::Random(_) => rng.fill_bytes(buffer),
+        Pass::Pattern(pattern) => {
+            let start = (offset % 3) as usize;
+            for (i, byte) in buffer.iter_mut().enumerate() {
+                *byte = pattern[(start + i) % 3];
+            }
+        }
+    }
+}
+
+// this writes a single pass over the entire file, including any tail that's smaller than the buffer
+fn overwrite(file: &mut File, len: u64, pass: Pass, progress: &dyn Fn(u64)) -> Result<()> {
+    let mut rng = pass_rng(pass);
+    let mut buffer = vec![0u8; BUFFER_SIZE];
+
+    file.seek(SeekFrom::Start(0))?;
+
+    let mut offset = 0;
+    while offset < len {
+        let size = buffer_len(len - offset);
+        fill(&mut buffer[..size], pass, offset, &mut rng);
+        file.write_all(&buffer[..size])?;
+        offset += size as u64;
+        progress(size as u64);
+    }
+
+    file.flush()?;
+    Ok(())
+}
+
+// this reads the file back, and checks that it contains exactly what the pass wrote
+fn check(file: &mut File, len: u64, pass: Pass) -> Result<()> {
+    let mut rng = pass_rng(pass);
+    let mut expected = vec![0u8; BUFFER_SIZE];
+    let mut actual = vec![0u8; BUFFER_SIZE];
+
+    file.seek(SeekFrom::Start(0))?;
+
+    let mut offset = 0;
+    while offset < len {
+        let size = buffer_len(len - offset);
+        fill(&mut expected[..size], pass, offset, &mut rng);
+        file.read_exact(&mut actual[..size])?;
+
+        if let Some(position) = (0..size).find(|&i| expected[i] != actual[i]) {
+            return Err(anyhow::anyhow!(
+                "The data at byte offset {} doesn't match what was written",
+                offset + position as u64
+            ));
+        }
+        offset += size as u64;
+    }
+
+    Ok(())
+}
+
+// random passes are seeded, so the same data can be generated again when verifying
+fn pass_rng(pass: Pass) -> StdRng {
+    match pass {
+        Pass::Random(seed) => StdRng::seed_from_u64(seed),
+        Pass::Pattern(_) => StdRng::seed_from_u64(0),
+    }
+}
+
+fn buffer_len(remaining: u64) -> usize {
+    usize::try_from(remaining).map_or(BUFFER_SIZE, |remaining| remaining.min(BUFFER_SIZE))
+}
+
+// this renames the file to a random name of the same length, and returns the new name
+fn hide_name(input: &str) -> Result<String> {
+    let path = Path::new(input);
+    let name_len = path
+        .file_name()
+        .map_or(0, |name| name.to_string_lossy().len())
+        .max(1);
+
+    let mut rng = StdRng::from_entropy();
+    let random_name: String = (0..name_len)
+        .map(|_| {
+            char::from(b"abcdefghijklmnopqrstuvwxyz0123456789"[(rng.next_u32() % 36) as usize])
+        })
+        .collect();
+
+    let hidden_name = path
+        .with_file_name(random_name)
+        .to_string_lossy()
+        .to_string();
+
+    // a file that already has this name must never be replaced, so the original name is kept if there's a clash
+    if Path::new(&hidden_name).exists() {
+        return Ok(input.to_string());
+    }
+
+    std::fs::rename(input, &hidden_name)
+        .with_context(|| format!("Unable to rename file: {}", input))?;
+    Ok(hidden_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // each test works within its own directory, so they're able to run in parallel
+    fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("dexios-erase-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_file(path: &Path, len: usize) {
+        let data: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+        std::fs::write(path, data).unwrap();
+    }
+
+    fn path_str(path: &Path) -> &str {
+        path.to_str().unwrap()
+    }
+
+    // the file is larger than the buffer, so the tail is overwritten and checked too
+    #[test]
+    fn every_pattern_erases_the_file() {
+        let dir = test_dir("patterns");
+
+        for (index, pattern) in [
+            ErasePattern::Random(2),
+            ErasePattern::Zeros(1),
+            ErasePattern::Dod,
+            ErasePattern::Gutmann,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let path = dir.join(format!("file{}", index));
+            write_file(&path, BUFFER_SIZE + 5);
+
+            erase_file(path_str(&path), pattern, EraseVerifyMode::VerifyPasses).unwrap();
+            assert!(!path.exists());
+        }
+
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        std::fs::remove_dir(&dir).unwrap();
+    }
+
+    #[test]
+    fn empty_files_are_erased() {
+        let dir = test_dir("empty");
+        let path = dir.join("empty");
+        File::create(&path).unwrap();
+
+        secure_erase(path_str(&path), 1).unwrap();
+        assert!(!path.exists());
+        std::fs::remove_dir(&dir).unwrap();
+    }
+
+    #[test]
+    fn zero_passes_are_rejected() {
+        let dir = test_dir("zero-passes");
+        let path = dir.join("file");
+        write_file(&path, 16);
+
+        let err = secure_erase(path_str(&path), 0).unwrap_err();
+        assert_eq!(Failure::of(&err), Failure::InvalidArguments);
+        assert!(path.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // the patterns continue across buffers, so the file is one unbroken pattern
+    #[test]
+    fn patterns_are_continuous() {
+        let pass = Pass::Pattern([0x92, 0x49, 0x24]);
+        let mut rng = pass_rng(pass);
+        let mut whole = vec![0u8; 10];
+        fill(&mut whole, pass, 0, &mut rng);
+
+        let mut tail = vec![0u8; 6];
+        fill(&mut tail, pass, 4, &mut rng);
+        assert_eq!(tail, whole[4..]);
+    }
+
+    #[test]
+    fn check_detects_tampering() {
+        let dir = test_dir("check");
+        let path = dir.join("file");
+        write_file(&path, BUFFER_SIZE + 5);
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        let len = file.metadata().unwrap().len();
+
+        for pass in [Pass::Random(7), Pass::Pattern([0x6D, 0xB6, 0xDB])] {
+            overwrite(&mut file, len, pass, &|_| {}).unwrap();
+            check(&mut file, len, pass).unwrap();
+
+            file.seek(SeekFrom::Start(BUFFER_SIZE as u64 + 2)).unwrap();
+            let mut byte = [0u8; 1];
+            file.read_exact(&mut byte).unwrap();
+            file.seek(SeekFrom::Start(BUFFER_SIZE as u64 + 2)).unwrap();
+            file.write_all(&[!byte[0]]).unwrap();
+
+            let err = check(&mut file, len, pass).unwrap_err();
+            assert!(err
+                .to_string()
+                .contains(&format!("byte offset {}", BUFFER_SIZE + 2)));
+        }
+
+        drop(file);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn directories_need_recursive_mode() {
+        let dir = test_dir("directory");
+        write_file(&dir.join("file"), 16);
+
+        let err = erase(
+            path_str(&dir),
+            DirectoryMode::Singular,
+            &[],
+            ErasePattern::Random(1),
+            EraseVerifyMode::SkipVerification,
+        )
+        .unwrap_err();
+        assert_eq!(Failure::of(&err), Failure::InvalidArguments);
+        assert!(dir.join("file").exists());
+
+        // directories can't be opened for writing, so erasing one as a file fails before anything is overwritten
+        assert!(erase_file(
+            path_str(&dir),
+            ErasePattern::Random(1),
+            EraseVerifyMode::SkipVerification,
+        )
+        .is_err());
+        assert!(dir.join("file").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn directories_are_erased_recursively() {
+        let dir = test_dir("recursive");
+        let input = dir.join("input");
+        std::fs::create_dir_all(input.join("nested/deeper")).unwrap();
+        write_file(&input.join("top"), 16);
+        write_file(&input.join("nested/middle"), BUFFER_SIZE + 1);
+        write_file(&input.join("nested/deeper/bottom"), 0);
+
+        erase(
+            path_str(&input),
+            DirectoryMode::Recursive,
+            &[],
+            ErasePattern::Random(1),
+            EraseVerifyMode::VerifyPasses,
+        )
+        .unwrap();
+        assert!(!input.exists());
+
+        std::fs::remove_dir(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_never_followed() {
+        let dir = test_dir("symlinks");
+        let target = dir.join("target");
+        write_file(&target, 16);
+        let original = std::fs::read(&target).unwrap();
+
+        let link = dir.join("link");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        let err = erase(
+            path_str(&link),
+            DirectoryMode::Singular,
+            &[],
+            ErasePattern::Random(1),
+            EraseVerifyMode::SkipVerification,
+        )
+        .unwrap_err();
+        assert_eq!(Failure::of(&err), Failure::InvalidArguments);
+        assert!(secure_erase(path_str(&link), 1).is_err());
+        assert_eq!(std::fs::read(&target).unwrap(), original);
+
+        // links within a directory are removed, but whatever they point to is left intact
+        let input = dir.join("input");
+        std::fs::create_dir(&input).unwrap();
+        std::os::unix::fs::symlink(&target, input.join("link")).unwrap();
+        write_file(&input.join("file"), 16);
+
+        erase(
+            path_str(&input),
+            DirectoryMode::Recursive,
+            &[],
+            ErasePattern::Random(1),
+            EraseVerifyMode::SkipVerification,
+        )
+        .unwrap();
+        assert!(!input.exists());
+        assert_eq!(std::fs::read(&target).unwrap(), original);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
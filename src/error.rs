@@ -0,0 +1,77 @@
+// this file contains the error type that is used throughout the dexios library
+// each variant represents a distinct failure, so callers are able to tell them apart
+// (e.g. "wrong key" vs "malformed file" vs "disk full")
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    NoHeader,
+    UnsupportedVersion(u16),
+    InvalidHeader(&'static str),
+    InvalidKdfParams,
+    InvalidKey(&'static str),
+    WrongKeyType(&'static str),
+    NoMatchingKeyslot,
+    TooManyKeyslots,
+    KdfCostTooHigh,
+    KeyslotsUnsupported,
+    KeyslotNotFound(usize),
+    LastKeyslot,
+    InvalidRange,
+    InvalidBlockSize(usize),
+    CorruptBlock { index: u64, offset: u64 },
+    WrongKeyOrCorrupt,
+    KeyDerivation,
+    CipherInit,
+    Encrypt,
+    Decrypt,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "I/O error: {}", err),
+            Error::NoHeader => write!(f, "No dexios header was found. If this file was encrypted with an older version of dexios, it must be decrypted in legacy mode"),
+            Error::UnsupportedVersion(version) => write!(
+                f,
+                "Unsupported header version ({}) - you may need a newer version of dexios",
+                version
+            ),
+            Error::InvalidHeader(reason) => write!(f, "Invalid header: {}", reason),
+            Error::InvalidKdfParams => write!(f, "Invalid argon2id parameters (the limits are 4GiB of memory, 64 iterations and a parallelism of 16)"),
+            Error::InvalidKey(reason) => write!(f, "Invalid key: {}", reason),
+            Error::WrongKeyType(reason) => write!(f, "Wrong type of key: {}", reason),
+            Error::NoMatchingKeyslot => write!(f, "None of the file's key slots could be unlocked with the provided key"),
+            Error::TooManyKeyslots => write!(f, "A file may only have up to 255 key slots"),
+            Error::KdfCostTooHigh => write!(f, "A file may only have up to 16 password/keyfile key slots, and their combined argon2id cost can't be more than a single key slot with the highest parameters"),
+            Error::KeyslotsUnsupported => write!(f, "This file doesn't have key slots, as it was encrypted by an older version of dexios"),
+            Error::KeyslotNotFound(index) => write!(f, "Key slot {} doesn't exist", index),
+            Error::LastKeyslot => write!(f, "The last key slot can't be removed, as the file would no longer be decryptable"),
+            Error::InvalidRange => write!(f, "The requested range is outside of the encrypted data"),
+            Error::InvalidBlockSize(block_size) => write!(f, "Invalid block size ({} bytes) - it must be between 64KiB and 64MiB", block_size),
+            Error::CorruptBlock { index, offset } => write!(f, "Block {} (at byte offset {}) failed authentication - the file (or its header) has been modified, truncated or corrupted", index, offset),
+            Error::WrongKeyOrCorrupt => write!(f, "Unable to decrypt the data - either the key is wrong, or the file has been modified or corrupted. This file's header version doesn't check the key separately, so the two can't be told apart"),
+            Error::KeyDerivation => write!(f, "Error while hashing your password with argon2id"),
+            Error::CipherInit => write!(f, "Unable to create cipher with argon2id hashed key."),
+            Error::Encrypt => write!(f, "Unable to encrypt the data"),
+            Error::Decrypt => write!(f, "Unable to decrypt the data. Maybe it's the wrong key, or it's not an encrypted file."),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
@@ -0,0 +1,118 @@
+// this file sorts errors into the different kinds of failure, and gives each of them its own exit code
+// the exit codes are documented in the README, so scripts are able to tell e.g. "wrong key" apart from "disk full"
+// most errors come from the library, but user aborts and bad arguments are raised by the CLI itself (as errors, or as context)
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    Other,
+    InvalidArguments,
+    Corrupt,
+    Authentication,
+    InvalidHeader,
+    UnsupportedVersion,
+    Io,
+    Aborted,
+}
+
+impl Failure {
+    // this decides what kind of failure an error is
+    // a `Failure` that was added as context takes priority over the error it wraps
+    // otherwise, the chain is walked from the outside in until we find a cause that we recognise
+    pub fn of(err: &anyhow::Error) -> Self {
+        if let Some(failure) = err.downcast_ref::<Failure>() {
+            return *failure;
+        }
+
+        for cause in err.chain() {
+            if let Some(err) = cause.downcast_ref::<dexios::Error>() {
+                return Self::from_library(err);
+            }
+
+            if let Some(err) = cause.downcast_ref::<std::io::Error>() {
+                // the library's Read/Write adapters (e.g. `DecryptReader`) have to wrap their errors within io errors
+                if let Some(err) = err
+                    .get_ref()
+                    .and_then(|err| err.downcast_ref::<dexios::Error>())
+                {
+                    return Self::from_library(err);
+                }
+
+                return Failure::Io;
+            }
+        }
+
+        Failure::Other
+    }
+
+    fn from_library(err: &dexios::Error) -> Self {
+        match err {
+            dexios::Error::Io(_) => Failure::Io,
+            dexios::Error::NoHeader
+            | dexios::Error::InvalidHeader(_)
+            | dexios::Error::InvalidKdfParams => Failure::InvalidHeader,
+            dexios::Error::UnsupportedVersion(_) => Failure::UnsupportedVersion,
+            // older files can't tell a wrong key apart from corrupt data, so that's treated as the wrong key
+            dexios::Error::NoMatchingKeyslot
+            | dexios::Error::WrongKeyType(_)
+            | dexios::Error::WrongKeyOrCorrupt
+            | dexios::Error::Decrypt => Failure::Authentication,
+            dexios::Error::CorruptBlock { .. } => Failure::Corrupt,
+            dexios::Error::InvalidKey(_)
+            | dexios::Error::InvalidRange
+            | dexios::Error::InvalidBlockSize(_)
+            | dexios::Error::TooManyKeyslots
+            | dexios::Error::KdfCostTooHigh
+            | dexios::Error::KeyslotsUnsupported
+            | dexios::Error::KeyslotNotFound(_)
+            | dexios::Error::LastKeyslot => Failure::InvalidArguments,
+            dexios::Error::KeyDerivation | dexios::Error::CipherInit | dexios::Error::Encrypt => {
+                Failure::Other
+            }
+        }
+    }
+
+    // 2 is also what clap uses when the command line can't be parsed
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Failure::Other => 1,
+            Failure::InvalidArguments => 2,
+            Failure::Corrupt => 3,
+            Failure::Authentication => 4,
+            Failure::InvalidHeader => 5,
+            Failure::UnsupportedVersion => 6,
+            Failure::Io => 7,
+            Failure::Aborted => 8,
+        }
+    }
+
+    // this is the short name that's used for the error kind in the json report
+    pub fn name(self) -> &'static str {
+        match self {
+            Failure::Other => "other",
+            Failure::InvalidArguments => "invalid_arguments",
+            Failure::Corrupt => "corrupt",
+            Failure::Authentication => "authentication",
+            Failure::InvalidHeader => "invalid_header",
+            Failure::UnsupportedVersion => "unsupported_version",
+            Failure::Io => "io",
+            Failure::Aborted => "aborted",
+        }
+    }
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Failure::Other => write!(f, "An error occurred"),
+            Failure::InvalidArguments => write!(f, "Invalid arguments"),
+            Failure::Corrupt => write!(f, "The file is corrupt"),
+            Failure::Authentication => write!(f, "Authentication failed"),
+            Failure::InvalidHeader => write!(f, "Invalid header"),
+            Failure::UnsupportedVersion => write!(f, "Unsupported header version"),
+            Failure::Io => write!(f, "I/O error"),
+            Failure::Aborted => write!(f, "Cancelled by the user"),
+        }
+    }
+}
+
+impl std::error::Error for Failure {}
@@ -1,22 +1,107 @@
-use crate::global::CipherType;
 use crate::global::DirectoryMode;
-use crate::global::SALT_LEN;
+use crate::global::OutputFile;
 use anyhow::{Context, Ok, Result};
+use dexios::Header;
 use globset::Glob;
+use globset::GlobSet;
 use globset::GlobSetBuilder;
+use rand::RngCore;
 use secrecy::Secret;
 use secrecy::SecretVec;
 use std::fs::read_dir;
+use std::fs::OpenOptions;
+use std::path::Path;
 use std::path::PathBuf;
 use std::{
     fs::File,
     io::{BufReader, Read, Write},
 };
 
-// this takes the name/relative path of a file, and returns the bytes wrapped in a secret
+// "-" may be used in place of a file name, to read from stdin or write to stdout
+pub const STDIO: &str = "-";
+
+// this opens the input for reading - either the named file, or stdin
+pub fn open_input(name: &str) -> Result<Box<dyn Read>> {
+    if name == STDIO {
+        Ok(Box::new(std::io::stdin()))
+    } else {
+        let file =
+            File::open(name).with_context(|| format!("Unable to open input file: {}", name))?;
+        Ok(Box::new(file))
+    }
+}
+
+// this returns the size of the input, if it's known
+// stdin (and pipes or other special files) don't have a size, so None is returned for them
+pub fn input_len(name: &str) -> Option<u64> {
+    if name == STDIO {
+        return None;
+    }
+
+    std::fs::metadata(name)
+        .ok()
+        .filter(std::fs::Metadata::is_file)
+        .map(|metadata| metadata.len())
+}
+
+// this creates the output - either the named file, or stdout
+pub fn create_output(name: &str) -> Result<OutputFile> {
+    if name == STDIO {
+        Ok(OutputFile::Stdout(std::io::stdout()))
+    } else {
+        let file =
+            File::create(name).with_context(|| format!("Unable to open output file: {}", name))?;
+        Ok(OutputFile::Some(file))
+    }
+}
+
+// this picks a name for a temporary file in the same directory as `name`, so it can be atomically renamed into place later
+// the name is hidden and random, so it won't clash with any existing files
+pub fn temp_name(name: &str) -> Result<String> {
+    let path = Path::new(name);
+    let file_name = path
+        .file_name()
+        .with_context(|| format!("Unable to get the output file's name: {}", name))?
+        .to_string_lossy();
+
+    Ok(path
+        .with_file_name(format!(
+            ".{}.{:08x}.dexios-tmp",
+            file_name,
+            rand::thread_rng().next_u32()
+        ))
+        .to_string_lossy()
+        .to_string())
+}
+
+// this creates a temporary file in the same directory as the output
+// it returns the temporary file's name, and the file itself
+pub fn create_temp_output(name: &str) -> Result<(String, File)> {
+    let temp_name = temp_name(name)?;
+
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&temp_name)
+        .with_context(|| format!("Unable to create temporary output file: {}", temp_name))?;
+
+    Ok((temp_name, file))
+}
+
+// this flushes the temporary file to the disk, and then renames it to the output file's name
+pub fn persist_temp_output(temp_name: &str, file: File, name: &str) -> Result<()> {
+    file.sync_all()
+        .with_context(|| format!("Unable to sync temporary output file: {}", temp_name))?;
+    drop(file);
+
+    std::fs::rename(temp_name, name)
+        .with_context(|| format!("Unable to rename {} to {}", temp_name, name))?;
+    Ok(())
+}
+
+// this takes the name/relative path of a file (or stdin), and returns the bytes wrapped in a secret
 pub fn get_bytes(name: &str) -> Result<Secret<Vec<u8>>> {
-    let file = File::open(name).with_context(|| format!("Unable to open file: {}", name))?;
-    let mut reader = BufReader::new(file);
+    let mut reader = BufReader::new(open_input(name)?);
     let mut data = Vec::new();
     reader
         .read_to_end(&mut data)
@@ -24,91 +109,16 @@ pub fn get_bytes(name: &str) -> Result<Secret<Vec<u8>>> {
     Ok(SecretVec::new(data))
 }
 
-// this takes the name/relative path of a file, and reads it in the correct format
-// this is used for memory-mode
-// the first 16 bytes of the file are always the salt
-// the next 12/24 bytes are always the nonce
-// the rest of the data is the encrpted data
-// all of these values are returned
-pub fn get_encrypted_data(
-    name: &str,
-    cipher_type: CipherType,
-) -> Result<([u8; SALT_LEN], Vec<u8>, Vec<u8>)> {
-    let file = File::open(name).with_context(|| format!("Unable to open input file: {}", name))?;
-    let mut reader = BufReader::new(file);
-
-    return match cipher_type {
-        CipherType::AesGcm => {
-            let mut salt = [0u8; SALT_LEN];
-            let mut nonce = [0u8; 12];
-            let mut encrypted_data: Vec<u8> = Vec::new();
-
-            let salt_size = reader
-                .read(&mut salt)
-                .with_context(|| format!("Unable to read salt from file: {}", name))?;
-            let nonce_size = reader
-                .read(&mut nonce)
-                .with_context(|| format!("Unable to read nonce from file: {}", name))?;
-            reader
-                .read_to_end(&mut encrypted_data)
-                .with_context(|| format!("Unable to read data from file: {}", name))?;
-
-            if salt_size != SALT_LEN || nonce_size != 12 {
-                return Err(anyhow::anyhow!(
-                    "Input file ({}) does not contain the correct amount of information",
-                    name
-                ));
-            }
-
-            Ok((salt, nonce.to_vec(), encrypted_data))
-        }
-        CipherType::XChaCha20Poly1305 => {
-            let mut salt = [0u8; SALT_LEN];
-            let mut nonce = [0u8; 24];
-            let mut encrypted_data: Vec<u8> = Vec::new();
-
-            let salt_size = reader
-                .read(&mut salt)
-                .with_context(|| format!("Unable to read salt from file: {}", name))?;
-            let nonce_size = reader
-                .read(&mut nonce)
-                .with_context(|| format!("Unable to read nonce from file: {}", name))?;
-            reader
-                .read_to_end(&mut encrypted_data)
-                .with_context(|| format!("Unable to read data from file: {}", name))?;
-
-            if salt_size != SALT_LEN || nonce_size != 24 {
-                return Err(anyhow::anyhow!(
-                    "Input file ({}) does not contain the correct amount of information",
-                    name
-                ));
-            }
-
-            Ok((salt, nonce.to_vec(), encrypted_data))
-        }
-    };
-}
-
-// this writes the data, in the format that get_encrypted_data() can read
+// this writes the data, in the format that the decrypt functions can read
 // this is used for memory-mode
-// it takes the file name/relative path, salt, nonce and the data
-// it first writes the 16 byte salt to the start of the file
-// then it writes the 12/24 byte nonce
-// and finally, it writes all of the data
-pub fn write_encrypted_data(
-    name: &str,
-    salt: &[u8; SALT_LEN],
-    nonce: &[u8],
-    data: &[u8],
-) -> Result<()> {
-    let mut writer =
-        File::create(name).with_context(|| format!("Unable to create output file: {}", name))?;
-    writer
-        .write_all(salt)
-        .with_context(|| format!("Unable to write salt to output file: {}", name))?;
-    writer
-        .write_all(nonce)
-        .with_context(|| format!("Unable to write nonce to output file: {}", name))?;
+// it takes the file name/relative path, the header and the data
+// it first writes the header to the start of the file
+// and then it writes all of the data
+pub fn write_encrypted_data(name: &str, header: &Header, data: &[u8]) -> Result<()> {
+    let mut writer = create_output(name)?;
+    header
+        .write(&mut writer)
+        .with_context(|| format!("Unable to write header to output file: {}", name))?;
     writer
         .write_all(data)
         .with_context(|| format!("Unable to write data to output file: {}", name))?;
@@ -118,10 +128,9 @@ pub fn write_encrypted_data(
     Ok(())
 }
 
-// this simply just writes bytes to the specified file
+// this simply just writes bytes to the specified file (or stdout)
 pub fn write_bytes(name: &str, bytes: &[u8]) -> Result<()> {
-    let mut writer =
-        File::create(name).with_context(|| format!("Unable to create output file: {}", name))?;
+    let mut writer = create_output(name)?;
     writer
         .write_all(bytes)
         .with_context(|| format!("Unable to write to the output file: {}", name))?;
@@ -131,6 +140,75 @@ pub fn write_bytes(name: &str, bytes: &[u8]) -> Result<()> {
     Ok(())
 }
 
+// this builds the set of glob patterns that should be excluded from a directory
+pub fn exclude_set(exclude: &[&str]) -> Result<GlobSet> {
+    let mut glob = GlobSetBuilder::new();
+    for p in exclude {
+        glob.add(Glob::new(p)?);
+    }
+    Ok(glob.build()?)
+}
+
+// paths are compared with both the full path and the file name
+pub fn is_excluded(set: &GlobSet, path: &Path) -> bool {
+    set.is_match(path) || path.file_name().is_some_and(|name| set.is_match(name))
+}
+
+// this lists everything within a directory, for archive formats that can store symlinks (and other special files) as they are
+// unlike get_paths_in_dir(), symlinks are listed rather than skipped - they're still never followed
+// each directory is listed before its contents
+pub fn get_entries_in_dir(
+    name: &str,
+    mode: DirectoryMode,
+    exclude: &[&str],
+) -> Result<Vec<PathBuf>> {
+    let set = exclude_set(exclude)?;
+    let mut entries = Vec::new();
+    list_entries(Path::new(name), mode, &set, &mut entries)?;
+    Ok(entries)
+}
+
+fn list_entries(
+    dir: &Path,
+    mode: DirectoryMode,
+    set: &GlobSet,
+    entries: &mut Vec<PathBuf>,
+) -> Result<()> {
+    let paths = read_dir(dir)
+        .with_context(|| format!("Unable to open the directory: {}", dir.display()))?;
+
+    for item in paths {
+        let path = item
+            .with_context(|| format!("Unable to get the item's path: {}", dir.display()))?
+            .path();
+
+        if is_excluded(set, &path) {
+            continue;
+        }
+
+        // symlink_metadata() doesn't follow symlinks, so a symlink to a directory isn't treated as one
+        let is_dir = path
+            .symlink_metadata()
+            .with_context(|| format!("Unable to get the metadata of {}", path.display()))?
+            .is_dir();
+
+        if is_dir && mode != DirectoryMode::Recursive {
+            eprintln!(
+                "Skipping {} as it's a directory and -r was not specified",
+                path.display()
+            );
+            continue;
+        }
+
+        entries.push(path.clone());
+        if is_dir {
+            list_entries(&path, mode, set, entries)?;
+        }
+    }
+
+    Ok(())
+}
+
 pub fn get_paths_in_dir(
     name: &str,
     mode: DirectoryMode,
@@ -142,35 +220,31 @@ pub fn get_paths_in_dir(
     let paths =
         read_dir(name).with_context(|| format!("Unable to open the directory: {}", name))?;
 
-    let mut glob = GlobSetBuilder::new();
-    for p in exclude {
-        glob.add(Glob::new(p)?);
-    }
-    let set = glob.build()?;
+    let set = exclude_set(exclude)?;
 
     for item in paths {
         let path = item
             .with_context(|| format!("Unable to get the item's path: {}", name))?
             .path(); // not great error message
 
-        if set.is_match(path.clone()) || set.is_match(path.clone().file_name().unwrap()) {
-            // compare with both file name and path
+        if is_excluded(&set, &path) {
             continue;
         }
 
-        if path.is_dir() && mode == DirectoryMode::Recursive {
+        // symlinks are checked first, as is_dir() follows them (and we never want to traverse outside of the directory)
+        if path.is_symlink() {
+            eprintln!("Not following {} as it's a symlink", path.display());
+        } else if path.is_dir() && mode == DirectoryMode::Recursive {
             let (files, dirs) = get_paths_in_dir(path.to_str().unwrap(), mode, exclude)?;
             dir_list.push(path);
 
             file_list.extend(files);
             dir_list.extend(dirs.unwrap()); // this should never error and it should be there, at least empty - should still add context
         } else if path.is_dir() {
-            println!(
+            eprintln!(
                 "Skipping {} as it's a directory and -r was not specified",
                 path.display()
             );
-        } else if path.is_symlink() {
-            println!("Skipping {} as it's a symlink", path.display());
         } else {
             file_list.push(path);
         }
@@ -1,17 +1,13 @@
-use aead::{
-    stream::{DecryptorLE31, EncryptorLE31},
-    Payload,
-};
-use aes_gcm::Aes256Gcm;
-use chacha20poly1305::XChaCha20Poly1305;
+use dexios::{CipherType, HeaderMode, KdfParams, KeyCache, Recipient};
 use std::fs::File;
 use std::io::Result;
+use std::io::Stdout;
 use std::io::Write;
+use std::ops::Range;
+use std::sync::Arc;
 
-// this file sets constants that are used throughout the codebase
-// these can be customised easily by anyone to suit their own needs
-pub const BLOCK_SIZE: usize = 1_048_576; // 1024*1024 bytes
-pub const SALT_LEN: usize = 16; // bytes
+// this file contains the types that are used throughout the command-line tool
+// the constants, ciphers and header types live within the dexios library
 
 pub struct Parameters {
     pub hash_mode: HashMode,
@@ -20,6 +16,16 @@ pub struct Parameters {
     pub password: PasswordMode,
     pub erase: EraseMode,
     pub cipher_type: CipherType,
+    pub header_mode: HeaderMode,
+    pub kdf_params: KdfParams,
+    pub verify: VerifyMode,
+    pub output_verify: OutputVerifyMode,
+    pub recipients: Vec<Recipient>,
+    pub identity: Option<String>,
+    pub threads: ThreadMode,
+    pub range: Option<Range<u64>>,
+    pub block_size: usize,
+    pub key_cache: Option<Arc<KeyCache>>, // only used in batch mode, where the key is read once for every file
 }
 
 #[derive(PartialEq, Eq, Clone, Copy)]
@@ -28,14 +34,35 @@ pub enum DirectoryMode {
     Recursive,
 }
 
+// zip archives can be read by other tools, but tar archives keep the unix metadata (permissions, ownership, timestamps, symlinks, hard links and xattrs)
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum PackFormat {
+    Zip,
+    Tar,
+}
+
+// restoring the owner of each file (from a tar archive) usually needs root, so it's opt-in
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum OwnershipMode {
+    RestoreOwnership,
+    IgnoreOwnership,
+}
+
+// hybrid identities combine X25519 with ML-KEM-768, so files encrypted to them stay secure against quantum computers
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum IdentityType {
+    X25519,
+    HybridPostQuantum,
+}
+
 #[derive(PartialEq, Eq, Clone, Copy)]
 pub enum EraseMode {
-    EraseFile(i32),
-    IgnoreFile(i32),
+    EraseFile(u32),
+    IgnoreFile(u32),
 }
 
 impl EraseMode {
-    pub fn get_passes(self) -> i32 {
+    pub fn get_passes(self) -> u32 {
         match self {
             EraseMode::EraseFile(passes) => passes,
             EraseMode::IgnoreFile(_) => 0,
@@ -43,6 +70,34 @@ impl EraseMode {
     }
 }
 
+// this is what erased files are overwritten with
+// random data and zeros are repeated for the chosen number of passes, the other patterns have a fixed number of passes
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum ErasePattern {
+    Random(u32),
+    Zeros(u32),
+    Dod,     // DoD 5220.22-M (zeros, ones and then random data)
+    Gutmann, // Peter Gutmann's 35-pass method
+}
+
+impl std::fmt::Display for ErasePattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ErasePattern::Random(_) => write!(f, "random data"),
+            ErasePattern::Zeros(_) => write!(f, "zeros"),
+            ErasePattern::Dod => write!(f, "the DoD 5220.22-M pattern"),
+            ErasePattern::Gutmann => write!(f, "the Gutmann pattern"),
+        }
+    }
+}
+
+// each pass may be read back once it has been synced, to check that it was written correctly
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum EraseVerifyMode {
+    VerifyPasses,
+    SkipVerification,
+}
+
 #[derive(PartialEq, Eq, Clone, Copy)]
 pub enum HashMode {
     CalculateHash,
@@ -67,91 +122,51 @@ pub enum PasswordMode {
     NormalKeySourcePriority,
 }
 
-pub enum OutputFile {
-    Some(File),
-    None,
-}
-
-impl OutputFile {
-    pub fn write_all(&mut self, buf: &[u8]) -> Result<()> {
-        match self {
-            OutputFile::Some(file) => file.write_all(buf),
-            OutputFile::None => Ok(()),
-        }
-    }
-    pub fn flush(&mut self) -> Result<()> {
-        match self {
-            OutputFile::Some(file) => file.flush(),
-            OutputFile::None => Ok(()),
-        }
-    }
-}
-
-#[derive(Copy, Clone)]
-pub enum CipherType {
-    AesGcm,
-    XChaCha20Poly1305,
-}
-
-impl std::fmt::Display for CipherType {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        match *self {
-            CipherType::AesGcm => write!(f, "AES-256-GCM"),
-            CipherType::XChaCha20Poly1305 => write!(f, "XChaCha20-Poly1305"),
-        }
-    }
+// stream decryption normally writes to a temporary file, which is only renamed to the output once it has been verified
+// the user may opt out of this, but then a failed decryption will leave unverified plaintext behind
+#[derive(PartialEq, Eq, Copy, Clone)]
+pub enum VerifyMode {
+    VerifyBeforeWriting,
+    WriteUnverified,
 }
 
-pub enum EncryptStreamCiphers {
-    AesGcm(Box<EncryptorLE31<Aes256Gcm>>),
-    XChaCha(Box<EncryptorLE31<XChaCha20Poly1305>>),
+// the output of encryption may be read back and authenticated before anything else happens (this is the default with --erase)
+// so if it was written incorrectly, the input is left intact rather than erased
+#[derive(PartialEq, Eq, Copy, Clone)]
+pub enum OutputVerifyMode {
+    VerifyOutput,
+    SkipVerification,
 }
 
-pub enum DecryptStreamCiphers {
-    AesGcm(Box<DecryptorLE31<Aes256Gcm>>),
-    XChaCha(Box<DecryptorLE31<XChaCha20Poly1305>>),
+// stream mode may encrypt/decrypt several blocks at once, each on its own thread
+// the output is the same either way
+#[derive(PartialEq, Eq, Copy, Clone)]
+pub enum ThreadMode {
+    SingleThreaded,
+    MultiThreaded(usize),
 }
 
-impl EncryptStreamCiphers {
-    pub fn encrypt_next<'msg, 'aad>(
-        &mut self,
-        payload: impl Into<Payload<'msg, 'aad>>,
-    ) -> aead::Result<Vec<u8>> {
-        match self {
-            EncryptStreamCiphers::AesGcm(s) => s.encrypt_next(payload),
-            EncryptStreamCiphers::XChaCha(s) => s.encrypt_next(payload),
-        }
-    }
-
-    pub fn encrypt_last<'msg, 'aad>(
-        self,
-        payload: impl Into<Payload<'msg, 'aad>>,
-    ) -> aead::Result<Vec<u8>> {
-        match self {
-            EncryptStreamCiphers::AesGcm(s) => s.encrypt_last(payload),
-            EncryptStreamCiphers::XChaCha(s) => s.encrypt_last(payload),
-        }
-    }
+pub enum OutputFile {
+    Some(File),
+    Stdout(Stdout),
+    None,
 }
 
-impl DecryptStreamCiphers {
-    pub fn decrypt_next<'msg, 'aad>(
-        &mut self,
-        payload: impl Into<Payload<'msg, 'aad>>,
-    ) -> aead::Result<Vec<u8>> {
+// when benchmarking, any data that's written is just discarded
+impl Write for OutputFile {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
         match self {
-            DecryptStreamCiphers::AesGcm(s) => s.decrypt_next(payload),
-            DecryptStreamCiphers::XChaCha(s) => s.decrypt_next(payload),
+            OutputFile::Some(file) => file.write(buf),
+            OutputFile::Stdout(stdout) => stdout.write(buf),
+            OutputFile::None => Ok(buf.len()),
         }
     }
 
-    pub fn decrypt_last<'msg, 'aad>(
-        self,
-        payload: impl Into<Payload<'msg, 'aad>>,
-    ) -> aead::Result<Vec<u8>> {
+    fn flush(&mut self) -> Result<()> {
         match self {
-            DecryptStreamCiphers::AesGcm(s) => s.decrypt_last(payload),
-            DecryptStreamCiphers::XChaCha(s) => s.decrypt_last(payload),
+            OutputFile::Some(file) => file.flush(),
+            OutputFile::Stdout(stdout) => stdout.flush(),
+            OutputFile::None => Ok(()),
         }
     }
 }
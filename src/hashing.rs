@@ -1,16 +1,100 @@
 use anyhow::{Ok, Result};
 
-use crate::global::{BLOCK_SIZE, SALT_LEN};
+use crate::global::HashMode;
+use crate::report;
+use dexios::BLOCK_SIZE;
 
 use anyhow::Context;
-use std::io::Read;
+use std::io::{Read, Write};
 
-// this simply just hashes the provided salt, nonce and data
+// this wraps a reader, and hashes everything that is read through it (if hashing is enabled)
+// this allows the library to handle the encrypted data, while we still get the hash of the file
+pub struct HashReader<R: Read> {
+    inner: R,
+    hasher: Option<blake3::Hasher>,
+}
+
+impl<R: Read> HashReader<R> {
+    pub fn new(inner: R, hash_mode: HashMode) -> Self {
+        let hasher = match hash_mode {
+            HashMode::CalculateHash => Some(blake3::Hasher::new()),
+            HashMode::NoHash => None,
+        };
+        HashReader { inner, hasher }
+    }
+
+    // this returns the hash in hex format, if hashing was enabled
+    pub fn finalize(&self) -> Option<String> {
+        self.hasher
+            .as_ref()
+            .map(|hasher| hasher.finalize().to_hex().to_string())
+    }
+}
+
+impl<R: Read> Read for HashReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read_count = self.inner.read(buf)?;
+        if let Some(hasher) = self.hasher.as_mut() {
+            hasher.update(&buf[..read_count]);
+        }
+        std::io::Result::Ok(read_count)
+    }
+}
+
+// this wraps a writer, and hashes everything that is written through it (if hashing is enabled)
+// it also counts the bytes that are written, for the json report
+pub struct HashWriter<W: Write> {
+    inner: W,
+    hasher: Option<blake3::Hasher>,
+    bytes: u64,
+}
+
+impl<W: Write> HashWriter<W> {
+    pub fn new(inner: W, hash_mode: HashMode) -> Self {
+        let hasher = match hash_mode {
+            HashMode::CalculateHash => Some(blake3::Hasher::new()),
+            HashMode::NoHash => None,
+        };
+        HashWriter {
+            inner,
+            hasher,
+            bytes: 0,
+        }
+    }
+
+    // this returns the number of bytes that have been written so far
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    // this returns the hash in hex format, if hashing was enabled
+    pub fn finalize(&self) -> Option<String> {
+        self.hasher
+            .as_ref()
+            .map(|hasher| hasher.finalize().to_hex().to_string())
+    }
+}
+
+impl<W: Write> Write for HashWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let write_count = self.inner.write(buf)?;
+        self.bytes += write_count as u64;
+        if let Some(hasher) = self.hasher.as_mut() {
+            hasher.update(&buf[..write_count]);
+        }
+        std::io::Result::Ok(write_count)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// this simply just hashes the provided header and data
 // it returns a blake3 hash in hex format
-pub fn hash_data_blake3(salt: &[u8; SALT_LEN], nonce: &[u8], data: &[u8]) -> Result<String> {
+pub fn hash_data_blake3(header: &[u8], data: &[u8]) -> Result<String> {
     let mut hasher = blake3::Hasher::new();
-    hasher.update(salt);
-    hasher.update(nonce);
+    hasher.update(header);
     hasher.update(data);
     let hash = hasher.finalize().to_hex().to_string();
     Ok(hash)
@@ -20,7 +104,7 @@ pub fn hash_stream(input: &str) -> Result<()> {
     let mut input_file =
         std::fs::File::open(input).with_context(|| format!("Unable to open file: {}", input))?;
 
-    println!("Hashing {} in stream mode (this may take a while)", input);
+    eprintln!("Hashing {} in stream mode (this may take a while)", input);
     let mut hasher = blake3::Hasher::new();
 
     let mut buffer = [0u8; BLOCK_SIZE];
@@ -37,7 +121,9 @@ pub fn hash_stream(input: &str) -> Result<()> {
 
     let hash = hasher.finalize().to_hex().to_string();
 
-    println!("The hash for {} is: {}", input, hash);
+    report::record("input", input);
+    report::record("hash", hash.as_str());
+    report::print_result(&format!("The hash for {} is: {}", input, hash));
 
     Ok(())
 }
@@ -51,10 +137,12 @@ pub fn hash_memory(input: &str) -> Result<()> {
         .read_to_end(&mut data)
         .with_context(|| format!("Unable to read data from file: {}", input))?;
 
-    println!("Hashing {} in memory mode (this may take a while)", input);
+    eprintln!("Hashing {} in memory mode (this may take a while)", input);
     let hash = blake3::hash(&data).to_hex().to_string();
 
-    println!("The hash for {} is: {}", input, hash);
+    report::record("input", input);
+    report::record("hash", hash.as_str());
+    report::print_result(&format!("The hash for {} is: {}", input, hash));
 
     Ok(())
 }
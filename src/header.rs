@@ -0,0 +1,1035 @@
+use crate::cipher::{nonce_len, CipherMode, CipherType, WRAPPED_KEY_LEN};
+use crate::error::{Error, Result};
+use crate::kdf::{within_total_limits, KdfParams};
+use crate::recipient::{
+    encode_hex, HybridWrappedKey, WrappedKey, FINGERPRINT_LEN, ML_KEM_CIPHERTEXT_LEN,
+};
+use crate::{BLOCK_SIZE, MAX_BLOCK_SIZE, MIN_BLOCK_SIZE, SALT_LEN};
+use std::io::{Read, Write};
+
+// this file handles the header that is written to the start of every encrypted file
+// the header makes files self-describing, so decryption no longer relies on the user passing the correct cipher
+// all integers are stored as little-endian
+//
+// V2 headers are identical to V1, but the entire header is authenticated as AEAD associated data
+// this means any modification of the header will cause decryption to fail
+//
+// V3 headers also store the argon2id cost parameters, V1/V2 headers always used the defaults
+//
+// the layout of a V3 header is as follows (V1/V2 headers are the same, minus the KDF parameters):
+// 4 bytes  - magic bytes ("DEXI")
+// 2 bytes  - header version
+// 1 byte   - cipher type
+// 1 byte   - cipher mode (memory or stream)
+// 1 byte   - key derivation function
+// 4 bytes  - argon2id memory cost (in KiB)
+// 4 bytes  - argon2id iterations
+// 4 bytes  - argon2id parallelism
+// 16 bytes - salt
+// N bytes  - nonce (the length depends on the cipher and the mode)
+//
+// V4 headers encrypt the data with a random master key, and store wrapped copies of it in key slots
+// only the part of the header before the key slots is authenticated, so the key slots may be changed without re-encrypting the data
+// the trade-off is that key slots can be added to a file by anyone, so the argon2id work that its password key slots need is limited when they're read (see `kdf::MAX_TOTAL_COST`)
+// the layout of a V4 header is as follows:
+// 4 bytes  - magic bytes ("DEXI")
+// 2 bytes  - header version
+// 1 byte   - cipher type
+// 1 byte   - cipher mode (memory or stream)
+// N bytes  - nonce (the length depends on the cipher and the mode)
+// 1 byte   - number of key slots
+// and then for each key slot:
+// 1 byte   - key slot type
+// 2 bytes  - length of the key slot
+// N bytes  - the key slot itself
+//
+// X25519 key slots (type 1) contain an 8 byte recipient fingerprint, the 32 byte ephemeral public key and the 48 byte wrapped master key
+// password key slots (type 2) contain the 1 byte KDF type, the 12 bytes of argon2id parameters, a 16 byte salt and the 48 byte wrapped master key
+// password subkey key slots (type 3) are the same as password key slots, but with a second 16 byte salt just before the wrapped master key
+// the argon2id hash of the key is expanded with HKDF-SHA256 and this second salt, and the result wraps the master key
+// this allows many files to share a single argon2id hash (e.g. in batch mode), while each of them still has its own wrapping key
+// hybrid key slots (type 4) are the same as X25519 key slots, but with the 1088 byte ML-KEM-768 ciphertext just before the wrapped master key
+//
+// V5 headers are identical to V4, but they also store the size of each block (in bytes) as 4 bytes, just after the cipher mode
+// the block size is only used in stream mode, and it's authenticated along with the rest of the header
+// files with a V4 header (or older) always used a block size of BLOCK_SIZE
+//
+// V6 headers are identical to V5, but they also store what the encrypted data contains as 1 byte, just after the block size
+// this is only used for data that needs to be handled differently once it's decrypted (e.g. tar archives created by `pack`), everything else still uses a V5 header
+// so files are only unreadable by older versions of dexios if they wouldn't be able to handle the contents anyway
+pub const MAGIC: [u8; 4] = *b"DEXI";
+pub const HEADER_PREFIX_LEN: usize = 8; // magic + version + cipher + mode
+
+const X25519_KEYSLOT_LEN: usize = FINGERPRINT_LEN + 32 + WRAPPED_KEY_LEN;
+const PASSWORD_KEYSLOT_LEN: usize = 1 + 12 + SALT_LEN + WRAPPED_KEY_LEN;
+const PASSWORD_SUBKEY_KEYSLOT_LEN: usize = PASSWORD_KEYSLOT_LEN + SALT_LEN;
+const HYBRID_KEYSLOT_LEN: usize = FINGERPRINT_LEN + 32 + ML_KEM_CIPHERTEXT_LEN + WRAPPED_KEY_LEN;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum HeaderVersion {
+    Legacy, // files created before headers were introduced, these are just the salt and nonce
+    V1,
+    V2,
+    V3,
+    V4,
+    V5,
+    V6,
+}
+
+// this describes what the encrypted data contains, so it can be handled correctly once it's decrypted
+// it's only recorded in V6 headers - it's always unspecified for older headers
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ContentType {
+    Unspecified,
+    TarArchive, // a bzip2-compressed tar archive
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum KdfType {
+    Argon2id,
+}
+
+// this is whether to read a header from the start of a file, or to treat it as a legacy headerless file
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub enum HeaderMode {
+    ReadHeader,
+    LegacyHeaderless,
+}
+
+// this is a single wrapped copy of the master key
+// password (and password subkey) key slots are unlocked by a password or keyfile, and X25519 (and hybrid) key slots are unlocked by an identity
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Keyslot {
+    X25519(WrappedKey),
+    Hybrid(HybridWrappedKey),
+    Password {
+        kdf: KdfType,
+        kdf_params: KdfParams,
+        salt: [u8; SALT_LEN],
+        wrapped_key: [u8; WRAPPED_KEY_LEN],
+    },
+    PasswordSubkey {
+        kdf: KdfType,
+        kdf_params: KdfParams,
+        salt: [u8; SALT_LEN],
+        subkey_salt: [u8; SALT_LEN],
+        wrapped_key: [u8; WRAPPED_KEY_LEN],
+    },
+}
+
+// this describes how the key that encrypts the data is obtained
+// V3 headers (and below) derive it directly from the user's key, V4 headers wrap a random master key within key slots
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum KeyMaterial {
+    Derived {
+        kdf: KdfType,
+        kdf_params: KdfParams,
+        salt: [u8; SALT_LEN],
+    },
+    Wrapped(Vec<Keyslot>),
+}
+
+impl Keyslot {
+    // this returns the argon2id parameters of a password (or password subkey) key slot
+    pub fn kdf_params(&self) -> Option<KdfParams> {
+        match self {
+            Keyslot::Password { kdf_params, .. } | Keyslot::PasswordSubkey { kdf_params, .. } => {
+                Some(*kdf_params)
+            }
+            Keyslot::X25519(_) | Keyslot::Hybrid(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for HeaderVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            HeaderVersion::Legacy => write!(f, "legacy (headerless)"),
+            HeaderVersion::V1 => write!(f, "V1"),
+            HeaderVersion::V2 => write!(f, "V2"),
+            HeaderVersion::V3 => write!(f, "V3"),
+            HeaderVersion::V4 => write!(f, "V4"),
+            HeaderVersion::V5 => write!(f, "V5"),
+            HeaderVersion::V6 => write!(f, "V6"),
+        }
+    }
+}
+
+impl std::fmt::Display for ContentType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            ContentType::Unspecified => write!(f, "unspecified"),
+            ContentType::TarArchive => write!(f, "tar archive (bzip2)"),
+        }
+    }
+}
+
+impl std::fmt::Display for KdfType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            KdfType::Argon2id => write!(f, "Argon2id"),
+        }
+    }
+}
+
+impl std::fmt::Display for Keyslot {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Keyslot::X25519(wrapped) => write!(
+                f,
+                "X25519 recipient (fingerprint: {})",
+                encode_hex(&wrapped.fingerprint)
+            ),
+            Keyslot::Hybrid(wrapped) => write!(
+                f,
+                "X25519 + ML-KEM-768 recipient (fingerprint: {})",
+                encode_hex(&wrapped.fingerprint)
+            ),
+            Keyslot::Password {
+                kdf, kdf_params, ..
+            } => write!(f, "password/keyfile ({} with {})", kdf, kdf_params),
+            Keyslot::PasswordSubkey {
+                kdf, kdf_params, ..
+            } => write!(
+                f,
+                "password/keyfile ({} with {}, and a per-file subkey)",
+                kdf, kdf_params
+            ),
+        }
+    }
+}
+
+impl std::fmt::Display for KeyMaterial {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            KeyMaterial::Derived {
+                kdf, kdf_params, ..
+            } => write!(f, "{} with {}", kdf, kdf_params),
+            KeyMaterial::Wrapped(keyslots) => write!(f, "{} key slot(s)", keyslots.len()),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Header {
+    pub version: HeaderVersion,
+    pub cipher_type: CipherType,
+    pub cipher_mode: CipherMode,
+    pub key_material: KeyMaterial,
+    pub nonce: Vec<u8>,
+    pub block_size: usize,
+    pub content: ContentType,
+}
+
+impl Header {
+    // this serializes the header into bytes, ready to be written to the start of a file
+    // legacy headers are just the salt and the nonce
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = self.serialize_authenticated();
+
+        if let KeyMaterial::Wrapped(keyslots) = &self.key_material {
+            bytes.push(keyslots.len() as u8);
+
+            for keyslot in keyslots {
+                match keyslot {
+                    Keyslot::X25519(wrapped) => {
+                        bytes.push(1);
+                        bytes.extend_from_slice(&(X25519_KEYSLOT_LEN as u16).to_le_bytes());
+                        bytes.extend_from_slice(&wrapped.fingerprint);
+                        bytes.extend_from_slice(&wrapped.ephemeral_public);
+                        bytes.extend_from_slice(&wrapped.wrapped_key);
+                    }
+                    Keyslot::Hybrid(wrapped) => {
+                        bytes.push(4);
+                        bytes.extend_from_slice(&(HYBRID_KEYSLOT_LEN as u16).to_le_bytes());
+                        bytes.extend_from_slice(&wrapped.fingerprint);
+                        bytes.extend_from_slice(&wrapped.ephemeral_public);
+                        bytes.extend_from_slice(&wrapped.ciphertext);
+                        bytes.extend_from_slice(&wrapped.wrapped_key);
+                    }
+                    Keyslot::Password {
+                        kdf,
+                        kdf_params,
+                        salt,
+                        wrapped_key,
+                    } => {
+                        bytes.push(2);
+                        bytes.extend_from_slice(&(PASSWORD_KEYSLOT_LEN as u16).to_le_bytes());
+                        bytes.push(kdf_tag(*kdf));
+                        bytes.extend_from_slice(&kdf_params_to_bytes(kdf_params));
+                        bytes.extend_from_slice(salt);
+                        bytes.extend_from_slice(wrapped_key);
+                    }
+                    Keyslot::PasswordSubkey {
+                        kdf,
+                        kdf_params,
+                        salt,
+                        subkey_salt,
+                        wrapped_key,
+                    } => {
+                        bytes.push(3);
+                        bytes
+                            .extend_from_slice(&(PASSWORD_SUBKEY_KEYSLOT_LEN as u16).to_le_bytes());
+                        bytes.push(kdf_tag(*kdf));
+                        bytes.extend_from_slice(&kdf_params_to_bytes(kdf_params));
+                        bytes.extend_from_slice(salt);
+                        bytes.extend_from_slice(subkey_salt);
+                        bytes.extend_from_slice(wrapped_key);
+                    }
+                }
+            }
+        }
+
+        bytes
+    }
+
+    // this serializes everything that is authenticated - for V4 headers (and above) this excludes the key slots
+    fn serialize_authenticated(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+
+        if self.version != HeaderVersion::Legacy {
+            let version_tag: u16 = match self.version {
+                HeaderVersion::Legacy | HeaderVersion::V1 => 1,
+                HeaderVersion::V2 => 2,
+                HeaderVersion::V3 => 3,
+                HeaderVersion::V4 => 4,
+                HeaderVersion::V5 => 5,
+                HeaderVersion::V6 => 6,
+            };
+            let cipher_tag: u8 = match self.cipher_type {
+                CipherType::XChaCha20Poly1305 => 1,
+                CipherType::AesGcm => 2,
+            };
+            let mode_tag: u8 = match self.cipher_mode {
+                CipherMode::MemoryMode => 1,
+                CipherMode::StreamMode => 2,
+            };
+
+            bytes.extend_from_slice(&MAGIC);
+            bytes.extend_from_slice(&version_tag.to_le_bytes());
+            bytes.push(cipher_tag);
+            bytes.push(mode_tag);
+        }
+
+        if self.version == HeaderVersion::V5 || self.version == HeaderVersion::V6 {
+            bytes.extend_from_slice(&(self.block_size as u32).to_le_bytes());
+        }
+
+        if self.version == HeaderVersion::V6 {
+            bytes.push(content_tag(self.content));
+        }
+
+        if let KeyMaterial::Derived {
+            kdf,
+            kdf_params,
+            salt,
+        } = &self.key_material
+        {
+            if self.version != HeaderVersion::Legacy {
+                bytes.push(kdf_tag(*kdf));
+            }
+
+            if self.version == HeaderVersion::V3 {
+                bytes.extend_from_slice(&kdf_params_to_bytes(kdf_params));
+            }
+
+            bytes.extend_from_slice(salt);
+        }
+
+        bytes.extend_from_slice(&self.nonce);
+        bytes
+    }
+
+    // this returns the associated data that should be authenticated alongside the encrypted data
+    // legacy and V1 headers were not authenticated, so they return nothing
+    // V4 headers (and above) don't authenticate the key slots, as they're protected by the master key instead
+    pub fn aad(&self) -> Vec<u8> {
+        match self.version {
+            HeaderVersion::Legacy | HeaderVersion::V1 => Vec::new(),
+            HeaderVersion::V2
+            | HeaderVersion::V3
+            | HeaderVersion::V4
+            | HeaderVersion::V5
+            | HeaderVersion::V6 => self.serialize_authenticated(),
+        }
+    }
+
+    // this calculates the length of the decrypted data, from the length of the encrypted data that follows the header
+    // memory mode has a single AEAD tag, and stream mode has one for each block (the final block is always present, even if it's empty)
+    // it returns None if the encrypted data is the wrong length to be valid (e.g. it has been truncated)
+    pub fn data_len(&self, encrypted_len: u64) -> Option<u64> {
+        match self.cipher_mode {
+            CipherMode::MemoryMode => encrypted_len.checked_sub(16),
+            CipherMode::StreamMode => {
+                let encrypted_block_size = self.block_size as u64 + 16;
+                if encrypted_len % encrypted_block_size < 16 {
+                    return None;
+                }
+
+                let blocks = encrypted_len / encrypted_block_size + 1;
+                Some(encrypted_len - blocks * 16)
+            }
+        }
+    }
+
+    // this writes the serialized header to the provided writer
+    pub fn write(&self, writer: &mut impl Write) -> Result<()> {
+        writer.write_all(&self.serialize())?;
+        Ok(())
+    }
+
+    // this reads a header from the start of the reader
+    // it validates the magic bytes and the version, and errors out if they're not recognised
+    // the reader is left positioned at the start of the encrypted data
+    pub fn deserialize(reader: &mut impl Read) -> Result<Self> {
+        let mut prefix = [0u8; HEADER_PREFIX_LEN];
+        read_header_bytes(reader, &mut prefix)?;
+
+        if prefix[..4] != MAGIC {
+            return Err(Error::NoHeader);
+        }
+
+        let version = match u16::from_le_bytes([prefix[4], prefix[5]]) {
+            1 => HeaderVersion::V1,
+            2 => HeaderVersion::V2,
+            3 => HeaderVersion::V3,
+            4 => HeaderVersion::V4,
+            5 => HeaderVersion::V5,
+            6 => HeaderVersion::V6,
+            v => return Err(Error::UnsupportedVersion(v)),
+        };
+
+        let cipher_type = match prefix[6] {
+            1 => CipherType::XChaCha20Poly1305,
+            2 => CipherType::AesGcm,
+            _ => return Err(Error::InvalidHeader("unknown cipher type")),
+        };
+
+        let cipher_mode = match prefix[7] {
+            1 => CipherMode::MemoryMode,
+            2 => CipherMode::StreamMode,
+            _ => return Err(Error::InvalidHeader("unknown cipher mode")),
+        };
+
+        let block_size = if version == HeaderVersion::V5 || version == HeaderVersion::V6 {
+            let mut block_size = [0u8; 4];
+            read_header_bytes(reader, &mut block_size)?;
+            let block_size = u32::from_le_bytes(block_size) as usize;
+
+            // this is checked here, as it decides how much memory is allocated when decrypting
+            if !(MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&block_size) {
+                return Err(Error::InvalidHeader("the block size is out of range"));
+            }
+
+            block_size
+        } else {
+            BLOCK_SIZE
+        };
+
+        let content = if version == HeaderVersion::V6 {
+            let mut tag = [0u8; 1];
+            read_header_bytes(reader, &mut tag)?;
+            content_from_tag(tag[0])?
+        } else {
+            ContentType::Unspecified
+        };
+
+        if version == HeaderVersion::V4
+            || version == HeaderVersion::V5
+            || version == HeaderVersion::V6
+        {
+            let mut nonce = vec![0u8; nonce_len(cipher_type, cipher_mode)];
+            read_header_bytes(reader, &mut nonce)?;
+
+            let keyslots = read_keyslots(reader)?;
+
+            return Ok(Header {
+                version,
+                cipher_type,
+                cipher_mode,
+                key_material: KeyMaterial::Wrapped(keyslots),
+                nonce,
+                block_size,
+                content,
+            });
+        }
+
+        let mut kdf_tag = [0u8; 1];
+        read_header_bytes(reader, &mut kdf_tag)?;
+        let kdf = kdf_from_tag(kdf_tag[0])?;
+
+        let kdf_params = if version == HeaderVersion::V3 {
+            let mut params = [0u8; 12];
+            read_header_bytes(reader, &mut params)?;
+            kdf_params_from_bytes(&params)?
+        } else {
+            KdfParams::default()
+        };
+
+        let mut salt = [0u8; SALT_LEN];
+        read_header_bytes(reader, &mut salt)?;
+
+        let mut nonce = vec![0u8; nonce_len(cipher_type, cipher_mode)];
+        read_header_bytes(reader, &mut nonce)?;
+
+        Ok(Header {
+            version,
+            cipher_type,
+            cipher_mode,
+            key_material: KeyMaterial::Derived {
+                kdf,
+                kdf_params,
+                salt,
+            },
+            nonce,
+            block_size,
+            content,
+        })
+    }
+
+    // this reads the salt and nonce from a file created before headers were introduced
+    // these files don't contain any information about how they were encrypted, so the user has to provide it
+    pub fn deserialize_legacy(
+        reader: &mut impl Read,
+        cipher_type: CipherType,
+        cipher_mode: CipherMode,
+    ) -> Result<Self> {
+        let mut salt = [0u8; SALT_LEN];
+        read_header_bytes(reader, &mut salt)?;
+
+        let mut nonce = vec![0u8; nonce_len(cipher_type, cipher_mode)];
+        read_header_bytes(reader, &mut nonce)?;
+
+        Ok(Header {
+            version: HeaderVersion::Legacy,
+            cipher_type,
+            cipher_mode,
+            key_material: KeyMaterial::Derived {
+                kdf: KdfType::Argon2id,
+                kdf_params: KdfParams::default(),
+                salt,
+            },
+            nonce,
+            block_size: BLOCK_SIZE,
+            content: ContentType::Unspecified,
+        })
+    }
+}
+
+// this reads the header in the way the user has requested
+// legacy files don't record the cipher or mode, so these are only used for headerless files
+pub fn read_header(
+    reader: &mut impl Read,
+    header_mode: HeaderMode,
+    cipher_type: CipherType,
+    cipher_mode: CipherMode,
+) -> Result<Header> {
+    match header_mode {
+        HeaderMode::ReadHeader => Header::deserialize(reader),
+        HeaderMode::LegacyHeaderless => {
+            Header::deserialize_legacy(reader, cipher_type, cipher_mode)
+        }
+    }
+}
+
+// this reads the key slots from a V4/V5 header
+fn read_keyslots(reader: &mut impl Read) -> Result<Vec<Keyslot>> {
+    let mut count = [0u8; 1];
+    read_header_bytes(reader, &mut count)?;
+
+    let mut keyslots = Vec::with_capacity(count[0].into());
+
+    for _ in 0..count[0] {
+        let mut keyslot_prefix = [0u8; 3];
+        read_header_bytes(reader, &mut keyslot_prefix)?;
+        let keyslot_len = u16::from_le_bytes([keyslot_prefix[1], keyslot_prefix[2]]) as usize;
+
+        let mut body = vec![0u8; keyslot_len];
+        read_header_bytes(reader, &mut body)?;
+
+        let keyslot = match keyslot_prefix[0] {
+            1 if keyslot_len == X25519_KEYSLOT_LEN => {
+                let mut wrapped = WrappedKey {
+                    fingerprint: [0u8; FINGERPRINT_LEN],
+                    ephemeral_public: [0u8; 32],
+                    wrapped_key: [0u8; WRAPPED_KEY_LEN],
+                };
+                let (fingerprint, rest) = body.split_at(FINGERPRINT_LEN);
+                let (ephemeral_public, wrapped_key) = rest.split_at(32);
+                wrapped.fingerprint.copy_from_slice(fingerprint);
+                wrapped.ephemeral_public.copy_from_slice(ephemeral_public);
+                wrapped.wrapped_key.copy_from_slice(wrapped_key);
+                Keyslot::X25519(wrapped)
+            }
+            2 if keyslot_len == PASSWORD_KEYSLOT_LEN => {
+                let mut params = [0u8; 12];
+                let mut salt = [0u8; SALT_LEN];
+                let mut wrapped_key = [0u8; WRAPPED_KEY_LEN];
+                params.copy_from_slice(&body[1..13]);
+                salt.copy_from_slice(&body[13..13 + SALT_LEN]);
+                wrapped_key.copy_from_slice(&body[13 + SALT_LEN..]);
+
+                Keyslot::Password {
+                    kdf: kdf_from_tag(body[0])?,
+                    kdf_params: kdf_params_from_bytes(&params)?,
+                    salt,
+                    wrapped_key,
+                }
+            }
+            3 if keyslot_len == PASSWORD_SUBKEY_KEYSLOT_LEN => {
+                let mut params = [0u8; 12];
+                let mut salt = [0u8; SALT_LEN];
+                let mut subkey_salt = [0u8; SALT_LEN];
+                let mut wrapped_key = [0u8; WRAPPED_KEY_LEN];
+                params.copy_from_slice(&body[1..13]);
+                salt.copy_from_slice(&body[13..13 + SALT_LEN]);
+                subkey_salt.copy_from_slice(&body[13 + SALT_LEN..13 + SALT_LEN * 2]);
+                wrapped_key.copy_from_slice(&body[13 + SALT_LEN * 2..]);
+
+                Keyslot::PasswordSubkey {
+                    kdf: kdf_from_tag(body[0])?,
+                    kdf_params: kdf_params_from_bytes(&params)?,
+                    salt,
+                    subkey_salt,
+                    wrapped_key,
+                }
+            }
+            4 if keyslot_len == HYBRID_KEYSLOT_LEN => {
+                let mut wrapped = HybridWrappedKey {
+                    fingerprint: [0u8; FINGERPRINT_LEN],
+                    ephemeral_public: [0u8; 32],
+                    ciphertext: Vec::new(),
+                    wrapped_key: [0u8; WRAPPED_KEY_LEN],
+                };
+                let (fingerprint, rest) = body.split_at(FINGERPRINT_LEN);
+                let (ephemeral_public, rest) = rest.split_at(32);
+                let (ciphertext, wrapped_key) = rest.split_at(ML_KEM_CIPHERTEXT_LEN);
+                wrapped.fingerprint.copy_from_slice(fingerprint);
+                wrapped.ephemeral_public.copy_from_slice(ephemeral_public);
+                wrapped.ciphertext = ciphertext.to_vec();
+                wrapped.wrapped_key.copy_from_slice(wrapped_key);
+                Keyslot::Hybrid(wrapped)
+            }
+            1 => {
+                return Err(Error::InvalidHeader(
+                    "an X25519 key slot is the wrong length",
+                ))
+            }
+            4 => {
+                return Err(Error::InvalidHeader(
+                    "a hybrid key slot is the wrong length",
+                ))
+            }
+            2 | 3 => {
+                return Err(Error::InvalidHeader(
+                    "a password key slot is the wrong length",
+                ))
+            }
+            _ => return Err(Error::InvalidHeader("unknown key slot type")),
+        };
+
+        keyslots.push(keyslot);
+    }
+
+    if !within_total_limits(keyslots.iter().filter_map(Keyslot::kdf_params)) {
+        return Err(Error::InvalidHeader(
+            "the password key slots need too much argon2id work to try",
+        ));
+    }
+
+    Ok(keyslots)
+}
+
+fn kdf_tag(kdf: KdfType) -> u8 {
+    match kdf {
+        KdfType::Argon2id => 1,
+    }
+}
+
+fn kdf_from_tag(tag: u8) -> Result<KdfType> {
+    match tag {
+        1 => Ok(KdfType::Argon2id),
+        _ => Err(Error::InvalidHeader("unknown key derivation function")),
+    }
+}
+
+fn content_tag(content: ContentType) -> u8 {
+    match content {
+        ContentType::Unspecified => 0,
+        ContentType::TarArchive => 1,
+    }
+}
+
+fn content_from_tag(tag: u8) -> Result<ContentType> {
+    match tag {
+        0 => Ok(ContentType::Unspecified),
+        1 => Ok(ContentType::TarArchive),
+        _ => Err(Error::InvalidHeader("unknown content type")),
+    }
+}
+
+fn kdf_params_to_bytes(kdf_params: &KdfParams) -> [u8; 12] {
+    let mut bytes = [0u8; 12];
+    bytes[..4].copy_from_slice(&kdf_params.m_cost.to_le_bytes());
+    bytes[4..8].copy_from_slice(&kdf_params.t_cost.to_le_bytes());
+    bytes[8..].copy_from_slice(&kdf_params.p_cost.to_le_bytes());
+    bytes
+}
+
+// the parameters haven't been authenticated yet, so they're validated before they can be used (see KdfParams::validate())
+fn kdf_params_from_bytes(bytes: &[u8; 12]) -> Result<KdfParams> {
+    let kdf_params = KdfParams {
+        m_cost: u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        t_cost: u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+        p_cost: u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]),
+    };
+
+    kdf_params
+        .validate()
+        .map_err(|_| Error::InvalidHeader("the argon2id parameters are invalid or too large"))?;
+
+    Ok(kdf_params)
+}
+
+// this reads an exact amount of bytes for the header
+// running out of data means the file is too small to have been encrypted by dexios
+fn read_header_bytes(reader: &mut impl Read, buffer: &mut [u8]) -> Result<()> {
+    reader.read_exact(buffer).map_err(|err| {
+        if err.kind() == std::io::ErrorKind::UnexpectedEof {
+            Error::InvalidHeader("the file is too small to contain a header")
+        } else {
+            Error::Io(err)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kdf::{MAX_M_COST, MAX_PASSWORD_KEYSLOTS, MAX_T_COST};
+
+    fn keyslots() -> Vec<Keyslot> {
+        vec![
+            Keyslot::Password {
+                kdf: KdfType::Argon2id,
+                kdf_params: KdfParams {
+                    m_cost: 8192,
+                    t_cost: 2,
+                    p_cost: 1,
+                },
+                salt: [1u8; SALT_LEN],
+                wrapped_key: [2u8; WRAPPED_KEY_LEN],
+            },
+            Keyslot::PasswordSubkey {
+                kdf: KdfType::Argon2id,
+                kdf_params: KdfParams::default(),
+                salt: [3u8; SALT_LEN],
+                subkey_salt: [10u8; SALT_LEN],
+                wrapped_key: [11u8; WRAPPED_KEY_LEN],
+            },
+            Keyslot::X25519(WrappedKey {
+                fingerprint: [4u8; FINGERPRINT_LEN],
+                ephemeral_public: [5u8; 32],
+                wrapped_key: [6u8; WRAPPED_KEY_LEN],
+            }),
+            Keyslot::Hybrid(HybridWrappedKey {
+                fingerprint: [12u8; FINGERPRINT_LEN],
+                ephemeral_public: [13u8; 32],
+                ciphertext: vec![14u8; ML_KEM_CIPHERTEXT_LEN],
+                wrapped_key: [15u8; WRAPPED_KEY_LEN],
+            }),
+        ]
+    }
+
+    fn wrapped_header(version: HeaderVersion, content: ContentType) -> Header {
+        Header {
+            version,
+            cipher_type: CipherType::XChaCha20Poly1305,
+            cipher_mode: CipherMode::StreamMode,
+            key_material: KeyMaterial::Wrapped(keyslots()),
+            nonce: vec![7u8; nonce_len(CipherType::XChaCha20Poly1305, CipherMode::StreamMode)],
+            block_size: if version == HeaderVersion::V4 {
+                BLOCK_SIZE
+            } else {
+                MIN_BLOCK_SIZE
+            },
+            content,
+        }
+    }
+
+    fn derived_header(version: HeaderVersion) -> Header {
+        Header {
+            version,
+            cipher_type: CipherType::AesGcm,
+            cipher_mode: CipherMode::MemoryMode,
+            key_material: KeyMaterial::Derived {
+                kdf: KdfType::Argon2id,
+                kdf_params: if version == HeaderVersion::V3 {
+                    KdfParams {
+                        m_cost: 8192,
+                        t_cost: 2,
+                        p_cost: 1,
+                    }
+                } else {
+                    KdfParams::default()
+                },
+                salt: [8u8; SALT_LEN],
+            },
+            nonce: vec![9u8; nonce_len(CipherType::AesGcm, CipherMode::MemoryMode)],
+            block_size: BLOCK_SIZE,
+            content: ContentType::Unspecified,
+        }
+    }
+
+    // this deserializes the header, and checks that the reader is left at the start of the data
+    fn round_trip(header: &Header) -> Header {
+        let mut bytes = header.serialize();
+        bytes.extend_from_slice(b"data");
+
+        let mut reader = bytes.as_slice();
+        let read = Header::deserialize(&mut reader).unwrap();
+        assert_eq!(reader, b"data");
+        read
+    }
+
+    fn assert_same(read: &Header, header: &Header) {
+        assert_eq!(read.version, header.version);
+        assert_eq!(read.cipher_type, header.cipher_type);
+        assert_eq!(read.cipher_mode, header.cipher_mode);
+        assert_eq!(read.key_material, header.key_material);
+        assert_eq!(read.nonce, header.nonce);
+        assert_eq!(read.block_size, header.block_size);
+        assert_eq!(read.content, header.content);
+        assert_eq!(read.serialize(), header.serialize());
+    }
+
+    #[test]
+    fn wrapped_headers_round_trip() {
+        for version in [HeaderVersion::V4, HeaderVersion::V5] {
+            let header = wrapped_header(version, ContentType::Unspecified);
+            assert_same(&round_trip(&header), &header);
+        }
+
+        let header = wrapped_header(HeaderVersion::V6, ContentType::TarArchive);
+        assert_same(&round_trip(&header), &header);
+    }
+
+    #[test]
+    fn derived_headers_round_trip() {
+        for version in [HeaderVersion::V1, HeaderVersion::V2, HeaderVersion::V3] {
+            let header = derived_header(version);
+            assert_same(&round_trip(&header), &header);
+        }
+    }
+
+    #[test]
+    fn legacy_header_round_trips() {
+        let header = derived_header(HeaderVersion::Legacy);
+        let bytes = header.serialize();
+        assert_eq!(bytes.len(), SALT_LEN + header.nonce.len());
+
+        let read = Header::deserialize_legacy(
+            &mut bytes.as_slice(),
+            CipherType::AesGcm,
+            CipherMode::MemoryMode,
+        )
+        .unwrap();
+        assert_same(&read, &header);
+    }
+
+    #[test]
+    fn aad_covers_the_header_but_not_the_keyslots() {
+        assert!(derived_header(HeaderVersion::V1).aad().is_empty());
+        assert!(derived_header(HeaderVersion::Legacy).aad().is_empty());
+
+        let header = derived_header(HeaderVersion::V3);
+        assert_eq!(header.aad(), header.serialize());
+
+        let header = wrapped_header(HeaderVersion::V5, ContentType::Unspecified);
+        let mut changed = header.clone();
+        changed.key_material = KeyMaterial::Wrapped(keyslots()[..1].to_vec());
+        assert_eq!(header.aad(), changed.aad());
+        assert_ne!(header.serialize(), changed.serialize());
+
+        // the block size and content type are authenticated, so they can't be changed without the data failing to decrypt
+        changed.block_size *= 2;
+        assert_ne!(header.aad(), changed.aad());
+
+        let header = wrapped_header(HeaderVersion::V6, ContentType::TarArchive);
+        let mut changed = header.clone();
+        changed.content = ContentType::Unspecified;
+        assert_ne!(header.aad(), changed.aad());
+    }
+
+    #[test]
+    fn data_len_accounts_for_tags() {
+        let header = derived_header(HeaderVersion::V3);
+        assert_eq!(header.data_len(116), Some(100));
+        assert_eq!(header.data_len(10), None);
+
+        let header = wrapped_header(HeaderVersion::V5, ContentType::Unspecified);
+        let block = MIN_BLOCK_SIZE as u64;
+        assert_eq!(header.data_len(16), Some(0));
+        assert_eq!(header.data_len(block + 16 + 16), Some(block));
+        assert_eq!(
+            header.data_len(2 * (block + 16) + 116),
+            Some(2 * block + 100)
+        );
+        assert_eq!(header.data_len(block + 16 + 8), None);
+    }
+
+    #[test]
+    fn keyslots_show_their_fingerprint() {
+        let keyslots = keyslots();
+        assert_eq!(
+            keyslots[2].to_string(),
+            "X25519 recipient (fingerprint: 0404040404040404)"
+        );
+        assert_eq!(
+            keyslots[3].to_string(),
+            "X25519 + ML-KEM-768 recipient (fingerprint: 0c0c0c0c0c0c0c0c)"
+        );
+    }
+
+    #[test]
+    fn missing_magic_is_not_a_header() {
+        let mut bytes = derived_header(HeaderVersion::V3).serialize();
+        bytes[0] = b'X';
+
+        assert!(matches!(
+            Header::deserialize(&mut bytes.as_slice()),
+            Err(Error::NoHeader)
+        ));
+    }
+
+    #[test]
+    fn unknown_versions_are_unsupported() {
+        let mut bytes = derived_header(HeaderVersion::V3).serialize();
+        bytes[4..6].copy_from_slice(&7u16.to_le_bytes());
+
+        assert!(matches!(
+            Header::deserialize(&mut bytes.as_slice()),
+            Err(Error::UnsupportedVersion(7))
+        ));
+    }
+
+    #[test]
+    fn truncated_headers_are_rejected() {
+        for bytes in [
+            derived_header(HeaderVersion::V3).serialize(),
+            wrapped_header(HeaderVersion::V5, ContentType::Unspecified).serialize(),
+        ] {
+            for len in [0, 3, HEADER_PREFIX_LEN, bytes.len() - 1] {
+                assert!(matches!(
+                    Header::deserialize(&mut &bytes[..len]),
+                    Err(Error::InvalidHeader(_))
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn tampered_fields_are_rejected() {
+        let tamper = |bytes: &[u8], offset: usize, value: &[u8]| {
+            let mut bytes = bytes.to_vec();
+            bytes[offset..offset + value.len()].copy_from_slice(value);
+            Header::deserialize(&mut bytes.as_slice())
+        };
+
+        // the cipher, mode and kdf
+        let bytes = derived_header(HeaderVersion::V3).serialize();
+        for offset in [6, 7, 8] {
+            assert!(matches!(
+                tamper(&bytes, offset, &[9]),
+                Err(Error::InvalidHeader(_))
+            ));
+        }
+
+        // the block size and content type
+        let bytes = wrapped_header(HeaderVersion::V6, ContentType::TarArchive).serialize();
+        for block_size in [1024, u32::MAX] {
+            assert!(matches!(
+                tamper(&bytes, 8, &block_size.to_le_bytes()),
+                Err(Error::InvalidHeader(_))
+            ));
+        }
+        assert!(matches!(
+            tamper(&bytes, 12, &[9]),
+            Err(Error::InvalidHeader(_))
+        ));
+
+        // the first key slot's type, length, kdf and argon2id memory cost
+        let header = wrapped_header(HeaderVersion::V5, ContentType::Unspecified);
+        let bytes = header.serialize();
+        let keyslots_start = header.aad().len() + 1;
+        assert!(matches!(
+            tamper(&bytes, keyslots_start, &[9]),
+            Err(Error::InvalidHeader(_))
+        ));
+        assert!(matches!(
+            tamper(&bytes, keyslots_start + 1, &1u16.to_le_bytes()),
+            Err(Error::InvalidHeader(_))
+        ));
+        assert!(matches!(
+            tamper(&bytes, keyslots_start + 3, &[9]),
+            Err(Error::InvalidHeader(_))
+        ));
+        assert!(matches!(
+            tamper(&bytes, keyslots_start + 4, &u32::MAX.to_le_bytes()),
+            Err(Error::InvalidHeader(_))
+        ));
+    }
+
+    #[test]
+    fn expensive_password_keyslots_are_rejected() {
+        let read_with = |kdf_params: KdfParams, count: usize| {
+            let keyslot = Keyslot::Password {
+                kdf: KdfType::Argon2id,
+                kdf_params,
+                salt: [1u8; SALT_LEN],
+                wrapped_key: [2u8; WRAPPED_KEY_LEN],
+            };
+            let mut header = wrapped_header(HeaderVersion::V5, ContentType::Unspecified);
+            header.key_material = KeyMaterial::Wrapped(vec![keyslot; count]);
+            Header::deserialize(&mut header.serialize().as_slice())
+        };
+
+        // a single key slot with the highest parameters is allowed, but two of them aren't
+        let expensive = KdfParams {
+            m_cost: MAX_M_COST,
+            t_cost: MAX_T_COST,
+            p_cost: 1,
+        };
+        assert!(read_with(expensive, 1).is_ok());
+        assert!(matches!(
+            read_with(expensive, 2),
+            Err(Error::InvalidHeader(_))
+        ));
+
+        // and neither are too many cheap key slots
+        assert!(read_with(KdfParams::default(), MAX_PASSWORD_KEYSLOTS).is_ok());
+        assert!(matches!(
+            read_with(KdfParams::default(), MAX_PASSWORD_KEYSLOTS + 1),
+            Err(Error::InvalidHeader(_))
+        ));
+    }
+
+    #[test]
+    fn oversized_kdf_params_are_rejected() {
+        let mut header = derived_header(HeaderVersion::V3);
+        header.key_material = KeyMaterial::Derived {
+            kdf: KdfType::Argon2id,
+            kdf_params: KdfParams {
+                m_cost: 8192,
+                t_cost: u32::MAX,
+                p_cost: 1,
+            },
+            salt: [0u8; SALT_LEN],
+        };
+
+        assert!(matches!(
+            Header::deserialize(&mut header.serialize().as_slice()),
+            Err(Error::InvalidHeader(_))
+        ));
+    }
+}
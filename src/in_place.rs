@@ -0,0 +1,129 @@
+use crate::failure::Failure;
+use crate::file::temp_name;
+use crate::file::STDIO;
+use crate::global::EraseMode;
+use crate::global::Parameters;
+use crate::report;
+use anyhow::{Context, Result};
+use std::fs::OpenOptions;
+use std::path::Path;
+
+// this file handles `--in-place`, where a file is replaced with its encrypted/decrypted contents
+// the output is written to a temporary file next to the input, which is synced to the disk and then atomically renamed over the input
+// so if dexios crashes (or the power goes out) part-way through, the original file is still intact
+// this works the same in memory and stream mode, so files of any size can be replaced
+
+// this runs the operation with a temporary output, and then replaces the input with it
+// the original data is only erased (if the user requested it) once the replacement is in place
+pub fn replace(
+    input: &str,
+    mut params: Parameters,
+    operation: impl FnOnce(&str, &str, &Parameters) -> Result<()>,
+) -> Result<()> {
+    if input == STDIO {
+        return Err(
+            anyhow::anyhow!("stdin can't be replaced in-place").context(Failure::InvalidArguments)
+        );
+    }
+
+    // the input would be replaced with just the decrypted part of it
+    if params.range.is_some() {
+        return Err(anyhow::anyhow!("A range can't be decrypted in-place")
+            .context(Failure::InvalidArguments));
+    }
+
+    let metadata = std::fs::metadata(input)
+        .with_context(|| format!("Unable to get input file metadata: {}", input))?;
+    if !metadata.is_file() {
+        return Err(
+            anyhow::anyhow!("{} isn't a regular file, so it can't be replaced", input)
+                .context(Failure::InvalidArguments),
+        );
+    }
+
+    // the operation mustn't erase the input itself, as it's still the only copy until the rename
+    let erase = params.erase;
+    params.erase = EraseMode::IgnoreFile(0);
+
+    let temp_name = temp_name(input)?;
+    if let Err(err) = operation(input, &temp_name, &params) {
+        // the temporary file may contain unverified plaintext (with --unverified), so it's erased rather than just removed
+        if Path::new(&temp_name).exists() {
+            crate::erase::secure_erase(&temp_name, 1).ok();
+        }
+        return Err(err);
+    }
+
+    let result = persist(input, &temp_name, &metadata, erase);
+    if result.is_err() && Path::new(&temp_name).exists() {
+        std::fs::remove_file(&temp_name).ok();
+    }
+    result
+}
+
+// this syncs the temporary file, and renames it over the input
+// if the original data needs erasing, it's kept under another name first, as the rename alone would leave it on the disk
+fn persist(
+    input: &str,
+    temp_name: &str,
+    metadata: &std::fs::Metadata,
+    erase: EraseMode,
+) -> Result<()> {
+    let temp_file = OpenOptions::new()
+        .write(true)
+        .open(temp_name)
+        .with_context(|| format!("Unable to open temporary output file: {}", temp_name))?;
+    temp_file
+        .set_permissions(metadata.permissions())
+        .with_context(|| format!("Unable to set the permissions of {}", temp_name))?;
+    temp_file
+        .sync_all()
+        .with_context(|| format!("Unable to sync temporary output file: {}", temp_name))?;
+    drop(temp_file);
+
+    let original_name = if erase == EraseMode::IgnoreFile(0) {
+        None
+    } else {
+        let original_name = crate::file::temp_name(input)?;
+
+        // a hard link means the input is never missing, but some filesystems (e.g. FAT) don't support them
+        // in that case the input is moved instead, and both copies are on the disk until the rename below
+        if std::fs::hard_link(input, &original_name).is_err() {
+            std::fs::rename(input, &original_name)
+                .with_context(|| format!("Unable to rename {} to {}", input, original_name))?;
+        }
+        Some(original_name)
+    };
+
+    if let Err(err) = std::fs::rename(temp_name, input) {
+        // put the original back, so it isn't left behind under the other name
+        if let Some(original_name) = &original_name {
+            if Path::new(input).exists() {
+                std::fs::remove_file(original_name).ok();
+            } else {
+                std::fs::rename(original_name, input).ok();
+            }
+        }
+
+        return Err(err).with_context(|| format!("Unable to rename {} to {}", temp_name, input));
+    }
+
+    // the rename is only durable once the directory itself has been synced
+    // directories can't be opened like this on every platform, so this is best-effort
+    let parent = Path::new(input)
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    if let Ok(directory) = std::fs::File::open(parent) {
+        directory.sync_all().ok();
+    }
+
+    report::record("output", input);
+    eprintln!("Replaced {} in-place", input);
+
+    if let Some(original_name) = original_name {
+        crate::erase::secure_erase(&original_name, erase.get_passes())?;
+    }
+
+    Ok(())
+}
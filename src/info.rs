@@ -0,0 +1,249 @@
+use crate::report;
+use anyhow::{Context, Ok, Result};
+use dexios::header::KdfType;
+use dexios::recipient::encode_hex;
+use dexios::{
+    CipherMode, CipherType, ContentType, Header, HeaderVersion, KdfParams, KeyMaterial, Keyslot,
+    BLOCK_SIZE, SALT_LEN,
+};
+use serde_json::{json, Value};
+use std::fs::File;
+
+// this file handles the `info` subcommand, which shows an encrypted file's metadata
+// everything here is read from the header, so no key is required
+// legacy files don't have a header, so we can only guess how they were encrypted
+
+// this reads the header of the file, and prints its metadata
+// in json mode, the metadata becomes part of the json report instead
+pub fn show_info(input: &str) -> Result<()> {
+    let mut file =
+        File::open(input).with_context(|| format!("Unable to open input file: {}", input))?;
+    let file_len = file
+        .metadata()
+        .with_context(|| format!("Unable to get input file metadata: {}", input))?
+        .len();
+
+    let header = match Header::deserialize(&mut file) {
+        Result::Ok(header) => Some(header),
+        Err(dexios::Error::NoHeader) => None,
+        Err(err) => {
+            return Err(anyhow::Error::new(err)
+                .context(format!("Unable to read the header from file: {}", input)))
+        }
+    };
+
+    match (header, report::is_json()) {
+        (Some(header), true) => report::record_all(header_info(input, &header, file_len)),
+        (None, true) => report::record_all(legacy_info(input, file_len)),
+        (Some(header), false) => print_header(input, &header, file_len),
+        (None, false) => print_legacy(input, file_len),
+    }
+
+    Ok(())
+}
+
+// this collects the metadata from a file with a header, for the json report
+fn header_info(input: &str, header: &Header, file_len: u64) -> Value {
+    let header_len = header.serialize().len() as u64;
+    let encrypted_len = file_len.saturating_sub(header_len);
+
+    let (kdf, keyslots) = match &header.key_material {
+        KeyMaterial::Derived {
+            kdf, kdf_params, ..
+        } => (kdf_info(&kdf.to_string(), kdf_params), Value::Null),
+        KeyMaterial::Wrapped(keyslots) => (
+            Value::Null,
+            keyslots
+                .iter()
+                .enumerate()
+                .map(|(index, keyslot)| keyslot_info(index, keyslot))
+                .collect(),
+        ),
+    };
+
+    let block_size = match header.cipher_mode {
+        CipherMode::MemoryMode => None,
+        CipherMode::StreamMode => Some(header.block_size),
+    };
+
+    json!({
+        "file": input,
+        "format": "dexios",
+        "version": header.version.to_string(),
+        "cipher": header.cipher_type.to_string(),
+        "mode": mode_name(header.cipher_mode),
+        "block_size": block_size,
+        "content": header.content.to_string(),
+        "kdf": kdf,
+        "keyslots": keyslots,
+        "header_size": header_len,
+        "encrypted_size": encrypted_len,
+        "decrypted_size": header.data_len(encrypted_len),
+    })
+}
+
+// this collects what can be guessed about a legacy (headerless) file, for the json report
+fn legacy_info(input: &str, file_len: u64) -> Value {
+    let cipher_mode = legacy_mode(file_len);
+
+    let estimates: Vec<Value> = LEGACY_CIPHERS
+        .into_iter()
+        .map(|cipher_type| {
+            let header = legacy_header(cipher_type, cipher_mode);
+            let header_len = header.serialize().len() as u64;
+            let encrypted_len = file_len.saturating_sub(header_len);
+
+            json!({
+                "cipher": cipher_type.to_string(),
+                "header_size": header_len,
+                "encrypted_size": encrypted_len,
+                "decrypted_size": header.data_len(encrypted_len),
+            })
+        })
+        .collect();
+
+    json!({
+        "file": input,
+        "format": "legacy",
+        "version": HeaderVersion::Legacy.to_string(),
+        "mode": mode_name(cipher_mode),
+        "kdf": kdf_info(&KdfType::Argon2id.to_string(), &KdfParams::default()),
+        "file_size": file_len,
+        "estimates": estimates,
+    })
+}
+
+// legacy (headerless) files are just the salt, the nonce and the encrypted data, so the cipher can't be determined
+// the mode is guessed from the size of the file, in the same way that decrypt does it
+const LEGACY_CIPHERS: [CipherType; 2] = [CipherType::XChaCha20Poly1305, CipherType::AesGcm];
+
+fn legacy_mode(file_len: u64) -> CipherMode {
+    if file_len <= (BLOCK_SIZE + 24 + SALT_LEN) as u64 {
+        CipherMode::MemoryMode
+    } else {
+        CipherMode::StreamMode
+    }
+}
+
+// this builds the header that a legacy file would have, so the sizes can be calculated
+// the salt and nonce are zeroed, as only their lengths matter
+fn legacy_header(cipher_type: CipherType, cipher_mode: CipherMode) -> Header {
+    let mut empty = std::io::repeat(0);
+    Header::deserialize_legacy(&mut empty, cipher_type, cipher_mode)
+        .expect("reading from io::repeat can't fail")
+}
+
+fn kdf_info(kdf: &str, kdf_params: &KdfParams) -> Value {
+    json!({
+        "algorithm": kdf,
+        "memory": kdf_params.m_cost,
+        "iterations": kdf_params.t_cost,
+        "parallelism": kdf_params.p_cost,
+    })
+}
+
+fn keyslot_info(index: usize, keyslot: &Keyslot) -> Value {
+    match keyslot {
+        Keyslot::X25519(wrapped) => json!({
+            "index": index,
+            "type": "x25519",
+            "fingerprint": encode_hex(&wrapped.fingerprint),
+        }),
+        Keyslot::Hybrid(wrapped) => json!({
+            "index": index,
+            "type": "hybrid",
+            "fingerprint": encode_hex(&wrapped.fingerprint),
+        }),
+        Keyslot::Password {
+            kdf, kdf_params, ..
+        } => json!({
+            "index": index,
+            "type": "password",
+            "kdf": kdf_info(&kdf.to_string(), kdf_params),
+        }),
+        Keyslot::PasswordSubkey {
+            kdf, kdf_params, ..
+        } => json!({
+            "index": index,
+            "type": "password_subkey",
+            "kdf": kdf_info(&kdf.to_string(), kdf_params),
+        }),
+    }
+}
+
+fn mode_name(cipher_mode: CipherMode) -> &'static str {
+    match cipher_mode {
+        CipherMode::MemoryMode => "memory",
+        CipherMode::StreamMode => "stream",
+    }
+}
+
+// this prints the metadata from a file with a header in a human-readable format
+// the header's types are printed through their Display impls, so this matches the rest of dexios' messages
+fn print_header(input: &str, header: &Header, file_len: u64) {
+    let header_len = header.serialize().len() as u64;
+    let encrypted_len = file_len.saturating_sub(header_len);
+
+    println!("File: {}", input);
+    println!("Header version: {}", header.version);
+    println!("Cipher: {}", header.cipher_type);
+    println!("Mode: {}", header.cipher_mode);
+
+    if header.cipher_mode == CipherMode::StreamMode {
+        println!("Block size: {} bytes", header.block_size);
+    }
+
+    if header.content != ContentType::Unspecified {
+        println!("Content: {}", header.content);
+    }
+
+    match &header.key_material {
+        KeyMaterial::Derived { .. } => println!("Key derivation: {}", header.key_material),
+        KeyMaterial::Wrapped(keyslots) => {
+            println!("Key slots: {}", keyslots.len());
+            for (index, keyslot) in keyslots.iter().enumerate() {
+                println!("  {}: {}", index, keyslot);
+            }
+        }
+    }
+
+    println!("Header size: {} bytes", header_len);
+    println!("Encrypted size: {} bytes", encrypted_len);
+    println!(
+        "Decrypted size: {}",
+        size_text(header.data_len(encrypted_len))
+    );
+}
+
+// this prints what can be guessed about a legacy (headerless) file in a human-readable format
+fn print_legacy(input: &str, file_len: u64) {
+    let cipher_mode = legacy_mode(file_len);
+
+    println!("File: {}", input);
+    println!("No dexios header was found, so this is most likely a legacy (headerless) file");
+    println!("Mode: {} (estimated from the file's size)", cipher_mode);
+    println!("Cipher: unknown (use -g when decrypting if it was encrypted with AES-256-GCM)");
+    println!(
+        "Key derivation: {} with {}",
+        KdfType::Argon2id,
+        KdfParams::default()
+    );
+    println!("File size: {} bytes", file_len);
+
+    for cipher_type in LEGACY_CIPHERS {
+        let header = legacy_header(cipher_type, cipher_mode);
+        let encrypted_len = file_len.saturating_sub(header.serialize().len() as u64);
+        println!(
+            "Decrypted size (if {}): {}",
+            cipher_type,
+            size_text(header.data_len(encrypted_len))
+        );
+    }
+}
+
+fn size_text(size: Option<u64>) -> String {
+    match size {
+        Some(size) => format!("{} bytes", size),
+        None => String::from("unknown (the file appears to be truncated)"),
+    }
+}
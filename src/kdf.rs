@@ -0,0 +1,151 @@
+use crate::error::{Error, Result};
+use crate::SALT_LEN;
+use argon2::{Argon2, Params};
+use hkdf::Hkdf;
+use rand::{prelude::StdRng, RngCore, SeedableRng};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+
+// these are the argon2id cost parameters
+// they're stored in the header, so files encrypted with custom parameters can still be decrypted
+// m_cost is in KiB
+#[derive(PartialEq, Eq, Copy, Clone, Debug)]
+pub struct KdfParams {
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+}
+
+// these are the highest cost parameters that are accepted, for both encryption and decryption
+// they're well above the paranoid preset, but still low enough that a file can always be decrypted on a reasonable machine
+pub const MAX_M_COST: u32 = 4_194_304; // 4GiB
+pub const MAX_T_COST: u32 = 64;
+pub const MAX_P_COST: u32 = 16;
+
+// password key slots are read before anything has been authenticated too, and every one of them has to be tried before a wrong key is rejected
+// so these bound the argon2id work that a whole header can ask for, otherwise it could hold 255 maximum-cost key slots
+// the total cost (memory cost × iterations, summed over the key slots) is the same as a single key slot with the highest parameters
+pub const MAX_PASSWORD_KEYSLOTS: usize = 16;
+pub const MAX_TOTAL_COST: u64 = MAX_M_COST as u64 * MAX_T_COST as u64;
+
+impl Default for KdfParams {
+    // these are the argon2 crate's defaults, and were used by every file prior to V3 headers
+    fn default() -> Self {
+        KdfParams {
+            m_cost: 4096,
+            t_cost: 3,
+            p_cost: 1,
+        }
+    }
+}
+
+impl KdfParams {
+    // these presets follow libsodium's argon2id recommendations
+    pub fn interactive() -> Self {
+        KdfParams {
+            m_cost: 65536, // 64MiB
+            t_cost: 2,
+            p_cost: 1,
+        }
+    }
+
+    pub fn moderate() -> Self {
+        KdfParams {
+            m_cost: 262_144, // 256MiB
+            t_cost: 3,
+            p_cost: 1,
+        }
+    }
+
+    pub fn paranoid() -> Self {
+        KdfParams {
+            m_cost: 1_048_576, // 1GiB
+            t_cost: 4,
+            p_cost: 1,
+        }
+    }
+
+    // this checks that the parameters are within the limits below, and that argon2 will accept them
+    // the parameters are read from the header before anything has been authenticated, so the limits stop a crafted header from using up all of the memory (or hanging)
+    pub fn validate(&self) -> Result<()> {
+        if self.m_cost > MAX_M_COST || self.t_cost > MAX_T_COST || self.p_cost > MAX_P_COST {
+            return Err(Error::InvalidKdfParams);
+        }
+
+        self.to_argon2_params().map(|_| ())
+    }
+
+    fn to_argon2_params(self) -> Result<Params> {
+        Params::new(self.m_cost, self.t_cost, self.p_cost, None)
+            .map_err(|_| Error::InvalidKdfParams)
+    }
+}
+
+// this checks that a header's password key slots, with these parameters, are within the limits above
+pub fn within_total_limits(kdf_params: impl IntoIterator<Item = KdfParams>) -> bool {
+    let mut count = 0;
+    let mut total_cost = 0;
+
+    for params in kdf_params {
+        count += 1;
+        total_cost += u64::from(params.m_cost) * u64::from(params.t_cost);
+    }
+
+    count <= MAX_PASSWORD_KEYSLOTS && total_cost <= MAX_TOTAL_COST
+}
+
+impl std::fmt::Display for KdfParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "memory: {}KiB, iterations: {}, parallelism: {}",
+            self.m_cost, self.t_cost, self.p_cost
+        )
+    }
+}
+
+// this generates a salt for password hashing
+pub fn gen_salt() -> [u8; SALT_LEN] {
+    let mut salt: [u8; SALT_LEN] = [0; SALT_LEN];
+    StdRng::from_entropy().fill_bytes(&mut salt);
+    salt
+}
+
+// this handles argon2id hashing with the provided key, salt and cost parameters
+// it returns the 32 byte key, ready to be used with either cipher
+pub fn derive_key(
+    raw_key: Secret<Vec<u8>>,
+    salt: &[u8; SALT_LEN],
+    kdf_params: KdfParams,
+) -> Result<Secret<[u8; 32]>> {
+    let mut key = [0u8; 32];
+
+    let argon2 = Argon2::new(
+        argon2::Algorithm::Argon2id,
+        argon2::Version::V0x13,
+        kdf_params.to_argon2_params()?,
+    );
+    let result = argon2.hash_password_into(raw_key.expose_secret(), salt, &mut key);
+    drop(raw_key);
+
+    if result.is_err() {
+        return Err(Error::KeyDerivation);
+    }
+
+    Ok(Secret::new(key))
+}
+
+// this expands an argon2id hash into a subkey with HKDF-SHA256, using a unique salt
+// it's used by password subkey key slots, so many files can share one (slow) argon2id hash but still have their own wrapping keys
+pub fn derive_subkey(
+    key: &Secret<[u8; 32]>,
+    subkey_salt: &[u8; SALT_LEN],
+) -> Result<Secret<[u8; 32]>> {
+    let mut subkey = [0u8; 32];
+
+    Hkdf::<Sha256>::new(Some(subkey_salt), key.expose_secret())
+        .expand(b"dexios password subkey", &mut subkey)
+        .map_err(|_| Error::KeyDerivation)?;
+
+    Ok(Secret::new(subkey))
+}
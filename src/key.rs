@@ -1,29 +1,46 @@
+use crate::failure::Failure;
 use crate::file::get_bytes;
+use crate::global::Parameters;
 use crate::global::PasswordMode;
+use crate::report;
 use anyhow::{Context, Ok, Result};
+use dexios::header::Keyslot;
+use dexios::recipient::{HYBRID_PUBLIC_KEY_PREFIX, PUBLIC_KEY_PREFIX};
+use dexios::{DecryptionKey, EncryptionKey, Header, Identity, KeyMaterial, Recipient};
+use secrecy::ExposeSecret;
 use secrecy::Secret;
 use secrecy::SecretVec;
 use secrecy::Zeroize;
 
 // this interactively gets the user's password from the terminal
 // it takes the password twice, compares, and returns the bytes
-fn get_password(validation: bool) -> Result<Secret<Vec<u8>>> {
+// in json mode we fail instead, so the key has to come from a keyfile or the environment
+fn get_password(prompt: &str, validation: bool) -> Result<Secret<Vec<u8>>> {
+    if report::is_json() {
+        return Err(anyhow::anyhow!(
+            "Unable to prompt for a password in json mode - use a keyfile or the DEXIOS_KEY/DEXIOS_NEW_KEY environment variables"
+        )
+        .context(Failure::InvalidArguments));
+    }
+
     Ok(loop {
-        let input = rpassword::prompt_password("Password: ").context("Unable to read password")?;
+        let input = rpassword::prompt_password(format!("{}: ", prompt))
+            .context("Unable to read password")?;
         if !validation {
             return Ok(SecretVec::new(input.into_bytes()));
         }
 
-        let mut input_validation = rpassword::prompt_password("Password (for validation): ")
-            .context("Unable to read password")?;
+        let mut input_validation =
+            rpassword::prompt_password(format!("{} (for validation): ", prompt))
+                .context("Unable to read password")?;
 
         if input == input_validation && !input.is_empty() {
             input_validation.zeroize();
             break SecretVec::new(input.into_bytes());
         } else if input.is_empty() {
-            println!("Password cannot be empty, please try again.");
+            eprintln!("Password cannot be empty, please try again.");
         } else {
-            println!("The passwords aren't the same, please try again.");
+            eprintln!("The passwords aren't the same, please try again.");
         }
     })
 }
@@ -40,18 +57,158 @@ pub fn get_user_key(
     password: PasswordMode,
 ) -> Result<Secret<Vec<u8>>> {
     Ok(if !keyfile.is_empty() {
-        println!("Reading key from {}", keyfile);
+        eprintln!("Reading key from {}", keyfile);
         get_bytes(keyfile)? // already a secret
     } else if std::env::var("DEXIOS_KEY").is_ok()
         && password == PasswordMode::NormalKeySourcePriority
     {
-        println!("Reading key from DEXIOS_KEY environment variable");
+        eprintln!("Reading key from DEXIOS_KEY environment variable");
         SecretVec::new(
             std::env::var("DEXIOS_KEY")
                 .context("Unable to read DEXIOS_KEY from environment variable")?
                 .into_bytes(),
         )
     } else {
-        get_password(validation)? // already a secret
+        get_password("Password", validation)? // already a secret
     })
 }
+
+// this checks whether the key would have to be entered at a password prompt, rather than read from a keyfile, the environment or the key cache
+// recipients and identities don't need a password at all
+pub fn prompts_for_password(keyfile: &str, params: &Parameters) -> bool {
+    keyfile.is_empty()
+        && params.recipients.is_empty()
+        && params.identity.is_none()
+        && params.key_cache.is_none()
+        && (std::env::var("DEXIOS_KEY").is_err()
+            || params.password == PasswordMode::ForceUserProvidedPassword)
+}
+
+// this gets a new key, for adding to an encrypted file's key slots
+// it works the same as get_user_key, but reads from the new keyfile and the DEXIOS_NEW_KEY environment variable instead
+// the password always has to be entered twice, as it's a new one
+pub fn get_new_user_key(new_keyfile: &str) -> Result<Secret<Vec<u8>>> {
+    Ok(if !new_keyfile.is_empty() {
+        eprintln!("Reading new key from {}", new_keyfile);
+        get_bytes(new_keyfile)?
+    } else if std::env::var("DEXIOS_NEW_KEY").is_ok() {
+        eprintln!("Reading new key from DEXIOS_NEW_KEY environment variable");
+        SecretVec::new(
+            std::env::var("DEXIOS_NEW_KEY")
+                .context("Unable to read DEXIOS_NEW_KEY from environment variable")?
+                .into_bytes(),
+        )
+    } else {
+        get_password("New password", true)?
+    })
+}
+
+// this gets the key for encrypting a file
+// if any recipients were specified, the file is encrypted to them and the user isn't asked for a key
+// otherwise, the user's key is read as usual and hashed with argon2id (or taken from the key cache, in batch mode)
+pub fn get_encryption_key(keyfile: &str, params: &Parameters) -> Result<EncryptionKey> {
+    if !params.recipients.is_empty() {
+        eprintln!("Encrypting to {} recipient(s)", params.recipients.len());
+        return Ok(EncryptionKey::Recipients(params.recipients.clone()));
+    }
+
+    if let Some(cache) = &params.key_cache {
+        return Ok(EncryptionKey::Cached {
+            cache: cache.clone(),
+            kdf_params: params.kdf_params,
+        });
+    }
+
+    Ok(EncryptionKey::Password {
+        raw_key: get_user_key(keyfile, true, params.password)?,
+        kdf_params: params.kdf_params,
+    })
+}
+
+// this gets the key for decrypting a file
+// files that were only encrypted to recipients require an identity, so we check for that before asking the user for anything
+pub fn get_decryption_key(
+    keyfile: &str,
+    header: &Header,
+    params: &Parameters,
+) -> Result<DecryptionKey> {
+    if let Some(identity) = &params.identity {
+        if let KeyMaterial::Derived { .. } = header.key_material {
+            return Err(anyhow::anyhow!(
+                "This file was encrypted with a password or keyfile, so an identity can't be used to decrypt it"
+            )
+            .context(Failure::InvalidArguments));
+        }
+
+        eprintln!("Reading identity from {}", identity);
+        return Ok(DecryptionKey::Identity(read_identity(identity)?));
+    }
+
+    if let KeyMaterial::Wrapped(keyslots) = &header.key_material {
+        if !keyslots.iter().any(|keyslot| {
+            matches!(
+                keyslot,
+                Keyslot::Password { .. } | Keyslot::PasswordSubkey { .. }
+            )
+        }) {
+            return Err(anyhow::anyhow!(
+                "This file was encrypted to one or more recipients, please provide your identity with --identity"
+            )
+            .context(Failure::InvalidArguments));
+        }
+    }
+
+    if let Some(cache) = &params.key_cache {
+        return Ok(DecryptionKey::Cached(cache.clone()));
+    }
+
+    Ok(DecryptionKey::Password(get_user_key(
+        keyfile,
+        false,
+        params.password,
+    )?))
+}
+
+// this reads an identity from a file, such as one that was created by `dexios keygen`
+pub fn read_identity(name: &str) -> Result<Identity> {
+    let contents = get_bytes(name)?;
+    let contents = std::str::from_utf8(contents.expose_secret())
+        .with_context(|| format!("Unable to read identity file: {}", name))?;
+    Identity::parse(contents).with_context(|| format!("Unable to read identity file: {}", name))
+}
+
+// this parses the recipients that the user provided
+// each one may either be a public key, or a file containing one public key per line
+pub fn read_recipients(values: &[&str]) -> Result<Vec<Recipient>> {
+    let mut recipients = Vec::new();
+
+    for value in values {
+        if value.starts_with(PUBLIC_KEY_PREFIX) || value.starts_with(HYBRID_PUBLIC_KEY_PREFIX) {
+            recipients.push(
+                Recipient::parse(value)
+                    .with_context(|| format!("Unable to read recipient: {}", value))?,
+            );
+            continue;
+        }
+
+        let contents = std::fs::read_to_string(value)
+            .with_context(|| format!("Unable to read recipients file: {}", value))?;
+
+        // the line itself isn't included in any errors, in case this is actually a secret key
+        for (number, line) in contents.lines().map(str::trim).enumerate() {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            recipients.push(Recipient::parse(line).with_context(|| {
+                format!(
+                    "Unable to read recipient from {} (line {})",
+                    value,
+                    number + 1
+                )
+            })?);
+        }
+    }
+
+    Ok(recipients)
+}
@@ -0,0 +1,72 @@
+use crate::failure::Failure;
+use crate::global::{BenchMode, IdentityType, SkipMode};
+use crate::prompt::overwrite_check;
+use crate::report;
+use anyhow::{Context, Ok, Result};
+use dexios::Identity;
+use secrecy::{ExposeSecret, SecretString};
+use std::fs::OpenOptions;
+use std::io::Write;
+
+// this generates a new X25519 (or hybrid X25519 + ML-KEM-768) identity (keypair), and writes the secret half of it to the output file
+// the public key is printed, so it can be shared with anyone that would like to encrypt files for this identity
+// the identity file is only readable by the current user (on unix)
+pub fn generate_identity(output: &str, skip: SkipMode, identity_type: IdentityType) -> Result<()> {
+    if !overwrite_check(output, skip, BenchMode::WriteToFilesystem)? {
+        return Err(Failure::Aborted.into());
+    }
+
+    let identity = match identity_type {
+        IdentityType::X25519 => Identity::generate(),
+        IdentityType::HybridPostQuantum => {
+            Identity::generate_hybrid().context("Unable to generate a hybrid identity")?
+        }
+    };
+    let recipient = identity.recipient();
+
+    let contents = SecretString::new(format!(
+        "# created by dexios keygen\n# public key: {}\n{}\n",
+        recipient,
+        identity
+            .encode()
+            .context("Unable to encode the identity")?
+            .expose_secret()
+    ));
+
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options
+        .open(output)
+        .with_context(|| format!("Unable to open output file: {}", output))?;
+
+    // the mode only applies to new files, so an existing file's permissions are restricted before the secret is written to it
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))
+            .with_context(|| format!("Unable to set the permissions of {}", output))?;
+    }
+
+    file.write_all(contents.expose_secret().as_bytes())
+        .with_context(|| format!("Unable to write identity to file: {}", output))?;
+    file.flush()
+        .with_context(|| format!("Unable to flush the output file: {}", output))?;
+
+    report::record("output", output);
+    report::record("public_key", recipient.to_string());
+
+    eprintln!(
+        "Your identity has been saved to {}. Keep it secret!",
+        output
+    );
+    report::print_result(&format!("Public key: {}", recipient));
+
+    Ok(())
+}
@@ -0,0 +1,760 @@
+use crate::cipher::{nonce_len, unwrap_key, wrap_key, CipherMode, CipherType};
+use crate::error::{Error, Result};
+use crate::header::{ContentType, Header, HeaderVersion, KdfType, KeyMaterial, Keyslot};
+use crate::kdf::{derive_key, derive_subkey, gen_salt, within_total_limits, KdfParams};
+use crate::recipient::{Identity, Recipient};
+use crate::{MAX_BLOCK_SIZE, MIN_BLOCK_SIZE, SALT_LEN};
+use rand::{prelude::StdRng, RngCore, SeedableRng};
+use secrecy::{ExposeSecret, Secret};
+use std::sync::{Arc, Mutex};
+
+// this file decides how the key that encrypts the data is obtained, and builds the header to match
+// the data is encrypted with a random master key, and a wrapped copy of it is stored in each key slot
+// password/keyfile key slots are wrapped with an argon2id hash of the key, and recipient key slots are wrapped for an X25519 (or hybrid X25519 + ML-KEM-768) public key
+// key slots may be added or removed later on, without re-encrypting the data
+//
+// files with a V3 header (or older) don't have a master key, as the key was hashed with argon2id and used to encrypt the data directly
+
+pub const MAX_KEYSLOTS: usize = 255;
+
+// this is the key that's used when encrypting (or when adding a new key slot)
+// a cached key adds a password subkey key slot, so the key doesn't need to be hashed again for every file
+pub enum EncryptionKey {
+    Password {
+        raw_key: Secret<Vec<u8>>,
+        kdf_params: KdfParams,
+    },
+    Cached {
+        cache: Arc<KeyCache>,
+        kdf_params: KdfParams,
+    },
+    Recipients(Vec<Recipient>),
+}
+
+// this is the key that's used when decrypting
+// the master key is returned by the encrypt functions, so a file can be verified straight after it's written (even if it was only encrypted to recipients)
+pub enum DecryptionKey {
+    Password(Secret<Vec<u8>>),
+    Cached(Arc<KeyCache>),
+    Identity(Identity),
+    MasterKey(Secret<[u8; 32]>),
+}
+
+// this holds a password/keyfile, along with every argon2id hash of it that has been calculated so far
+// batch operations use this so that the key is only hashed once per run, rather than once per file
+// new key slots all share the cache's salt, but each of them gets a unique subkey (see `Keyslot::PasswordSubkey`)
+pub struct KeyCache {
+    raw_key: Secret<Vec<u8>>,
+    salt: [u8; SALT_LEN],
+    hashes: Mutex<Vec<CachedHash>>,
+}
+
+struct CachedHash {
+    salt: [u8; SALT_LEN],
+    kdf_params: KdfParams,
+    hash: Secret<[u8; 32]>,
+}
+
+impl KeyCache {
+    pub fn new(raw_key: Secret<Vec<u8>>) -> Self {
+        KeyCache {
+            raw_key,
+            salt: gen_salt(),
+            hashes: Mutex::new(Vec::new()),
+        }
+    }
+
+    // this returns the argon2id hash of the key with this salt and these parameters
+    // the key is only hashed if it hasn't been hashed with them before
+    fn hash(&self, salt: &[u8; SALT_LEN], kdf_params: KdfParams) -> Result<Secret<[u8; 32]>> {
+        let mut hashes = self.hashes.lock().map_err(|_| Error::KeyDerivation)?;
+
+        if let Some(cached) = hashes
+            .iter()
+            .find(|cached| cached.salt == *salt && cached.kdf_params == kdf_params)
+        {
+            return Ok(Secret::new(*cached.hash.expose_secret()));
+        }
+
+        let raw_key = Secret::new(self.raw_key.expose_secret().clone());
+        let hash = derive_key(raw_key, salt, kdf_params)?;
+        hashes.push(CachedHash {
+            salt: *salt,
+            kdf_params,
+            hash: Secret::new(*hash.expose_secret()),
+        });
+
+        Ok(hash)
+    }
+}
+
+// this generates the nonce and the key material for a new file
+// the block size is only used in stream mode, but it's always recorded in the header
+// it returns the header, along with the key that the data should be encrypted with
+pub fn new_header(
+    cipher_type: CipherType,
+    cipher_mode: CipherMode,
+    block_size: usize,
+    key: EncryptionKey,
+) -> Result<(Header, Secret<[u8; 32]>)> {
+    new_header_with_content(
+        cipher_type,
+        cipher_mode,
+        block_size,
+        key,
+        ContentType::Unspecified,
+    )
+}
+
+// this is the same as new_header(), but it also records what the data contains
+// a V6 header is only used if the content is specified, so that other files can still be read by older versions of dexios
+pub fn new_header_with_content(
+    cipher_type: CipherType,
+    cipher_mode: CipherMode,
+    block_size: usize,
+    key: EncryptionKey,
+    content: ContentType,
+) -> Result<(Header, Secret<[u8; 32]>)> {
+    if !(MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&block_size) {
+        return Err(Error::InvalidBlockSize(block_size));
+    }
+
+    let mut nonce = vec![0u8; nonce_len(cipher_type, cipher_mode)];
+    StdRng::from_entropy().fill_bytes(&mut nonce);
+
+    let master_key = gen_master_key();
+
+    let version = if content == ContentType::Unspecified {
+        HeaderVersion::V5
+    } else {
+        HeaderVersion::V6
+    };
+
+    let mut header = Header {
+        version,
+        cipher_type,
+        cipher_mode,
+        key_material: KeyMaterial::Wrapped(Vec::new()),
+        nonce,
+        block_size,
+        content,
+    };
+
+    add_keyslot(&mut header, &master_key, key)?;
+
+    Ok((header, master_key))
+}
+
+// this obtains the key that the data was encrypted with, from the key material within the header
+// a master key is returned as-is, as it's already the key that the data was encrypted with
+pub fn unlock(header: &Header, key: DecryptionKey) -> Result<Secret<[u8; 32]>> {
+    match &header.key_material {
+        KeyMaterial::Derived {
+            kdf_params, salt, ..
+        } => match key {
+            DecryptionKey::Password(raw_key) => derive_key(raw_key, salt, *kdf_params),
+            DecryptionKey::Cached(cache) => cache.hash(salt, *kdf_params),
+            DecryptionKey::Identity(_) => Err(Error::WrongKeyType(
+                "this file was encrypted with a password or keyfile, not to a recipient",
+            )),
+            DecryptionKey::MasterKey(master_key) => Ok(master_key),
+        },
+        KeyMaterial::Wrapped(_) => match key {
+            DecryptionKey::MasterKey(master_key) => Ok(master_key),
+            key => unlock_keyslot(header, key).map(|(_, master_key)| master_key),
+        },
+    }
+}
+
+// files with key slots (V4 headers and above) check the key when the master key is unwrapped, so any data that fails authentication afterwards must be corrupt
+// older files use the argon2id hash of the key directly, so a wrong key can't be told apart from corrupt data until a block has been decrypted successfully
+pub fn has_key_check(header: &Header) -> bool {
+    matches!(header.key_material, KeyMaterial::Wrapped(_))
+}
+
+// this tries each of the header's key slots until one of them can be unwrapped with the key
+// it returns the index of that key slot, along with the master key
+pub fn unlock_keyslot(header: &Header, key: DecryptionKey) -> Result<(usize, Secret<[u8; 32]>)> {
+    let keyslots = match &header.key_material {
+        KeyMaterial::Wrapped(keyslots) => keyslots,
+        KeyMaterial::Derived { .. } => return Err(Error::KeyslotsUnsupported),
+    };
+
+    match key {
+        DecryptionKey::Password(raw_key) => {
+            unlock_password_keyslot(keyslots, &KeyCache::new(raw_key))
+        }
+        DecryptionKey::Cached(cache) => unlock_password_keyslot(keyslots, &cache),
+        DecryptionKey::Identity(identity) => keyslots
+            .iter()
+            .enumerate()
+            .find_map(|(index, keyslot)| match keyslot {
+                Keyslot::X25519(wrapped) => identity
+                    .unwrap_key(wrapped)
+                    .map(|master_key| (index, master_key)),
+                Keyslot::Hybrid(wrapped) => identity
+                    .unwrap_hybrid_key(wrapped)
+                    .map(|master_key| (index, master_key)),
+                Keyslot::Password { .. } | Keyslot::PasswordSubkey { .. } => None,
+            })
+            .ok_or(Error::NoMatchingKeyslot),
+        DecryptionKey::MasterKey(_) => Err(Error::WrongKeyType(
+            "the master key isn't stored in a key slot, so it can't be used to find one",
+        )),
+    }
+}
+
+// this tries each of the password (and password subkey) key slots with the password/keyfile
+// the key is hashed through the cache, so key slots that share a salt only need to hash it once
+fn unlock_password_keyslot(
+    keyslots: &[Keyslot],
+    cache: &KeyCache,
+) -> Result<(usize, Secret<[u8; 32]>)> {
+    let mut has_password_keyslot = false;
+
+    for (index, keyslot) in keyslots.iter().enumerate() {
+        let (wrapping_key, wrapped_key) = match keyslot {
+            Keyslot::Password {
+                kdf_params,
+                salt,
+                wrapped_key,
+                ..
+            } => (cache.hash(salt, *kdf_params)?, wrapped_key),
+            Keyslot::PasswordSubkey {
+                kdf_params,
+                salt,
+                subkey_salt,
+                wrapped_key,
+                ..
+            } => (
+                derive_subkey(&cache.hash(salt, *kdf_params)?, subkey_salt)?,
+                wrapped_key,
+            ),
+            Keyslot::X25519(_) | Keyslot::Hybrid(_) => continue,
+        };
+
+        has_password_keyslot = true;
+        if let Some(master_key) = unwrap_key(&wrapping_key, wrapped_key) {
+            return Ok((index, master_key));
+        }
+    }
+
+    if has_password_keyslot {
+        Err(Error::NoMatchingKeyslot)
+    } else {
+        Err(Error::WrongKeyType(
+            "this file was encrypted to recipients, so it must be decrypted with an identity",
+        ))
+    }
+}
+
+// this wraps the master key with a new key, and adds the resulting key slot(s) to the header
+// a password/keyfile adds a single key slot, and recipients add one key slot each
+pub fn add_keyslot(
+    header: &mut Header,
+    master_key: &Secret<[u8; 32]>,
+    key: EncryptionKey,
+) -> Result<()> {
+    let keyslots = match &mut header.key_material {
+        KeyMaterial::Wrapped(keyslots) => keyslots,
+        KeyMaterial::Derived { .. } => return Err(Error::KeyslotsUnsupported),
+    };
+
+    // this is checked before the key is hashed, as that may take a while
+    let new_kdf_params = match &key {
+        EncryptionKey::Password { kdf_params, .. } | EncryptionKey::Cached { kdf_params, .. } => {
+            Some(*kdf_params)
+        }
+        EncryptionKey::Recipients(_) => None,
+    };
+    if !within_total_limits(
+        keyslots
+            .iter()
+            .filter_map(Keyslot::kdf_params)
+            .chain(new_kdf_params),
+    ) {
+        return Err(Error::KdfCostTooHigh);
+    }
+
+    let new_keyslots = match key {
+        EncryptionKey::Password {
+            raw_key,
+            kdf_params,
+        } => vec![wrap_with_password(master_key, raw_key, kdf_params)?],
+        EncryptionKey::Cached { cache, kdf_params } => {
+            vec![wrap_with_subkey(master_key, &cache, kdf_params)?]
+        }
+        EncryptionKey::Recipients(recipients) => {
+            if recipients.is_empty() {
+                return Err(Error::InvalidKey("at least one recipient is required"));
+            }
+
+            recipients
+                .iter()
+                .map(|recipient| recipient.wrap_key(master_key))
+                .collect::<Result<Vec<_>>>()?
+        }
+    };
+
+    if keyslots.len() + new_keyslots.len() > MAX_KEYSLOTS {
+        return Err(Error::TooManyKeyslots);
+    }
+
+    keyslots.extend(new_keyslots);
+    Ok(())
+}
+
+// this removes a key slot from the header, and returns it
+// the last key slot can't be removed, as the file would no longer be decryptable
+pub fn remove_keyslot(header: &mut Header, index: usize) -> Result<Keyslot> {
+    let keyslots = match &mut header.key_material {
+        KeyMaterial::Wrapped(keyslots) => keyslots,
+        KeyMaterial::Derived { .. } => return Err(Error::KeyslotsUnsupported),
+    };
+
+    if index >= keyslots.len() {
+        return Err(Error::KeyslotNotFound(index));
+    }
+
+    if keyslots.len() == 1 {
+        return Err(Error::LastKeyslot);
+    }
+
+    Ok(keyslots.remove(index))
+}
+
+// this re-wraps the master key within a password/keyfile key slot, using a new password/keyfile
+// the new key slot is always a plain password key slot, so the header's size only changes if it was a password subkey key slot
+pub fn change_keyslot(
+    header: &mut Header,
+    index: usize,
+    master_key: &Secret<[u8; 32]>,
+    raw_key: Secret<Vec<u8>>,
+    kdf_params: KdfParams,
+) -> Result<()> {
+    let keyslots = match &mut header.key_material {
+        KeyMaterial::Wrapped(keyslots) => keyslots,
+        KeyMaterial::Derived { .. } => return Err(Error::KeyslotsUnsupported),
+    };
+
+    let keyslot = keyslots.get(index).ok_or(Error::KeyslotNotFound(index))?;
+
+    if let Keyslot::X25519(_) | Keyslot::Hybrid(_) = keyslot {
+        return Err(Error::WrongKeyType(
+            "only password/keyfile key slots can be changed",
+        ));
+    }
+
+    let other_kdf_params = keyslots
+        .iter()
+        .enumerate()
+        .filter(|(other, _)| *other != index)
+        .filter_map(|(_, keyslot)| keyslot.kdf_params());
+    if !within_total_limits(other_kdf_params.chain(Some(kdf_params))) {
+        return Err(Error::KdfCostTooHigh);
+    }
+
+    keyslots[index] = wrap_with_password(master_key, raw_key, kdf_params)?;
+    Ok(())
+}
+
+// this hashes the password/keyfile with argon2id and a fresh salt, and uses the result to wrap the master key
+fn wrap_with_password(
+    master_key: &Secret<[u8; 32]>,
+    raw_key: Secret<Vec<u8>>,
+    kdf_params: KdfParams,
+) -> Result<Keyslot> {
+    let salt = gen_salt();
+    let wrapping_key = derive_key(raw_key, &salt, kdf_params)?;
+
+    Ok(Keyslot::Password {
+        kdf: KdfType::Argon2id,
+        kdf_params,
+        salt,
+        wrapped_key: wrap_key(&wrapping_key, master_key)?,
+    })
+}
+
+// this expands the cached argon2id hash of the password/keyfile into a new subkey (with a fresh salt), and uses that to wrap the master key
+fn wrap_with_subkey(
+    master_key: &Secret<[u8; 32]>,
+    cache: &KeyCache,
+    kdf_params: KdfParams,
+) -> Result<Keyslot> {
+    let subkey_salt = gen_salt();
+    let wrapping_key = derive_subkey(&cache.hash(&cache.salt, kdf_params)?, &subkey_salt)?;
+
+    Ok(Keyslot::PasswordSubkey {
+        kdf: KdfType::Argon2id,
+        kdf_params,
+        salt: cache.salt,
+        subkey_salt,
+        wrapped_key: wrap_key(&wrapping_key, master_key)?,
+    })
+}
+
+// this generates a random master key
+fn gen_master_key() -> Secret<[u8; 32]> {
+    let mut master_key = [0u8; 32];
+    StdRng::from_entropy().fill_bytes(&mut master_key);
+    Secret::new(master_key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kdf::{MAX_M_COST, MAX_PASSWORD_KEYSLOTS, MAX_T_COST};
+    use crate::test_utils::{password, raw_key, KDF_PARAMS};
+    use crate::{BLOCK_SIZE, SALT_LEN};
+
+    fn new_password_header(key: &str) -> (Header, Secret<[u8; 32]>) {
+        new_header(
+            CipherType::XChaCha20Poly1305,
+            CipherMode::StreamMode,
+            MIN_BLOCK_SIZE,
+            password(key),
+        )
+        .unwrap()
+    }
+
+    fn unlock_with(header: &Header, key: &str) -> Result<(usize, Secret<[u8; 32]>)> {
+        unlock_keyslot(header, DecryptionKey::Password(raw_key(key)))
+    }
+
+    fn keyslots(header: &Header) -> &[Keyslot] {
+        match &header.key_material {
+            KeyMaterial::Wrapped(keyslots) => keyslots,
+            KeyMaterial::Derived { .. } => panic!("the header doesn't have key slots"),
+        }
+    }
+
+    #[test]
+    fn new_header_unlocks_with_the_password() {
+        let (header, master_key) = new_password_header("hunter2");
+        assert_eq!(header.version, HeaderVersion::V5);
+        assert!(has_key_check(&header));
+        assert!(matches!(keyslots(&header), [Keyslot::Password { .. }]));
+
+        let (index, unlocked) = unlock_with(&header, "hunter2").unwrap();
+        assert_eq!(index, 0);
+        assert_eq!(unlocked.expose_secret(), master_key.expose_secret());
+
+        let unlocked = unlock(&header, DecryptionKey::Password(raw_key("hunter2"))).unwrap();
+        assert_eq!(unlocked.expose_secret(), master_key.expose_secret());
+
+        assert!(matches!(
+            unlock_with(&header, "hunter3"),
+            Err(Error::NoMatchingKeyslot)
+        ));
+    }
+
+    #[test]
+    fn invalid_block_sizes_are_rejected() {
+        for block_size in [MIN_BLOCK_SIZE - 1, MAX_BLOCK_SIZE + 1] {
+            assert!(matches!(
+                new_header(
+                    CipherType::AesGcm,
+                    CipherMode::StreamMode,
+                    block_size,
+                    password("hunter2"),
+                ),
+                Err(Error::InvalidBlockSize(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn added_keyslots_unlock_the_same_master_key() {
+        let (mut header, master_key) = new_password_header("hunter2");
+        let identity = Identity::generate();
+
+        add_keyslot(&mut header, &master_key, password("correct horse")).unwrap();
+        add_keyslot(
+            &mut header,
+            &master_key,
+            EncryptionKey::Recipients(vec![identity.recipient()]),
+        )
+        .unwrap();
+        assert_eq!(keyslots(&header).len(), 3);
+
+        let (index, unlocked) = unlock_with(&header, "correct horse").unwrap();
+        assert_eq!(index, 1);
+        assert_eq!(unlocked.expose_secret(), master_key.expose_secret());
+
+        let (index, unlocked) = unlock_keyslot(&header, DecryptionKey::Identity(identity)).unwrap();
+        assert_eq!(index, 2);
+        assert_eq!(unlocked.expose_secret(), master_key.expose_secret());
+
+        assert!(matches!(
+            unlock_keyslot(&header, DecryptionKey::Identity(Identity::generate())),
+            Err(Error::NoMatchingKeyslot)
+        ));
+    }
+
+    #[test]
+    fn keys_of_the_wrong_type_are_rejected() {
+        let (header, _) = new_password_header("hunter2");
+        assert!(matches!(
+            unlock_keyslot(&header, DecryptionKey::Identity(Identity::generate())),
+            Err(Error::NoMatchingKeyslot)
+        ));
+
+        let identity = Identity::generate();
+        let (header, _) = new_header(
+            CipherType::XChaCha20Poly1305,
+            CipherMode::MemoryMode,
+            BLOCK_SIZE,
+            EncryptionKey::Recipients(vec![identity.recipient()]),
+        )
+        .unwrap();
+        assert!(matches!(
+            unlock_with(&header, "hunter2"),
+            Err(Error::WrongKeyType(_))
+        ));
+
+        assert!(matches!(
+            new_header(
+                CipherType::XChaCha20Poly1305,
+                CipherMode::MemoryMode,
+                BLOCK_SIZE,
+                EncryptionKey::Recipients(Vec::new()),
+            ),
+            Err(Error::InvalidKey(_))
+        ));
+    }
+
+    #[test]
+    fn master_key_unlocks_without_a_keyslot() {
+        let (header, master_key) = new_password_header("hunter2");
+
+        let unlocked = unlock(
+            &header,
+            DecryptionKey::MasterKey(Secret::new(*master_key.expose_secret())),
+        )
+        .unwrap();
+        assert_eq!(unlocked.expose_secret(), master_key.expose_secret());
+
+        assert!(matches!(
+            unlock_keyslot(&header, DecryptionKey::MasterKey(master_key)),
+            Err(Error::WrongKeyType(_))
+        ));
+    }
+
+    #[test]
+    fn removed_keyslots_no_longer_unlock() {
+        let (mut header, master_key) = new_password_header("hunter2");
+        add_keyslot(&mut header, &master_key, password("correct horse")).unwrap();
+
+        assert!(matches!(
+            remove_keyslot(&mut header, 2),
+            Err(Error::KeyslotNotFound(2))
+        ));
+
+        let removed = remove_keyslot(&mut header, 0).unwrap();
+        assert!(matches!(removed, Keyslot::Password { .. }));
+        assert!(matches!(
+            unlock_with(&header, "hunter2"),
+            Err(Error::NoMatchingKeyslot)
+        ));
+        assert_eq!(unlock_with(&header, "correct horse").unwrap().0, 0);
+
+        assert!(matches!(
+            remove_keyslot(&mut header, 0),
+            Err(Error::LastKeyslot)
+        ));
+    }
+
+    #[test]
+    fn changed_keyslots_unlock_with_the_new_key() {
+        let (mut header, master_key) = new_password_header("hunter2");
+        let (aad, len) = (header.aad(), header.serialize().len());
+
+        change_keyslot(
+            &mut header,
+            0,
+            &master_key,
+            raw_key("correct horse"),
+            KDF_PARAMS,
+        )
+        .unwrap();
+
+        // the key slot is the same size, so the header can be rewritten in place
+        assert_eq!(header.aad(), aad);
+        assert_eq!(header.serialize().len(), len);
+
+        assert!(matches!(
+            unlock_with(&header, "hunter2"),
+            Err(Error::NoMatchingKeyslot)
+        ));
+        let (_, unlocked) = unlock_with(&header, "correct horse").unwrap();
+        assert_eq!(unlocked.expose_secret(), master_key.expose_secret());
+
+        assert!(matches!(
+            change_keyslot(&mut header, 1, &master_key, raw_key("x"), KDF_PARAMS),
+            Err(Error::KeyslotNotFound(1))
+        ));
+
+        add_keyslot(
+            &mut header,
+            &master_key,
+            EncryptionKey::Recipients(vec![Identity::generate().recipient()]),
+        )
+        .unwrap();
+        assert!(matches!(
+            change_keyslot(&mut header, 1, &master_key, raw_key("x"), KDF_PARAMS),
+            Err(Error::WrongKeyType(_))
+        ));
+    }
+
+    // every file shares the cache's salt (so the key is only hashed once), but each one gets its own subkey
+    #[test]
+    fn cached_keys_use_unique_subkeys() {
+        let cache = Arc::new(KeyCache::new(raw_key("hunter2")));
+        let new_cached_header = || {
+            new_header(
+                CipherType::AesGcm,
+                CipherMode::MemoryMode,
+                MIN_BLOCK_SIZE,
+                EncryptionKey::Cached {
+                    cache: cache.clone(),
+                    kdf_params: KDF_PARAMS,
+                },
+            )
+            .unwrap()
+        };
+
+        let (first, first_key) = new_cached_header();
+        let (second, second_key) = new_cached_header();
+
+        match (keyslots(&first), keyslots(&second)) {
+            (
+                [Keyslot::PasswordSubkey {
+                    salt: first_salt,
+                    subkey_salt: first_subkey_salt,
+                    ..
+                }],
+                [Keyslot::PasswordSubkey {
+                    salt: second_salt,
+                    subkey_salt: second_subkey_salt,
+                    ..
+                }],
+            ) => {
+                assert_eq!(first_salt, second_salt);
+                assert_ne!(first_subkey_salt, second_subkey_salt);
+            }
+            _ => panic!("cached keys should add password subkey key slots"),
+        }
+        assert_eq!(cache.hashes.lock().unwrap().len(), 1);
+
+        for (header, master_key) in [(&first, &first_key), (&second, &second_key)] {
+            let (_, unlocked) = unlock_with(header, "hunter2").unwrap();
+            assert_eq!(unlocked.expose_secret(), master_key.expose_secret());
+
+            let (_, unlocked) =
+                unlock_keyslot(header, DecryptionKey::Cached(cache.clone())).unwrap();
+            assert_eq!(unlocked.expose_secret(), master_key.expose_secret());
+        }
+    }
+
+    #[test]
+    fn keyslots_are_limited() {
+        let (mut header, master_key) = new_password_header("hunter2");
+        let recipient = Identity::generate().recipient();
+
+        add_keyslot(
+            &mut header,
+            &master_key,
+            EncryptionKey::Recipients(vec![recipient.clone(); MAX_KEYSLOTS - 1]),
+        )
+        .unwrap();
+        assert_eq!(keyslots(&header).len(), MAX_KEYSLOTS);
+
+        assert!(matches!(
+            add_keyslot(
+                &mut header,
+                &master_key,
+                EncryptionKey::Recipients(vec![recipient])
+            ),
+            Err(Error::TooManyKeyslots)
+        ));
+    }
+
+    #[test]
+    fn password_keyslots_are_limited() {
+        let (mut header, master_key) = new_password_header("hunter2");
+        for _ in 1..MAX_PASSWORD_KEYSLOTS {
+            add_keyslot(&mut header, &master_key, password("correct horse")).unwrap();
+        }
+
+        assert!(matches!(
+            add_keyslot(&mut header, &master_key, password("correct horse")),
+            Err(Error::KdfCostTooHigh)
+        ));
+
+        // recipients don't need any argon2id work
+        add_keyslot(
+            &mut header,
+            &master_key,
+            EncryptionKey::Recipients(vec![Identity::generate().recipient()]),
+        )
+        .unwrap();
+
+        // a key slot can't be changed to parameters that would take the total over the limit
+        let expensive = KdfParams {
+            m_cost: MAX_M_COST,
+            t_cost: MAX_T_COST,
+            p_cost: 1,
+        };
+        assert!(matches!(
+            change_keyslot(&mut header, 0, &master_key, raw_key("x"), expensive),
+            Err(Error::KdfCostTooHigh)
+        ));
+        assert!(unlock_with(&header, "hunter2").is_ok());
+    }
+
+    // older files derive the key directly from the password, so they don't have any key slots to manage
+    #[test]
+    fn derived_headers_have_no_keyslots() {
+        let salt = [1u8; SALT_LEN];
+        let mut header = Header {
+            version: HeaderVersion::V3,
+            cipher_type: CipherType::XChaCha20Poly1305,
+            cipher_mode: CipherMode::MemoryMode,
+            key_material: KeyMaterial::Derived {
+                kdf: KdfType::Argon2id,
+                kdf_params: KDF_PARAMS,
+                salt,
+            },
+            nonce: vec![0u8; nonce_len(CipherType::XChaCha20Poly1305, CipherMode::MemoryMode)],
+            block_size: BLOCK_SIZE,
+            content: ContentType::Unspecified,
+        };
+
+        assert!(!has_key_check(&header));
+
+        let key = unlock(&header, DecryptionKey::Password(raw_key("hunter2"))).unwrap();
+        let expected = derive_key(raw_key("hunter2"), &salt, KDF_PARAMS).unwrap();
+        assert_eq!(key.expose_secret(), expected.expose_secret());
+
+        assert!(matches!(
+            unlock(&header, DecryptionKey::Identity(Identity::generate())),
+            Err(Error::WrongKeyType(_))
+        ));
+        assert!(matches!(
+            unlock_with(&header, "hunter2"),
+            Err(Error::KeyslotsUnsupported)
+        ));
+        assert!(matches!(
+            add_keyslot(&mut header, &key, password("x")),
+            Err(Error::KeyslotsUnsupported)
+        ));
+        assert!(matches!(
+            remove_keyslot(&mut header, 0),
+            Err(Error::KeyslotsUnsupported)
+        ));
+        assert!(matches!(
+            change_keyslot(&mut header, 0, &key, raw_key("x"), KDF_PARAMS),
+            Err(Error::KeyslotsUnsupported)
+        ));
+    }
+}
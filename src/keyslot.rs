@@ -0,0 +1,215 @@
+use crate::failure::Failure;
+use crate::file::create_temp_output;
+use crate::file::persist_temp_output;
+use crate::global::Parameters;
+use crate::key::get_decryption_key;
+use crate::key::get_new_user_key;
+use crate::report;
+use anyhow::{Context, Ok, Result};
+use dexios::{EncryptionKey, Header, KeyMaterial};
+use std::fs::{File, OpenOptions};
+use std::io::{Seek, SeekFrom};
+
+// this file handles the `key` subcommands, which manage the key slots of an encrypted file
+// the master key that encrypts the data never changes, so only the header needs to be rewritten
+// the encrypted data is copied across as-is, it's never decrypted or re-encrypted
+
+// this reads the header from the start of an encrypted file
+fn read_header(input: &str) -> Result<Header> {
+    let mut file =
+        File::open(input).with_context(|| format!("Unable to open input file: {}", input))?;
+    Header::deserialize(&mut file)
+        .with_context(|| format!("Unable to read the header from file: {}", input))
+}
+
+// this writes the new header, followed by the file's encrypted data, to a temporary file
+// the temporary file then replaces the original, so the original is left untouched if anything goes wrong
+fn rewrite_header(input: &str, old_header: &Header, new_header: &Header) -> Result<()> {
+    let mut input_file =
+        File::open(input).with_context(|| format!("Unable to open input file: {}", input))?;
+    let permissions = input_file
+        .metadata()
+        .with_context(|| format!("Unable to get input file metadata: {}", input))?
+        .permissions();
+
+    input_file
+        .seek(SeekFrom::Start(old_header.serialize().len() as u64))
+        .with_context(|| format!("Unable to seek past the header in file: {}", input))?;
+
+    let (temp_name, mut temp_file) = create_temp_output(input)?;
+
+    let result = new_header
+        .write(&mut temp_file)
+        .context("Unable to write the new header")
+        .and_then(|_| {
+            std::io::copy(&mut input_file, &mut temp_file)
+                .context("Unable to copy the encrypted data")
+        })
+        .and_then(|_| {
+            temp_file
+                .set_permissions(permissions)
+                .context("Unable to set the file's permissions")
+        });
+
+    if let Err(err) = result {
+        drop(temp_file);
+        std::fs::remove_file(&temp_name).ok();
+        return Err(err.context(format!("Unable to rewrite the header of {}", input)));
+    }
+
+    persist_temp_output(&temp_name, temp_file, input)
+}
+
+// this overwrites the header at the start of the file, leaving the encrypted data untouched
+// it's only used when the new header is exactly the same size as the old one
+fn write_header_in_place(input: &str, new_header: &Header) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .open(input)
+        .with_context(|| format!("Unable to open input file for writing: {}", input))?;
+
+    new_header
+        .write(&mut file)
+        .with_context(|| format!("Unable to write the new header to {}", input))?;
+    file.sync_all()
+        .with_context(|| format!("Unable to flush the new header to {}", input))?;
+
+    Ok(())
+}
+
+// this unlocks the file with the old password/keyfile, and then re-wraps that key slot with a new one
+// the key slot usually stays the same size, so the header is rewritten in place and the encrypted data is never touched
+// if its size does change, the whole file is copied through a temporary file instead
+pub fn change_key(
+    input: &str,
+    keyfile: &str,
+    new_keyfile: &str,
+    params: &Parameters,
+) -> Result<()> {
+    let header = read_header(input)?;
+    if let KeyMaterial::Derived { .. } = header.key_material {
+        return Err(anyhow::anyhow!(
+            "{} doesn't have key slots, as it was encrypted by an older version of dexios ({} header)",
+            input,
+            header.version
+        )
+        .context(Failure::InvalidArguments));
+    }
+
+    let key = get_decryption_key(keyfile, &header, params)?;
+    let (index, master_key) = dexios::keys::unlock_keyslot(&header, key)
+        .with_context(|| format!("Unable to unlock {}", input))?;
+    eprintln!("Unlocked {} with key slot {}", input, index);
+
+    let new_key = get_new_user_key(new_keyfile)?;
+
+    let mut new_header = header.clone();
+    dexios::keys::change_keyslot(
+        &mut new_header,
+        index,
+        &master_key,
+        new_key,
+        params.kdf_params,
+    )?;
+    drop(master_key);
+
+    if new_header.serialize().len() == header.serialize().len() {
+        write_header_in_place(input, &new_header)?;
+    } else {
+        rewrite_header(input, &header, &new_header)?;
+    }
+
+    report::record("input", input);
+    report::record("keyslot", index);
+    report::print_result(&format!(
+        "Changed the key in key slot {} of {}",
+        index, input
+    ));
+    Ok(())
+}
+
+// this unlocks the file with an existing key, and then adds a new key slot for the new key
+// the new key may be a password/keyfile, or one or more recipients
+pub fn add_key(input: &str, keyfile: &str, new_keyfile: &str, params: &Parameters) -> Result<()> {
+    let header = read_header(input)?;
+    if let KeyMaterial::Derived { .. } = header.key_material {
+        return Err(anyhow::anyhow!(
+            "{} doesn't have key slots, as it was encrypted by an older version of dexios ({} header)",
+            input,
+            header.version
+        )
+        .context(Failure::InvalidArguments));
+    }
+
+    let key = get_decryption_key(keyfile, &header, params)?;
+    let (index, master_key) = dexios::keys::unlock_keyslot(&header, key)
+        .with_context(|| format!("Unable to unlock {}", input))?;
+    eprintln!("Unlocked {} with key slot {}", input, index);
+
+    let new_key = if params.recipients.is_empty() {
+        EncryptionKey::Password {
+            raw_key: get_new_user_key(new_keyfile)?,
+            kdf_params: params.kdf_params,
+        }
+    } else {
+        EncryptionKey::Recipients(params.recipients.clone())
+    };
+
+    let mut new_header = header.clone();
+    dexios::keys::add_keyslot(&mut new_header, &master_key, new_key)?;
+    drop(master_key);
+
+    rewrite_header(input, &header, &new_header)?;
+
+    eprintln!("Added a new key to {}", input);
+    list_keys(input)
+}
+
+// this removes a key slot from the file
+// an existing key is required to do this, and the last key slot can never be removed
+pub fn delete_key(input: &str, keyfile: &str, index: usize, params: &Parameters) -> Result<()> {
+    let header = read_header(input)?;
+
+    let key = get_decryption_key(keyfile, &header, params)?;
+    let (unlocked_index, master_key) = dexios::keys::unlock_keyslot(&header, key)
+        .with_context(|| format!("Unable to unlock {}", input))?;
+    drop(master_key);
+    eprintln!("Unlocked {} with key slot {}", input, unlocked_index);
+
+    let mut new_header = header.clone();
+    let removed = dexios::keys::remove_keyslot(&mut new_header, index)
+        .with_context(|| format!("Unable to remove key slot {} from {}", index, input))?;
+
+    rewrite_header(input, &header, &new_header)?;
+
+    eprintln!("Removed key slot {} ({}) from {}", index, removed, input);
+    list_keys(input)
+}
+
+// this lists the key slots of a file
+// no key is required, as the key slots only contain wrapped keys
+pub fn list_keys(input: &str) -> Result<()> {
+    let header = read_header(input)?;
+    report::record("input", input);
+
+    match &header.key_material {
+        KeyMaterial::Derived { .. } => {
+            report::record("keyslots", serde_json::Value::Null);
+            report::print_result(&format!(
+                "{} doesn't have key slots, as it was encrypted by an older version of dexios ({} header, {})",
+                input, header.version, header.key_material
+            ));
+        }
+        KeyMaterial::Wrapped(keyslots) => {
+            let descriptions: Vec<String> = keyslots.iter().map(ToString::to_string).collect();
+            report::print_result(&format!("Key slots for {}:", input));
+            for (index, keyslot) in descriptions.iter().enumerate() {
+                report::print_result(&format!("  {}: {}", index, keyslot));
+            }
+
+            report::record("keyslots", descriptions);
+        }
+    }
+
+    Ok(())
+}
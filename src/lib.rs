@@ -0,0 +1,31 @@
+//! The dexios library contains the core of dexios - the ciphers, the header format, key derivation and recipient keys.
+//! It allows data to be encrypted and decrypted (in either memory or stream mode) over anything that implements `Read`/`Write`.
+//! The dexios command-line tool is a thin wrapper around this library.
+
+pub mod cipher;
+pub mod error;
+pub mod header;
+pub mod kdf;
+pub mod keys;
+pub mod memory;
+pub mod progress;
+pub mod recipient;
+pub mod stream;
+
+#[cfg(test)]
+mod test_utils;
+
+pub use cipher::{CipherMode, CipherType};
+pub use error::{Error, Result};
+pub use header::{ContentType, Header, HeaderMode, HeaderVersion, KeyMaterial, Keyslot};
+pub use kdf::KdfParams;
+pub use keys::{DecryptionKey, EncryptionKey, KeyCache};
+pub use progress::ProgressReader;
+pub use recipient::{Identity, Recipient};
+
+// these are constants that are used throughout the codebase
+// changing these will break compatibility with files created by other builds of dexios
+pub const BLOCK_SIZE: usize = 1_048_576; // 1024*1024 bytes, the default (and the only block size used by V4 headers and below)
+pub const MIN_BLOCK_SIZE: usize = 65_536; // 64KiB
+pub const MAX_BLOCK_SIZE: usize = 67_108_864; // 64MiB
+pub const SALT_LEN: usize = 16; // bytes
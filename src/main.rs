@@ -1,104 +1,203 @@
 use anyhow::{Context, Result};
-use global::{DirectoryMode, BLOCK_SIZE};
+use dexios::BLOCK_SIZE;
+use failure::Failure;
+use global::DirectoryMode;
+use global::OwnershipMode;
+use global::PackFormat;
 use param_handler::param_handler;
+use std::process::exit;
 use std::result::Result::Ok;
+use std::time::Instant;
 
+mod batch;
 mod cli;
 mod decrypt;
 mod encrypt;
 mod erase;
+mod failure;
 mod file;
 mod global;
 mod hashing;
+mod in_place;
+mod info;
 mod key;
+mod keygen;
+mod keyslot;
 mod pack;
 mod param_handler;
+mod progress_bar;
 mod prompt;
+mod report;
+mod tar_archive;
+mod verify;
+mod zip_writer;
 
-#[allow(clippy::too_many_lines)]
-fn main() -> Result<()> {
+// this runs the requested subcommand, and then prints the json report (if --json was specified)
+// each kind of failure has its own exit code (see failure.rs)
+fn main() {
     let matches = cli::get_matches();
 
+    if matches.is_present("json") {
+        report::enable_json();
+    }
+
+    let start_time = Instant::now();
+    let result = run(&matches);
+    report::finish(&operation_name(&matches), start_time.elapsed(), &result);
+
+    if let Err(err) = result {
+        eprintln!("Error: {:?}", err);
+        exit(Failure::of(&err).exit_code());
+    }
+}
+
+// this returns the full name of the subcommand, e.g. "key add"
+fn operation_name(matches: &clap::ArgMatches) -> String {
+    let mut names = Vec::new();
+    let mut current = matches;
+    while let Some((name, sub_matches)) = current.subcommand() {
+        names.push(name);
+        current = sub_matches;
+    }
+    names.join(" ")
+}
+
+#[allow(clippy::too_many_lines)]
+fn run(matches: &clap::ArgMatches) -> Result<()> {
     match matches.subcommand() {
         Some(("encrypt", sub_matches)) => {
             let (keyfile, params) = param_handler(sub_matches)?;
+            let input = sub_matches
+                .value_of("input")
+                .context("No input file/invalid text provided")?;
+            let memory = sub_matches.is_present("memory");
 
-            let result = if sub_matches.is_present("memory") {
-                crate::encrypt::memory_mode(
-                    sub_matches
-                        .value_of("input")
-                        .context("No input file/invalid text provided")?,
-                    sub_matches
-                        .value_of("output")
-                        .context("No output file/invalid text provided")?,
-                    keyfile,
-                    &params,
-                )
-            } else {
-                crate::encrypt::stream_mode(
-                    sub_matches
-                        .value_of("input")
-                        .context("No input file/invalid text provided")?,
-                    sub_matches
-                        .value_of("output")
-                        .context("No output file/invalid text provided")?,
-                    keyfile,
-                    &params,
-                )
+            let encrypt = |input: &str, output: &str, params: &global::Parameters| {
+                if memory {
+                    crate::encrypt::memory_mode(input, output, keyfile, params)
+                } else {
+                    crate::encrypt::stream_mode(input, output, keyfile, params)
+                }
             };
 
-            return result;
+            if sub_matches.is_present("in-place") {
+                return crate::in_place::replace(input, params, encrypt);
+            }
+
+            return encrypt(
+                input,
+                sub_matches
+                    .value_of("output")
+                    .context("No output file/invalid text provided")?,
+                &params,
+            );
         }
         Some(("decrypt", sub_matches)) => {
             let (keyfile, params) = param_handler(sub_matches)?;
+            let input = sub_matches
+                .value_of("input")
+                .context("No input file/invalid text provided")?;
+            let memory = sub_matches.is_present("memory");
 
-            let result = if sub_matches.is_present("memory") {
-                crate::decrypt::memory_mode(
-                    sub_matches
-                        .value_of("input")
-                        .context("No input file/invalid text provided")?,
-                    sub_matches
-                        .value_of("output")
-                        .context("No output file/invalid text provided")?,
-                    keyfile,
-                    &params,
-                )
-            } else {
-                crate::decrypt::stream_mode(
-                    sub_matches
-                        .value_of("input")
-                        .context("No input file/invalid text provided")?,
-                    sub_matches
-                        .value_of("output")
-                        .context("No output file/invalid text provided")?,
-                    keyfile,
-                    &params,
-                )
+            let decrypt = |input: &str, output: &str, params: &global::Parameters| {
+                crate::decrypt::decrypt(input, output, keyfile, memory, params)
             };
 
-            return result;
+            if sub_matches.is_present("in-place") {
+                return crate::in_place::replace(input, params, decrypt);
+            }
+
+            return decrypt(
+                input,
+                sub_matches
+                    .value_of("output")
+                    .context("No output file/invalid text provided")?,
+                &params,
+            );
+        }
+        Some(("batch", sub_matches)) => {
+            let (name, sub_matches) = sub_matches
+                .subcommand()
+                .context("No batch subcommand provided")?;
+            let (keyfile, params) = param_handler(sub_matches)?;
+
+            let inputs: Vec<&str> = sub_matches
+                .values_of("input")
+                .context("No input files/invalid text provided")?
+                .collect();
+            let suffix = sub_matches.value_of("suffix").unwrap_or_default();
+            let output_dir = sub_matches.value_of("output-dir");
+            let memory = sub_matches.is_present("memory");
+
+            return if name == "encrypt" {
+                crate::batch::encrypt(&inputs, suffix, output_dir, keyfile, memory, params)
+            } else {
+                crate::batch::decrypt(&inputs, suffix, output_dir, keyfile, memory, params)
+            };
         }
         Some(("erase", sub_matches)) => {
             let passes = if sub_matches.is_present("passes") {
-                let result = sub_matches
-                    .value_of("passes")
-                    .context("No amount of passes specified")?
-                    .parse::<i32>();
-                if let Ok(value) = result {
-                    value
-                } else {
-                    println!("Unable to read number of passes provided - using the default.");
-                    16
-                }
+                param_handler::parse_passes(
+                    sub_matches
+                        .value_of("passes")
+                        .context("No amount of passes specified")?,
+                )
+                .context(Failure::InvalidArguments)?
             } else {
-                println!("Number of passes not provided - using the default.");
+                eprintln!("Number of passes not provided - using the default.");
                 16
             };
-            erase::secure_erase(
+            let input = sub_matches
+                .value_of("input")
+                .context("No input file/invalid text provided")?;
+
+            let pattern = match sub_matches.value_of("pattern") {
+                Some("zeros") => global::ErasePattern::Zeros(passes),
+                Some("dod") => global::ErasePattern::Dod,
+                Some("gutmann") => global::ErasePattern::Gutmann,
+                _ => global::ErasePattern::Random(passes),
+            };
+
+            let verify = if sub_matches.is_present("verify") {
+                global::EraseVerifyMode::VerifyPasses
+            } else {
+                global::EraseVerifyMode::SkipVerification
+            };
+
+            let mode = if sub_matches.is_present("recursive") {
+                DirectoryMode::Recursive
+            } else {
+                DirectoryMode::Singular
+            };
+
+            let excluded: Vec<&str> = sub_matches
+                .values_of("exclude")
+                .map(Iterator::collect)
+                .unwrap_or_default();
+
+            erase::erase(input, mode, &excluded, pattern, verify)?;
+
+            report::record("input", input);
+            report::record("pattern", sub_matches.value_of("pattern").unwrap_or("random"));
+            report::record("passes", passes);
+        }
+        Some(("verify", sub_matches)) => {
+            let (keyfile, params) = param_handler(sub_matches)?;
+
+            verify::verify(
+                sub_matches
+                    .value_of("input")
+                    .context("No input file/invalid text provided")?,
+                keyfile,
+                sub_matches.is_present("memory"),
+                &params,
+            )?;
+        }
+        Some(("info", sub_matches)) => {
+            info::show_info(
                 sub_matches
                     .value_of("input")
                     .context("No input file/invalid text provided")?,
-                passes,
             )?;
         }
         Some(("hash", sub_matches)) => {
@@ -115,7 +214,7 @@ fn main() -> Result<()> {
                     .try_into()
                     .context("Unable to parse stream block size as u64")?
             {
-                println!("Input file size is less than the stream block size - redirecting to memory mode");
+                eprintln!("Input file size is less than the stream block size - redirecting to memory mode");
                 hashing::hash_memory(file_name)?;
             } else {
                 hashing::hash_stream(file_name)?;
@@ -136,14 +235,14 @@ fn main() -> Result<()> {
                         .parse();
             
                     let passes = if let Ok(value) = result {
-                        if value > 9 || value < 1 {
-                            println!("Compression level is out of specified bounds - using the default (6).");
+                        if !(1..=9).contains(&value) {
+                            eprintln!("Compression level is out of specified bounds - using the default (6).");
                             6
                         } else {
                             value
                         }
                     } else {
-                        println!("Unable to read compression level provided - using the default (6).");
+                        eprintln!("Unable to read compression level provided - using the default (6).");
                         6
                     };
                     passes
@@ -157,6 +256,12 @@ fn main() -> Result<()> {
                     Vec::new()
                 };
 
+                let format = if sub_matches.value_of("format") == Some("tar") {
+                    PackFormat::Tar
+                } else {
+                    PackFormat::Zip
+                };
+
                 let sub_matches_encrypt = sub_matches.subcommand_matches("encrypt").unwrap();
 
                 let (keyfile, params) = param_handler(sub_matches_encrypt)?;
@@ -171,6 +276,7 @@ fn main() -> Result<()> {
                     &excluded,
                     keyfile,
                     mode,
+                    format,
                     sub_matches_encrypt.is_present("memory"),
                     compression_level,
                     &params,
@@ -181,6 +287,12 @@ fn main() -> Result<()> {
 
                 let (keyfile, params) = param_handler(sub_matches_decrypt)?;
 
+                let ownership = if sub_matches.is_present("preserve-owner") {
+                    OwnershipMode::RestoreOwnership
+                } else {
+                    OwnershipMode::IgnoreOwnership
+                };
+
                 pack::decrypt_directory(
                     sub_matches_decrypt
                         .value_of("input")
@@ -190,11 +302,89 @@ fn main() -> Result<()> {
                         .context("No output file/invalid text provided")?,
                     keyfile,
                     sub_matches_decrypt.is_present("memory"),
+                    ownership,
                     &params,
                 )?;
             }
             _ => (),
         },
+        Some(("key", sub_matches)) => match sub_matches.subcommand() {
+            Some(("add", sub_matches_add)) => {
+                let (keyfile, params) = param_handler(sub_matches_add)?;
+                let new_keyfile = sub_matches_add.value_of("new-keyfile").unwrap_or_default();
+
+                keyslot::add_key(
+                    sub_matches_add
+                        .value_of("input")
+                        .context("No input file/invalid text provided")?,
+                    keyfile,
+                    new_keyfile,
+                    &params,
+                )?;
+            }
+            Some(("change", sub_matches_change)) => {
+                let (keyfile, params) = param_handler(sub_matches_change)?;
+                let new_keyfile = sub_matches_change
+                    .value_of("new-keyfile")
+                    .unwrap_or_default();
+
+                keyslot::change_key(
+                    sub_matches_change
+                        .value_of("input")
+                        .context("No input file/invalid text provided")?,
+                    keyfile,
+                    new_keyfile,
+                    &params,
+                )?;
+            }
+            Some(("del", sub_matches_del)) => {
+                let (keyfile, params) = param_handler(sub_matches_del)?;
+                let slot = sub_matches_del
+                    .value_of("slot")
+                    .context("No key slot provided")?;
+                let index = slot
+                    .parse()
+                    .with_context(|| format!("Unable to read the key slot: {}", slot))?;
+
+                keyslot::delete_key(
+                    sub_matches_del
+                        .value_of("input")
+                        .context("No input file/invalid text provided")?,
+                    keyfile,
+                    index,
+                    &params,
+                )?;
+            }
+            Some(("list", sub_matches_list)) => {
+                keyslot::list_keys(
+                    sub_matches_list
+                        .value_of("input")
+                        .context("No input file/invalid text provided")?,
+                )?;
+            }
+            _ => (),
+        },
+        Some(("keygen", sub_matches)) => {
+            let skip = if sub_matches.is_present("skip") {
+                global::SkipMode::HidePrompts
+            } else {
+                global::SkipMode::ShowPrompts
+            };
+
+            let identity_type = if sub_matches.is_present("pq") {
+                global::IdentityType::HybridPostQuantum
+            } else {
+                global::IdentityType::X25519
+            };
+
+            keygen::generate_identity(
+                sub_matches
+                    .value_of("output")
+                    .context("No output file/invalid text provided")?,
+                skip,
+                identity_type,
+            )?;
+        }
         _ => (),
     }
     Ok(())
@@ -0,0 +1,351 @@
+use crate::cipher::{nonce_len, CipherMode, CipherType, MemoryCiphers};
+use crate::error::{Error, Result};
+use crate::header::{ContentType, Header};
+use crate::keys::{has_key_check, new_header_with_content, unlock, DecryptionKey, EncryptionKey};
+use crate::BLOCK_SIZE;
+use aead::Payload;
+use secrecy::{ExposeSecret, Secret};
+use std::io::{Read, Write};
+
+// this encrypts data in memory mode
+// it takes the data, the key (a password/keyfile, or recipients) and the cipher
+// it builds the header (which generates the 12/24 byte nonce), and gets the key from it
+// the data is encrypted with the header as associated data, so the header is authenticated too
+// it returns the header (containing the nonce and key material), the encrypted bytes and the master key (so the output can be verified with DecryptionKey::MasterKey)
+pub fn encrypt_bytes(
+    data: &[u8],
+    key: EncryptionKey,
+    cipher_type: CipherType,
+) -> Result<(Header, Vec<u8>, Secret<[u8; 32]>)> {
+    encrypt_bytes_with_content(data, key, cipher_type, ContentType::Unspecified)
+}
+
+// this is the same as encrypt_bytes(), but it also records what the data contains within the header
+pub fn encrypt_bytes_with_content(
+    data: &[u8],
+    key: EncryptionKey,
+    cipher_type: CipherType,
+    content: ContentType,
+) -> Result<(Header, Vec<u8>, Secret<[u8; 32]>)> {
+    let (header, master_key) = new_header_with_content(
+        cipher_type,
+        CipherMode::MemoryMode,
+        BLOCK_SIZE,
+        key,
+        content,
+    )?;
+
+    let cipher = MemoryCiphers::initialize(Secret::new(*master_key.expose_secret()), cipher_type)?;
+
+    let aad = header.aad();
+    let payload = Payload {
+        aad: &aad,
+        msg: data,
+    };
+
+    let encrypted_bytes = cipher
+        .encrypt(&header.nonce, payload)
+        .map_err(|_| Error::Encrypt)?;
+
+    Ok((header, encrypted_bytes, master_key))
+}
+
+// this decrypts data in memory mode
+// it takes the header, the data and the key (a password/keyfile, or an identity)
+// it unlocks the key with the key material from the header, and decrypts all of the data with the header's nonce
+// the header is also authenticated as associated data (for V2 headers and above)
+// if the key was already checked by a key slot, a failure means that the data is corrupt (CorruptBlock), otherwise it may just be the wrong key (WrongKeyOrCorrupt)
+// it returns the decrypted bytes
+pub fn decrypt_bytes(header: &Header, data: &[u8], key: DecryptionKey) -> Result<Vec<u8>> {
+    if header.nonce.len() != nonce_len(header.cipher_type, CipherMode::MemoryMode) {
+        return Err(Error::InvalidHeader("the nonce is the wrong length"));
+    }
+
+    let key = unlock(header, key)?;
+    let cipher = MemoryCiphers::initialize(key, header.cipher_type)?;
+
+    let aad = header.aad();
+    let payload = Payload {
+        aad: &aad,
+        msg: data,
+    };
+
+    cipher.decrypt(&header.nonce, payload).map_err(|_| {
+        if has_key_check(header) {
+            Error::CorruptBlock {
+                index: 0,
+                offset: header.serialize().len() as u64,
+            }
+        } else {
+            Error::WrongKeyOrCorrupt
+        }
+    })
+}
+
+// this reads all of the input, encrypts it in memory mode and writes the header + encrypted data to the output
+// it returns the header that was written, along with the master key
+pub fn encrypt(
+    input: &mut impl Read,
+    output: &mut impl Write,
+    key: EncryptionKey,
+    cipher_type: CipherType,
+) -> Result<(Header, Secret<[u8; 32]>)> {
+    let mut data = Vec::new();
+    input.read_to_end(&mut data)?;
+    let data = Secret::new(data);
+
+    let (header, encrypted_bytes, master_key) =
+        encrypt_bytes(data.expose_secret(), key, cipher_type)?;
+    drop(data);
+
+    header.write(output)?;
+    output.write_all(&encrypted_bytes)?;
+    output.flush()?;
+
+    Ok((header, master_key))
+}
+
+// this reads the rest of the input (the header should have already been read), decrypts it and writes it to the output
+// nothing is written to the output unless the data was successfully authenticated
+pub fn decrypt(
+    input: &mut impl Read,
+    output: &mut impl Write,
+    header: &Header,
+    key: DecryptionKey,
+) -> Result<()> {
+    let mut encrypted_data = Vec::new();
+    input.read_to_end(&mut encrypted_data)?;
+
+    let decrypted_bytes = Secret::new(decrypt_bytes(header, &encrypted_data, key)?);
+
+    output.write_all(decrypted_bytes.expose_secret())?;
+    output.flush()?;
+
+    Ok(())
+}
+
+// this checks that data encrypted in memory mode is intact, without writing any of the decrypted data
+// the data is a single AEAD message, so CorruptBlock always refers to the first (and only) block
+// files without a key check can't tell a wrong key apart from corrupt data, so WrongKeyOrCorrupt is returned for them instead
+// it returns the length of the decrypted data
+pub fn verify(input: &mut impl Read, header: &Header, key: DecryptionKey) -> Result<u64> {
+    let mut encrypted_data = Vec::new();
+    input.read_to_end(&mut encrypted_data)?;
+
+    let decrypted_bytes = Secret::new(decrypt_bytes(header, &encrypted_data, key)?);
+
+    Ok(decrypted_bytes.expose_secret().len() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::{HeaderVersion, KdfType, KeyMaterial};
+    use crate::kdf::derive_key;
+    use crate::recipient::Identity;
+    use crate::test_utils::{data, password, raw_key, KDF_PARAMS};
+    use crate::SALT_LEN;
+
+    fn encrypt_with_password(cipher_type: CipherType) -> (Header, Vec<u8>) {
+        let (header, encrypted, _) =
+            encrypt_bytes(&data(100), password("hunter2"), cipher_type).unwrap();
+        (header, encrypted)
+    }
+
+    #[test]
+    fn password_round_trip() {
+        for cipher_type in [CipherType::XChaCha20Poly1305, CipherType::AesGcm] {
+            let (header, encrypted) = encrypt_with_password(cipher_type);
+            assert_eq!(header.version, HeaderVersion::V5);
+            assert_eq!(header.cipher_type, cipher_type);
+            assert_eq!(encrypted.len(), 100 + 16);
+
+            let decrypted = decrypt_bytes(
+                &header,
+                &encrypted,
+                DecryptionKey::Password(raw_key("hunter2")),
+            );
+            assert_eq!(decrypted.unwrap(), data(100));
+        }
+    }
+
+    #[test]
+    fn recipient_and_master_key_round_trip() {
+        for identity in [Identity::generate(), Identity::generate_hybrid().unwrap()] {
+            let key = EncryptionKey::Recipients(vec![identity.recipient()]);
+            let (header, encrypted, master_key) =
+                encrypt_bytes(&data(100), key, CipherType::AesGcm).unwrap();
+            assert_eq!(header.version, HeaderVersion::V5);
+
+            let decrypted = decrypt_bytes(&header, &encrypted, DecryptionKey::Identity(identity));
+            assert_eq!(decrypted.unwrap(), data(100));
+
+            let decrypted =
+                decrypt_bytes(&header, &encrypted, DecryptionKey::MasterKey(master_key));
+            assert_eq!(decrypted.unwrap(), data(100));
+        }
+    }
+
+    #[test]
+    fn reader_and_writer_round_trip() {
+        let data = data(1000);
+        let mut encrypted = Vec::new();
+        let (header, _) = encrypt(
+            &mut data.as_slice(),
+            &mut encrypted,
+            password("hunter2"),
+            CipherType::AesGcm,
+        )
+        .unwrap();
+
+        let mut reader = encrypted.as_slice();
+        let read = Header::deserialize(&mut reader).unwrap();
+        assert_eq!(read.serialize(), header.serialize());
+
+        let mut decrypted = Vec::new();
+        decrypt(
+            &mut reader,
+            &mut decrypted,
+            &read,
+            DecryptionKey::Password(raw_key("hunter2")),
+        )
+        .unwrap();
+        assert_eq!(decrypted, data);
+
+        let mut reader = &encrypted[header.serialize().len()..];
+        let len = verify(
+            &mut reader,
+            &read,
+            DecryptionKey::Password(raw_key("hunter2")),
+        );
+        assert_eq!(len.unwrap(), data.len() as u64);
+    }
+
+    #[test]
+    fn wrong_password_is_rejected() {
+        let (header, encrypted) = encrypt_with_password(CipherType::XChaCha20Poly1305);
+
+        let result = decrypt_bytes(
+            &header,
+            &encrypted,
+            DecryptionKey::Password(raw_key("hunter3")),
+        );
+        assert!(matches!(result, Err(Error::NoMatchingKeyslot)));
+    }
+
+    #[test]
+    fn tampered_data_is_corrupt() {
+        let (header, encrypted) = encrypt_with_password(CipherType::XChaCha20Poly1305);
+        let header_len = header.serialize().len() as u64;
+
+        for index in [0, 50, encrypted.len() - 1] {
+            let mut tampered = encrypted.clone();
+            tampered[index] ^= 1;
+
+            let result = decrypt_bytes(
+                &header,
+                &tampered,
+                DecryptionKey::Password(raw_key("hunter2")),
+            );
+            assert!(matches!(
+                result,
+                Err(Error::CorruptBlock { index: 0, offset }) if offset == header_len
+            ));
+
+            let result = verify(
+                &mut tampered.as_slice(),
+                &header,
+                DecryptionKey::Password(raw_key("hunter2")),
+            );
+            assert!(matches!(
+                result,
+                Err(Error::CorruptBlock { index: 0, offset }) if offset == header_len
+            ));
+        }
+
+        let result = decrypt_bytes(
+            &header,
+            &encrypted[..encrypted.len() - 1],
+            DecryptionKey::Password(raw_key("hunter2")),
+        );
+        assert!(matches!(result, Err(Error::CorruptBlock { .. })));
+    }
+
+    // the header is authenticated as associated data, so changing it makes the data fail to decrypt
+    #[test]
+    fn tampered_header_is_corrupt() {
+        let (header, encrypted) = encrypt_with_password(CipherType::XChaCha20Poly1305);
+
+        let mut tampered = header.clone();
+        tampered.nonce[0] ^= 1;
+        let result = decrypt_bytes(
+            &tampered,
+            &encrypted,
+            DecryptionKey::Password(raw_key("hunter2")),
+        );
+        assert!(matches!(result, Err(Error::CorruptBlock { .. })));
+
+        let mut tampered = header;
+        tampered.version = HeaderVersion::V2;
+        let result = decrypt_bytes(
+            &tampered,
+            &encrypted,
+            DecryptionKey::Password(raw_key("hunter2")),
+        );
+        assert!(matches!(result, Err(Error::CorruptBlock { .. })));
+    }
+
+    // files without key slots can't tell a wrong key apart from corrupt data
+    #[test]
+    fn derived_headers_report_wrong_key_or_corrupt() {
+        let header = Header {
+            version: HeaderVersion::V3,
+            cipher_type: CipherType::XChaCha20Poly1305,
+            cipher_mode: CipherMode::MemoryMode,
+            key_material: KeyMaterial::Derived {
+                kdf: KdfType::Argon2id,
+                kdf_params: KDF_PARAMS,
+                salt: [1u8; SALT_LEN],
+            },
+            nonce: vec![2u8; nonce_len(CipherType::XChaCha20Poly1305, CipherMode::MemoryMode)],
+            block_size: BLOCK_SIZE,
+            content: ContentType::Unspecified,
+        };
+
+        let key = derive_key(raw_key("hunter2"), &[1u8; SALT_LEN], KDF_PARAMS).unwrap();
+        let cipher = MemoryCiphers::initialize(key, header.cipher_type).unwrap();
+        let aad = header.aad();
+        let encrypted = cipher
+            .encrypt(
+                &header.nonce,
+                Payload {
+                    aad: &aad,
+                    msg: &data(100),
+                },
+            )
+            .unwrap();
+
+        let decrypted = decrypt_bytes(
+            &header,
+            &encrypted,
+            DecryptionKey::Password(raw_key("hunter2")),
+        );
+        assert_eq!(decrypted.unwrap(), data(100));
+
+        let result = decrypt_bytes(
+            &header,
+            &encrypted,
+            DecryptionKey::Password(raw_key("hunter3")),
+        );
+        assert!(matches!(result, Err(Error::WrongKeyOrCorrupt)));
+
+        let mut tampered = encrypted;
+        tampered[0] ^= 1;
+        let result = decrypt_bytes(
+            &header,
+            &tampered,
+            DecryptionKey::Password(raw_key("hunter2")),
+        );
+        assert!(matches!(result, Err(Error::WrongKeyOrCorrupt)));
+    }
+}
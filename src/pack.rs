@@ -1,140 +1,285 @@
 use std::{
     fs::File,
-    io::{Read, Write},
+    io::{Cursor, Read, Seek, Write},
     path::PathBuf,
     str::FromStr,
 };
 
 use anyhow::{Context, Result};
-use rand::distributions::{Alphanumeric, DistString};
-use zip::write::FileOptions;
+use dexios::{stream::DecryptReader, stream::EncryptWriter, CipherMode, ContentType};
+use secrecy::{ExposeSecret, Secret};
+use zip::ZipArchive;
 
 use crate::{
-    file::get_paths_in_dir,
-    global::{DirectoryMode, Parameters, SkipMode, BLOCK_SIZE},
-    prompt::get_answer,
+    decrypt::read_header,
+    failure::Failure,
+    file::{create_output, get_entries_in_dir, get_paths_in_dir, STDIO},
+    global::{
+        BenchMode, DirectoryMode, OutputFile, OwnershipMode, PackFormat, Parameters, SkipMode,
+    },
+    hashing::HashWriter,
+    key::{get_decryption_key, get_encryption_key},
+    prompt::{get_answer, overwrite_check},
+    zip_writer::ZipWriter,
 };
 
+// the archive is never written to the disk in plaintext
+// when packing, it's fed straight into the stream encryptor as it's written (or it's built in memory, for memory mode)
+// when unpacking, it's read straight out of the encrypted file, which is decrypted block-by-block as the zip reader seeks around it
+// tar archives are marked as such within the header, so they're unpacked with the right format automatically
+
+// zip archives need the files and directories separately, but tar archives list everything (including symlinks) in order
+enum Contents {
+    Zip(Vec<PathBuf>, Option<Vec<PathBuf>>),
+    Tar(Vec<PathBuf>),
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn encrypt_directory(
     input: &str,
     output: &str,
     exclude: &[&str],
     keyfile: &str,
     mode: DirectoryMode,
+    format: PackFormat,
     memory: bool,
     compression_level: i32,
     params: &Parameters,
 ) -> Result<()> {
-    let (files, dirs) = get_paths_in_dir(input, mode, exclude)?;
-    let random_extension: String = Alphanumeric.sample_string(&mut rand::thread_rng(), 8);
-
-    let tmp_name = format!("{}.{}", output, random_extension); // e.g. "output.kjHSD93l"
-
-    let file = File::create(&tmp_name)
-        .with_context(|| format!("Unable to create the output file: {}", output))?;
-    
-    println!("Creating zip called {} with a compression level of {}.", tmp_name, compression_level);
-    let mut zip = zip::ZipWriter::new(file);
-    let options = FileOptions::default()
-        .compression_method(zip::CompressionMethod::Bzip2)
-        .compression_level(Some(compression_level)) // this is the default anyway
-        .large_file(true)
-        .unix_permissions(0o755);
-
-    zip.add_directory(input, options)
-        .context("Unable to add directory to zip")?;
-
-    if mode == DirectoryMode::Recursive {
-        let directories = dirs.context("Error unwrapping Vec containing list of directories.")?; // this should always be *something* anyway
-        for dir in directories {
-            zip.add_directory(
-                dir.to_str()
-                    .context("Error converting directory path to string")?,
-                options,
-            )
-            .context("Unable to add directory to zip")?;
+    let (contents, content_type) = match format {
+        PackFormat::Zip => {
+            let (files, dirs) = get_paths_in_dir(input, mode, exclude)?;
+            (Contents::Zip(files, dirs), ContentType::Unspecified)
         }
+        PackFormat::Tar => (
+            Contents::Tar(get_entries_in_dir(input, mode, exclude)?),
+            ContentType::TarArchive,
+        ),
+    };
+    let compression_level = u32::try_from(compression_level)
+        .context("Invalid compression level")
+        .context(Failure::InvalidArguments)?;
+
+    if !overwrite_check(output, params.skip, params.bench)? {
+        return Err(Failure::Aborted.into());
     }
 
-    for file in files {
-        zip.start_file(
-            file.to_str()
-                .context("Error converting file path to string")?,
-            options,
-        )
-        .context("Unable to add file to zip")?;
-        println!("Compressing {} into {}", file.to_str().unwrap(), tmp_name);
-        let zip_writer = zip.by_ref();
-        let mut file_reader = File::open(file)?;
-        let file_size = file_reader.metadata().unwrap().len();
+    let key = get_encryption_key(keyfile, params)?;
 
-        if file_size <= BLOCK_SIZE.try_into().unwrap() {
-            let mut data = Vec::new();
-            file_reader.read_to_end(&mut data)?;
-            zip_writer.write_all(&data)?;
-        } else {
-            // stream read/write here
-            let mut buffer = [0u8; BLOCK_SIZE];
-
-            loop {
-                let read_count = file_reader.read(&mut buffer)?;
-                if read_count == BLOCK_SIZE {
-                    zip_writer
-                        .write_all(&buffer[..read_count])
-                        .with_context(|| {
-                            format!("Unable to write to the output file: {}", output)
-                        })?;
-                } else {
-                    zip_writer
-                        .write_all(&buffer[..read_count])
-                        .with_context(|| {
-                            format!("Unable to write to the output file: {}", output)
-                        })?;
-                    break;
-                }
-            }
-        }
-    }
-    zip.finish()?;
+    let mut output_file = if params.bench == BenchMode::WriteToFilesystem {
+        create_output(output)?
+    } else {
+        OutputFile::None
+    };
+    let mut writer = HashWriter::new(&mut output_file, params.hash_mode);
+
+    eprintln!(
+        "Packing {} into {} with {} and a compression level of {}.",
+        input, output, params.cipher_type, compression_level
+    );
 
     if memory {
-        crate::encrypt::memory_mode(&tmp_name, output, keyfile, params)?;
+        // the whole archive is built in memory, and then encrypted in one go
+        let archive = Secret::new(write_archive(
+            input,
+            Vec::new(),
+            &contents,
+            compression_level,
+        )?);
+        let (header, data, _) = dexios::memory::encrypt_bytes_with_content(
+            archive.expose_secret(),
+            key,
+            params.cipher_type,
+            content_type,
+        )?;
+        drop(archive);
+
+        header
+            .write(&mut writer)
+            .with_context(|| format!("Unable to write header to output file: {}", output))?;
+        writer
+            .write_all(&data)
+            .with_context(|| format!("Unable to write data to output file: {}", output))?;
+        writer
+            .flush()
+            .with_context(|| format!("Unable to flush the output file: {}", output))?;
     } else {
-        crate::encrypt::stream_mode(&tmp_name, output, keyfile, params)?;
-    };
+        let encrypt_writer = EncryptWriter::with_content(
+            &mut writer,
+            key,
+            params.cipher_type,
+            params.block_size,
+            content_type,
+        )?;
+        let encrypt_writer = write_archive(input, encrypt_writer, &contents, compression_level)?;
+        encrypt_writer
+            .finish()
+            .with_context(|| format!("Unable to write to the output file: {}", output))?;
+    }
 
-    crate::erase::secure_erase(&tmp_name, 16)?; // cleanup our tmp file
+    crate::report::record("bytes", writer.bytes());
+    if let Some(hash) = writer.finalize() {
+        eprintln!("Hash of the encrypted file is: {}", hash);
+        crate::report::record("hash", hash);
+    }
 
-    println!("Your output file is: {}", output);
+    crate::report::record("input", input);
+    crate::report::record("output", output);
+    crate::report::record("cipher", params.cipher_type.to_string());
+    crate::report::record("format", format_name(content_type));
+    crate::report::print_result(&format!("Your output file is: {}", output));
 
     Ok(())
 }
 
+fn write_archive<W: Write>(
+    input: &str,
+    output: W,
+    contents: &Contents,
+    compression_level: u32,
+) -> Result<W> {
+    match contents {
+        Contents::Zip(files, dirs) => write_zip(
+            input,
+            output,
+            files,
+            dirs.as_deref().unwrap_or_default(),
+            compression_level,
+        ),
+        Contents::Tar(entries) => {
+            crate::tar_archive::write(input, output, entries, compression_level)
+        }
+    }
+}
+
+fn format_name(content_type: ContentType) -> &'static str {
+    match content_type {
+        ContentType::TarArchive => "tar",
+        ContentType::Unspecified => "zip",
+    }
+}
+
+// this writes every directory and file to a zip archive, and returns the output once the archive is complete
+fn write_zip<W: Write>(
+    input: &str,
+    output: W,
+    files: &[PathBuf],
+    dirs: &[PathBuf],
+    compression_level: u32,
+) -> Result<W> {
+    let mut zip = ZipWriter::new(output, compression_level);
+
+    zip.add_directory(input)
+        .context("Unable to add directory to zip")?;
+
+    for dir in dirs {
+        zip.add_directory(
+            dir.to_str()
+                .context("Error converting directory path to string")?,
+        )
+        .context("Unable to add directory to zip")?;
+    }
+
+    // the progress bar covers the data in every file, as that's what takes the time to compress
+    let total_len = files
+        .iter()
+        .filter_map(|file| file.metadata().ok())
+        .map(|metadata| metadata.len())
+        .sum();
+    let bar = crate::progress_bar::new(Some(total_len));
+
+    for file in files {
+        let name = file
+            .to_str()
+            .context("Error converting file path to string")?;
+        crate::progress_bar::println(&bar, &format!("Compressing {}", name));
+
+        let mut file_reader =
+            File::open(file).with_context(|| format!("Unable to open {}", name))?;
+        zip.add_file(name, &mut file_reader, |bytes| bar.inc(bytes))
+            .context("Unable to add file to zip")?;
+    }
+    bar.finish_and_clear();
+
+    zip.finish()
+}
+
 pub fn decrypt_directory(
-    input: &str,         // encrypted zip file
-    output: &str,        // directory
-    keyfile: &str,       // for decrypt function
-    memory: bool,        // memory or stream mode
-    params: &Parameters, // params for decrypt function
+    input: &str,              // encrypted zip or tar file
+    output: &str,             // directory
+    keyfile: &str,            // for decrypt function
+    memory: bool,             // memory or stream mode (legacy files only)
+    ownership: OwnershipMode, // whether to restore the owner of each file (tar archives only)
+    params: &Parameters,      // params for decrypt function
 ) -> Result<()> {
-    let random_extension: String = Alphanumeric.sample_string(&mut rand::thread_rng(), 8);
+    if params.range.is_some() {
+        return Err(anyhow::anyhow!("A range can't be decrypted from a packed directory")
+            .context(Failure::InvalidArguments));
+    }
 
-    // this is the name of the decrypted zip file
-    let tmp_name = format!("{}.{}", input, random_extension); // e.g. "input.kjHSD93l"
+    // the zip reader needs to seek around the archive, which can't be done with stdin
+    if input == STDIO {
+        return Err(anyhow::anyhow!("A packed directory can't be read from stdin")
+            .context(Failure::InvalidArguments));
+    }
 
-    if memory {
-        crate::decrypt::memory_mode(input, &tmp_name, keyfile, params)?;
-    } else {
-        crate::decrypt::stream_mode(input, &tmp_name, keyfile, params)?;
+    let mut file =
+        File::open(input).with_context(|| format!("Unable to open input file: {}", input))?;
+    let header = read_header(input, &mut file, memory, params)?;
+    let key = get_decryption_key(keyfile, &header, params)?;
+
+    eprintln!(
+        "Decrypting {} in {} with {}",
+        input, header.cipher_mode, header.cipher_type
+    );
+
+    match header.cipher_mode {
+        CipherMode::MemoryMode => {
+            let mut data = Vec::new();
+            dexios::memory::decrypt(&mut file, &mut data, &header, key)
+                .with_context(|| format!("Unable to decrypt {}", input))?;
+            let data = Secret::new(data);
+            let reader = Cursor::new(data.expose_secret().as_slice());
+
+            if header.content == ContentType::TarArchive {
+                crate::tar_archive::extract(reader, output, ownership, params.skip)?;
+            } else {
+                let archive = ZipArchive::new(reader)
+                    .context("The decrypted archive can't be opened, is it a zip file?")?;
+                extract_archive(archive, output, params)?;
+            }
+        }
+        CipherMode::StreamMode => {
+            let reader = DecryptReader::new(file, &header, key)
+                .with_context(|| format!("Unable to decrypt {}", input))?;
+
+            if header.content == ContentType::TarArchive {
+                crate::tar_archive::extract(reader, output, ownership, params.skip)?;
+            } else {
+                let archive = ZipArchive::new(reader)
+                    .context("The decrypted archive can't be opened, is it a zip file?")?;
+                extract_archive(archive, output, params)?;
+            }
+        }
     }
 
-    let file = File::open(&tmp_name).context("Unable to open temporary archive")?;
-    let mut archive = zip::ZipArchive::new(file)
-        .context("Temporary archive can't be opened, is it a zip file?")?;
+    crate::report::record("input", input);
+    crate::report::record("output", output);
+    crate::report::record("format", format_name(header.content));
+    crate::report::print_result(&format!("Your files are in {}", output));
 
+    Ok(())
+}
+
+// this extracts every file and directory from the archive into the output directory
+fn extract_archive<R: Read + Seek>(
+    mut archive: ZipArchive<R>,
+    output: &str,
+    params: &Parameters,
+) -> Result<()> {
     match std::fs::create_dir(output) {
-        Ok(_) => println!("Created output directory: {}", output),
-        Err(_) => println!("Output directory ({}) already exists!", output),
+        Ok(_) => eprintln!("Created output directory: {}", output),
+        Err(_) => eprintln!("Output directory ({}) already exists!", output),
     }
 
     for i in 0..archive.len() {
@@ -166,11 +311,11 @@ pub fn decrypt_directory(
                     params.skip == SkipMode::HidePrompts,
                 )?;
                 if !answer {
-                    println!("Skipping {}", file_name);
+                    eprintln!("Skipping {}", file_name);
                     continue;
                 }
             }
-            println!("Extracting {}", file_name);
+            eprintln!("Extracting {}", file_name);
             let mut output_file =
                 File::create(full_path).context("Error creating an output file")?;
             std::io::copy(&mut file, &mut output_file)
@@ -178,9 +323,5 @@ pub fn decrypt_directory(
         }
     }
 
-    crate::erase::secure_erase(&tmp_name, 16)?; // cleanup the tmp file
-
-    println!("Your files are in {}", output);
-
     Ok(())
 }
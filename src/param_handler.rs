@@ -1,18 +1,29 @@
+use crate::failure::Failure;
 use crate::global::{
-    BenchMode, CipherType, EraseMode, HashMode, Parameters, PasswordMode, SkipMode,
+    BenchMode, EraseMode, HashMode, OutputVerifyMode, Parameters, PasswordMode, SkipMode,
+    ThreadMode, VerifyMode,
 };
+use crate::key::read_recipients;
 use anyhow::{Context, Result};
 use clap::ArgMatches;
+use dexios::{CipherType, HeaderMode, KdfParams, BLOCK_SIZE, MAX_BLOCK_SIZE, MIN_BLOCK_SIZE};
+use std::ops::Range;
 
+// this reads the parameters that are shared between subcommands
+// any problem with them is reported as invalid arguments, so it gets the matching exit code
 pub fn param_handler(sub_matches: &ArgMatches) -> Result<(&str, Parameters)> {
+    parse_params(sub_matches).context(Failure::InvalidArguments)
+}
+
+fn parse_params(sub_matches: &ArgMatches) -> Result<(&str, Parameters)> {
     let mut keyfile = "";
-    if sub_matches.is_present("keyfile") {
+    if is_present(sub_matches, "keyfile") {
         keyfile = sub_matches
             .value_of("keyfile")
             .context("No keyfile/invalid text provided")?;
     }
 
-    let hash_mode = if sub_matches.is_present("hash") {
+    let hash_mode = if is_present(sub_matches, "hash") {
         //specify to emit hash after operation
         HashMode::CalculateHash
     } else {
@@ -20,7 +31,7 @@ pub fn param_handler(sub_matches: &ArgMatches) -> Result<(&str, Parameters)> {
         HashMode::NoHash
     };
 
-    let skip = if sub_matches.is_present("skip") {
+    let skip = if is_present(sub_matches, "skip") {
         //specify to hide promps during operation
         SkipMode::HidePrompts
     } else {
@@ -28,24 +39,18 @@ pub fn param_handler(sub_matches: &ArgMatches) -> Result<(&str, Parameters)> {
         SkipMode::ShowPrompts
     };
 
-    let erase = if sub_matches.is_present("erase") {
-        let result = sub_matches
-            .value_of("erase")
-            .context("No amount of passes specified")?
-            .parse();
-
-        let passes = if let Ok(value) = result {
-            value
-        } else {
-            println!("Unable to read number of passes provided - using the default.");
-            16
-        };
+    let erase = if is_present(sub_matches, "erase") {
+        let passes = parse_passes(
+            sub_matches
+                .value_of("erase")
+                .context("No amount of passes specified")?,
+        )?;
         EraseMode::EraseFile(passes)
     } else {
         EraseMode::IgnoreFile(0)
     };
 
-    let bench = if sub_matches.is_present("bench") {
+    let bench = if is_present(sub_matches, "bench") {
         //specify to not write to filesystem, for benchmarking and saving wear on hardware
         BenchMode::BenchmarkInMemory
     } else {
@@ -53,7 +58,7 @@ pub fn param_handler(sub_matches: &ArgMatches) -> Result<(&str, Parameters)> {
         BenchMode::WriteToFilesystem
     };
 
-    let password = if sub_matches.is_present("password") {
+    let password = if is_present(sub_matches, "password") {
         //Overwrite, so the user provided password is used and ignore environment supplied one?!
         PasswordMode::ForceUserProvidedPassword
     } else {
@@ -61,7 +66,7 @@ pub fn param_handler(sub_matches: &ArgMatches) -> Result<(&str, Parameters)> {
         PasswordMode::NormalKeySourcePriority
     };
 
-    let cipher_type = if sub_matches.is_present("gcm") {
+    let cipher_type = if is_present(sub_matches, "gcm") {
         // specify gcm manually
         CipherType::AesGcm
     } else {
@@ -69,6 +74,98 @@ pub fn param_handler(sub_matches: &ArgMatches) -> Result<(&str, Parameters)> {
         CipherType::XChaCha20Poly1305
     };
 
+    let header_mode = if is_present(sub_matches, "legacy") {
+        // the file was created before headers were introduced, so the cipher must be specified manually
+        HeaderMode::LegacyHeaderless
+    } else {
+        // default
+        HeaderMode::ReadHeader
+    };
+
+    let kdf_params = kdf_param_handler(sub_matches)?;
+
+    let verify = if is_present(sub_matches, "unverified") {
+        // write straight to the output, and accept that it may contain unverified plaintext
+        VerifyMode::WriteUnverified
+    } else {
+        // default
+        VerifyMode::VerifyBeforeWriting
+    };
+
+    let output_verify = if is_present(sub_matches, "no-verify") {
+        // erase the input without checking that the output can be decrypted
+        OutputVerifyMode::SkipVerification
+    } else if is_present(sub_matches, "verify")
+        || (sub_matches.is_valid_arg("verify") && erase != EraseMode::IgnoreFile(0))
+    {
+        // default when erasing, as the input may be the only copy of the data
+        OutputVerifyMode::VerifyOutput
+    } else {
+        // default
+        OutputVerifyMode::SkipVerification
+    };
+
+    let recipients = if sub_matches.is_valid_arg("recipient") {
+        // encrypt to these public keys, instead of with a password/keyfile
+        let values: Vec<&str> = sub_matches
+            .values_of("recipient")
+            .map(Iterator::collect)
+            .unwrap_or_default();
+        read_recipients(&values)?
+    } else {
+        Vec::new()
+    };
+
+    let identity = if sub_matches.is_valid_arg("identity") {
+        sub_matches.value_of("identity").map(String::from)
+    } else {
+        None
+    };
+
+    let threads = if is_present(sub_matches, "threads") {
+        // 0 means one thread per available cpu core
+        let value = sub_matches
+            .value_of("threads")
+            .context("No thread count provided")?;
+        let threads: usize = value
+            .parse()
+            .with_context(|| format!("Unable to read the number of threads: {}", value))?;
+
+        let threads = if threads == 0 {
+            std::thread::available_parallelism().map_or(1, std::num::NonZeroUsize::get)
+        } else {
+            threads
+        };
+
+        if threads > 1 {
+            ThreadMode::MultiThreaded(threads)
+        } else {
+            ThreadMode::SingleThreaded
+        }
+    } else {
+        // default
+        ThreadMode::SingleThreaded
+    };
+
+    let range = if is_present(sub_matches, "range") {
+        // only decrypt part of the file
+        let value = sub_matches.value_of("range").context("No range provided")?;
+        Some(parse_range(value)?)
+    } else {
+        None
+    };
+
+    let block_size = if is_present(sub_matches, "chunk-size") {
+        // the size of each block in stream mode, this is recorded in the header
+        let value = sub_matches
+            .value_of("chunk-size")
+            .context("No chunk size provided")?;
+        parse_size(value)?
+    } else {
+        // default
+        BLOCK_SIZE
+    };
+
     Ok((
         keyfile,
         Parameters {
@@ -78,6 +175,130 @@ pub fn param_handler(sub_matches: &ArgMatches) -> Result<(&str, Parameters)> {
             password,
             erase,
             cipher_type,
+            header_mode,
+            kdf_params,
+            verify,
+            output_verify,
+            recipients,
+            identity,
+            threads,
+            range,
+            block_size,
+            key_cache: None,
         },
     ))
 }
+
+// not every subcommand defines every argument, and clap panics if an undefined argument is queried
+fn is_present(sub_matches: &ArgMatches, name: &str) -> bool {
+    sub_matches.is_valid_arg(name) && sub_matches.is_present(name)
+}
+
+// this parses a byte range in the form "start-end" (the end is exclusive), or "start-" to read until the end of the file
+fn parse_range(value: &str) -> Result<Range<u64>> {
+    let (start, end) = value
+        .split_once('-')
+        .with_context(|| format!("Invalid range (expected \"start-end\"): {}", value))?;
+
+    let start = start
+        .trim()
+        .parse()
+        .with_context(|| format!("Unable to read the start of the range: {}", value))?;
+
+    let end = if end.trim().is_empty() {
+        u64::MAX
+    } else {
+        end.trim()
+            .parse()
+            .with_context(|| format!("Unable to read the end of the range: {}", value))?
+    };
+
+    if start > end {
+        return Err(anyhow::anyhow!(
+            "The start of the range is after the end: {}",
+            value
+        ));
+    }
+
+    Ok(start..end)
+}
+
+// this parses a size in bytes, with an optional K or M suffix (e.g. "64K" or "16M")
+// it's validated now, rather than after the user has entered their password
+fn parse_size(value: &str) -> Result<usize> {
+    let trimmed = value.trim();
+    let (number, multiplier) = match trimmed.chars().last() {
+        Some('K' | 'k') => (&trimmed[..trimmed.len() - 1], 1024),
+        Some('M' | 'm') => (&trimmed[..trimmed.len() - 1], 1024 * 1024),
+        _ => (trimmed, 1),
+    };
+
+    let size = number
+        .parse::<usize>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .with_context(|| format!("Unable to read the chunk size: {}", value))?;
+
+    if !(MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&size) {
+        return Err(anyhow::anyhow!(
+            "The chunk size must be between 64K and 64M: {}",
+            value
+        ));
+    }
+
+    Ok(size)
+}
+
+// this reads the number of passes for `--erase=N` and `erase --passes=N`
+// at least one pass is required, as otherwise the file would be removed without ever being overwritten
+pub fn parse_passes(value: &str) -> Result<u32> {
+    match value.parse::<u32>() {
+        Ok(passes) if passes >= 1 => Ok(passes),
+        _ => Err(anyhow::anyhow!(
+            "The number of passes must be a whole number, and at least 1: {}",
+            value
+        )),
+    }
+}
+
+// this handles the argon2id cost parameters, which are only available when encrypting
+// it starts with the preset (or the defaults), and then any individually specified values override it
+// decryption reads the parameters from the header, so the defaults returned here are unused
+fn kdf_param_handler(sub_matches: &ArgMatches) -> Result<KdfParams> {
+    if !sub_matches.is_valid_arg("kdf-preset") {
+        return Ok(KdfParams::default());
+    }
+
+    let mut kdf_params = match sub_matches.value_of("kdf-preset") {
+        Some("interactive") => KdfParams::interactive(),
+        Some("moderate") => KdfParams::moderate(),
+        Some("paranoid") => KdfParams::paranoid(),
+        _ => KdfParams::default(),
+    };
+
+    if let Some(value) = sub_matches.value_of("kdf-memory") {
+        kdf_params.m_cost = value
+            .parse()
+            .with_context(|| format!("Unable to read the argon2id memory cost: {}", value))?;
+    }
+
+    if let Some(value) = sub_matches.value_of("kdf-iterations") {
+        kdf_params.t_cost = value
+            .parse()
+            .with_context(|| format!("Unable to read the argon2id iterations: {}", value))?;
+    }
+
+    if let Some(value) = sub_matches.value_of("kdf-parallelism") {
+        kdf_params.p_cost = value
+            .parse()
+            .with_context(|| format!("Unable to read the argon2id parallelism: {}", value))?;
+    }
+
+    // validate these now, rather than after the user has entered their password
+    // this is the same check that's used when the parameters are read from a header, so every file that's created can be decrypted
+    kdf_params
+        .validate()
+        .with_context(|| format!("Unable to use these argon2id parameters ({})", kdf_params))?;
+
+    Ok(kdf_params)
+}
@@ -0,0 +1,37 @@
+// this file contains a reader that reports how much of its input has been read
+// the stream functions read their input one block at a time, so the callback is called once per block
+// it can wrap the input of any of the encrypt/decrypt/verify functions, e.g. to drive a progress bar in a GUI
+
+use std::io::Read;
+
+pub struct ProgressReader<R: Read, F: FnMut(u64)> {
+    inner: R,
+    callback: F,
+    bytes: u64,
+}
+
+impl<R: Read, F: FnMut(u64)> ProgressReader<R, F> {
+    // the callback is given the total number of bytes that have been read so far
+    pub fn new(inner: R, callback: F) -> Self {
+        ProgressReader {
+            inner,
+            callback,
+            bytes: 0,
+        }
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+}
+
+impl<R: Read, F: FnMut(u64)> Read for ProgressReader<R, F> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read_count = self.inner.read(buf)?;
+        if read_count > 0 {
+            self.bytes += read_count as u64;
+            (self.callback)(self.bytes);
+        }
+        Ok(read_count)
+    }
+}
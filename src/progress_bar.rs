@@ -0,0 +1,46 @@
+use crate::file::input_len;
+use indicatif::{ProgressBar, ProgressStyle};
+
+// this file handles the progress bars that are shown during long operations
+// they're drawn to stderr, and indicatif hides them automatically if stderr isn't a terminal (e.g. when it's redirected to a file)
+
+// this creates a progress bar for an operation that processes `len` bytes
+// if the length isn't known (e.g. when reading from stdin), the bytes processed so far and the throughput are shown instead
+pub fn new(len: Option<u64>) -> ProgressBar {
+    match len {
+        Some(len) => {
+            let bar = ProgressBar::new(len);
+            bar.set_style(
+                ProgressStyle::default_bar()
+                    .template(
+                        "{bytes}/{total_bytes} [{wide_bar}] {binary_bytes_per_sec}, ETA {eta}",
+                    )
+                    .progress_chars("=> "),
+            );
+            bar
+        }
+        None => {
+            let bar = ProgressBar::new_spinner();
+            bar.set_style(
+                ProgressStyle::default_spinner().template("{bytes} ({binary_bytes_per_sec})"),
+            );
+            bar
+        }
+    }
+}
+
+// this creates a progress bar for reading the rest of the input
+// `already_read` is the number of bytes that have already been read from it (e.g. the header), which aren't included
+pub fn for_input(input: &str, already_read: u64) -> ProgressBar {
+    new(input_len(input).map(|len| len.saturating_sub(already_read)))
+}
+
+// this prints a status message above the progress bar, without breaking it
+// hidden progress bars don't print anything, so the message is written to stderr as usual instead
+pub fn println(bar: &ProgressBar, message: &str) {
+    if bar.is_hidden() {
+        eprintln!("{}", message);
+    } else {
+        bar.println(message);
+    }
+}
@@ -1,21 +1,35 @@
 use anyhow::{Context, Result};
 use std::io::{self, stdin, Write};
 
-use crate::global::{BenchMode, SkipMode};
+use crate::failure::Failure;
+use crate::file::STDIO;
+use crate::global::{BenchMode, Parameters, SkipMode};
+use crate::key::prompts_for_password;
+use crate::report;
 
 // this handles user-interactivity, specifically getting a "yes" or "no" answer from the user
 // it requires the question itself, if the default is true/false
 // if skip is enabled then it will just return the `default`
+// in json mode there's nobody to answer, so we fail instead of blocking (and never assume a "yes")
 pub fn get_answer(prompt: &str, default: bool, skip: bool) -> Result<bool> {
     if skip {
         return Ok(true);
     }
 
+    if report::is_json() {
+        return Err(anyhow::anyhow!(
+            "Unable to ask \"{}\" in json mode - use -y to skip prompts",
+            prompt
+        )
+        .context(Failure::InvalidArguments));
+    }
+
     let switch = if default { "(Y/n)" } else { "(y/N)" };
 
     let answer_bool = loop {
-        print!("{prompt} {switch}: ");
-        io::stdout().flush().context("Unable to flush stdout")?;
+        // prompts are written to stderr, so they don't end up in any data written to stdout
+        eprint!("{prompt} {switch}: ");
+        io::stderr().flush().context("Unable to flush stderr")?;
 
         let mut answer = String::new();
         stdin()
@@ -32,7 +46,7 @@ pub fn get_answer(prompt: &str, default: bool, skip: bool) -> Result<bool> {
             'y' => true,
             'n' => false,
             _ => {
-                println!("Unrecognised answer - please try again");
+                eprintln!("Unrecognised answer - please try again");
                 continue;
             }
         };
@@ -44,8 +58,9 @@ pub fn get_answer(prompt: &str, default: bool, skip: bool) -> Result<bool> {
 // then it prompts the user if they'd like to overwrite a file (while showing the associated file name)
 // if they have the skip argument supplied, this will just assume true
 // if benchmarking or skip are true, skip the prompt entirely
+// stdout ("-") can't be overwritten, so it never needs a prompt
 pub fn overwrite_check(name: &str, skip: SkipMode, bench: BenchMode) -> Result<bool> {
-    let answer = if std::fs::metadata(name).is_ok() {
+    let answer = if name != "-" && std::fs::metadata(name).is_ok() {
         let prompt = format!("{} already exists, would you like to overwrite?", name);
         let skip_or_bench = skip == SkipMode::HidePrompts || bench == BenchMode::BenchmarkInMemory;
         get_answer(&prompt, true, skip_or_bench)?
@@ -54,3 +69,33 @@ pub fn overwrite_check(name: &str, skip: SkipMode, bench: BenchMode) -> Result<b
     };
     Ok(answer)
 }
+
+// prompts (including the password prompt) are answered on stdin, so they can't be used while the input is being read from stdin
+// the answer would be taken from the start of the data, so we fail before anything is read unless every prompt can be skipped
+// the overwrite prompt is skipped with -y, and the password can come from a keyfile or the DEXIOS_KEY environment variable instead
+pub fn stdin_check(input: &str, output: &str, keyfile: &str, params: &Parameters) -> Result<()> {
+    if input != STDIO {
+        return Ok(());
+    }
+
+    if output != STDIO
+        && std::fs::metadata(output).is_ok()
+        && params.skip == SkipMode::ShowPrompts
+        && params.bench == BenchMode::WriteToFilesystem
+    {
+        return Err(anyhow::anyhow!(
+            "{} already exists, and you can't be asked whether to overwrite it while the input is read from stdin (use -y to overwrite it)",
+            output
+        )
+        .context(Failure::InvalidArguments));
+    }
+
+    if prompts_for_password(keyfile, params) {
+        return Err(anyhow::anyhow!(
+            "The password can't be entered while the input is read from stdin - use a keyfile or the DEXIOS_KEY environment variable"
+        )
+        .context(Failure::InvalidArguments));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,662 @@
+use crate::cipher::{unwrap_key, wrap_key, WRAPPED_KEY_LEN};
+use crate::error::{Error, Result};
+use crate::header::Keyslot;
+use aws_lc_rs::kem::{Ciphertext, DecapsulationKey, EncapsulationKey, ML_KEM_768};
+use rand::{prelude::StdRng, SeedableRng};
+use secrecy::{ExposeSecret, Secret, SecretString};
+use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
+
+// this file handles public-key (recipient) encryption, using X25519 (optionally combined with ML-KEM-768)
+// the data is encrypted with a random master key, and a copy of that key is wrapped for each recipient
+// to wrap a key, an ephemeral X25519 keypair is generated and combined with the recipient's public key
+// only the holder of the matching identity (secret key) is able to perform the same key agreement and unwrap it
+//
+// hybrid recipients also encapsulate a second shared secret to the recipient's ML-KEM-768 public key
+// the wrapping key is derived from both shared secrets, so it stays secure as long as either X25519 or ML-KEM-768 does
+// this protects long-lived files against an attacker that stores them now, and decrypts them with a quantum computer later
+//
+// public keys are encoded as "dexios-pub-" followed by 64 lowercase hex characters
+// identities are encoded as "DEXIOS-SECRET-KEY-" followed by 64 uppercase hex characters
+// hybrid keys use the "dexios-pq-pub-" and "DEXIOS-PQ-SECRET-KEY-" prefixes, followed by the X25519 key and then the ML-KEM-768 key
+pub const PUBLIC_KEY_PREFIX: &str = "dexios-pub-";
+pub const IDENTITY_PREFIX: &str = "DEXIOS-SECRET-KEY-";
+pub const HYBRID_PUBLIC_KEY_PREFIX: &str = "dexios-pq-pub-";
+pub const HYBRID_IDENTITY_PREFIX: &str = "DEXIOS-PQ-SECRET-KEY-";
+pub const FINGERPRINT_LEN: usize = 8; // bytes
+pub const ML_KEM_PUBLIC_KEY_LEN: usize = 1184; // bytes
+pub const ML_KEM_SECRET_KEY_LEN: usize = 2400; // bytes
+pub const ML_KEM_CIPHERTEXT_LEN: usize = 1088; // bytes
+
+// the expanded ML-KEM-768 secret key contains the public key, starting at this offset (see FIPS 203)
+const ML_KEM_PUBLIC_KEY_OFFSET: usize = 1152;
+
+// these are used for domain separation when deriving the wrapping key from the shared secret(s)
+// blake3's `derive_key` contexts should be globally unique, so they follow its convention of the application, the date the format was designed and the purpose
+// the dates are part of the file format and not a version number, so they must never change, as existing files wouldn't unwrap
+const WRAP_KEY_CONTEXT: &str = "dexios 2022-06 x25519 key wrapping";
+const HYBRID_WRAP_KEY_CONTEXT: &str = "dexios 2022-07 x25519 ml-kem-768 hybrid key wrapping";
+
+// this is the public half of a keypair, that files may be encrypted to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Recipient {
+    X25519(PublicKey),
+    Hybrid { x25519: PublicKey, ml_kem: Vec<u8> },
+}
+
+// this is the secret half of a keypair, which is used to decrypt files that were encrypted to the matching recipient
+// the ML-KEM-768 public key is kept alongside the secret key, as it's needed for the fingerprint and the wrapping key
+pub enum Identity {
+    X25519(StaticSecret),
+    Hybrid {
+        x25519: StaticSecret,
+        ml_kem: DecapsulationKey,
+        ml_kem_public: Vec<u8>,
+    },
+}
+
+// this is a master key that has been wrapped for a single recipient
+// the fingerprint allows the correct identity to be matched up without trying every key slot
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WrappedKey {
+    pub fingerprint: [u8; FINGERPRINT_LEN],
+    pub ephemeral_public: [u8; 32],
+    pub wrapped_key: [u8; WRAPPED_KEY_LEN],
+}
+
+// this is a master key that has been wrapped for a single hybrid recipient
+// it's the same as `WrappedKey`, but with the ML-KEM-768 ciphertext that encapsulates the second shared secret
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HybridWrappedKey {
+    pub fingerprint: [u8; FINGERPRINT_LEN],
+    pub ephemeral_public: [u8; 32],
+    pub ciphertext: Vec<u8>,
+    pub wrapped_key: [u8; WRAPPED_KEY_LEN],
+}
+
+impl Recipient {
+    // this parses a public key, such as one that was printed by `dexios keygen`
+    pub fn parse(encoded: &str) -> Result<Self> {
+        let encoded = encoded.trim();
+
+        if let Some(hex) = encoded.strip_prefix(HYBRID_PUBLIC_KEY_PREFIX) {
+            let bytes = decode_hex(hex)
+                .filter(|bytes| bytes.len() == 32 + ML_KEM_PUBLIC_KEY_LEN)
+                .ok_or(Error::InvalidKey("the public key is malformed"))?;
+            let (x25519, ml_kem) = bytes.split_at(32);
+
+            // this checks that the ML-KEM-768 public key is usable, before anything is encrypted to it
+            EncapsulationKey::new(&ML_KEM_768, ml_kem)
+                .map_err(|_| Error::InvalidKey("the public key is malformed"))?;
+
+            return Ok(Recipient::Hybrid {
+                x25519: PublicKey::from(to_array(x25519)),
+                ml_kem: ml_kem.to_vec(),
+            });
+        }
+
+        let hex = encoded
+            .strip_prefix(PUBLIC_KEY_PREFIX)
+            .ok_or(Error::InvalidKey(
+                "public keys should start with dexios-pub- or dexios-pq-pub-",
+            ))?;
+        let bytes = decode_hex(hex)
+            .filter(|bytes| bytes.len() == 32)
+            .ok_or(Error::InvalidKey("the public key is malformed"))?;
+        Ok(Recipient::X25519(PublicKey::from(to_array(&bytes))))
+    }
+
+    // this returns a short identifier for the public key, that is stored alongside each wrapped key
+    pub fn fingerprint(&self) -> [u8; FINGERPRINT_LEN] {
+        let hash = match self {
+            Recipient::X25519(public) => blake3::hash(public.as_bytes()),
+            Recipient::Hybrid { x25519, ml_kem } => {
+                let mut hasher = blake3::Hasher::new();
+                hasher.update(x25519.as_bytes());
+                hasher.update(ml_kem);
+                hasher.finalize()
+            }
+        };
+
+        let mut fingerprint = [0u8; FINGERPRINT_LEN];
+        fingerprint.copy_from_slice(&hash.as_bytes()[..FINGERPRINT_LEN]);
+        fingerprint
+    }
+
+    // this wraps the master key so that only this recipient is able to unwrap it
+    // the key slot's type depends on the type of recipient, so decryption can pick the correct way to unwrap it
+    pub fn wrap_key(&self, master_key: &Secret<[u8; 32]>) -> Result<Keyslot> {
+        let x25519 = match self {
+            Recipient::X25519(public) | Recipient::Hybrid { x25519: public, .. } => public,
+        };
+
+        let ephemeral_secret = EphemeralSecret::random_from_rng(StdRng::from_entropy());
+        let ephemeral_public = PublicKey::from(&ephemeral_secret);
+        let shared_secret = ephemeral_secret.diffie_hellman(x25519);
+
+        if !shared_secret.was_contributory() {
+            return Err(Error::InvalidKey("the public key is not usable"));
+        }
+
+        match self {
+            Recipient::X25519(public) => {
+                // the wrapping key is unique to this ephemeral keypair
+                let wrapping_key =
+                    derive_wrapping_key(shared_secret.as_bytes(), &ephemeral_public, public);
+
+                Ok(Keyslot::X25519(WrappedKey {
+                    fingerprint: self.fingerprint(),
+                    ephemeral_public: ephemeral_public.to_bytes(),
+                    wrapped_key: wrap_key(&wrapping_key, master_key)?,
+                }))
+            }
+            Recipient::Hybrid { x25519, ml_kem } => {
+                let (ciphertext, ml_kem_secret) = EncapsulationKey::new(&ML_KEM_768, ml_kem)
+                    .map_err(|_| Error::InvalidKey("the public key is malformed"))?
+                    .encapsulate()
+                    .map_err(|_| Error::Encrypt)?;
+
+                let wrapping_key = derive_hybrid_wrapping_key(
+                    shared_secret.as_bytes(),
+                    ml_kem_secret.as_ref(),
+                    &ephemeral_public,
+                    x25519,
+                    ciphertext.as_ref(),
+                );
+
+                Ok(Keyslot::Hybrid(HybridWrappedKey {
+                    fingerprint: self.fingerprint(),
+                    ephemeral_public: ephemeral_public.to_bytes(),
+                    ciphertext: ciphertext.as_ref().to_vec(),
+                    wrapped_key: wrap_key(&wrapping_key, master_key)?,
+                }))
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Recipient {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Recipient::X25519(public) => write!(
+                f,
+                "{}{}",
+                PUBLIC_KEY_PREFIX,
+                encode_hex(public.as_bytes()).to_lowercase()
+            ),
+            Recipient::Hybrid { x25519, ml_kem } => write!(
+                f,
+                "{}{}{}",
+                HYBRID_PUBLIC_KEY_PREFIX,
+                encode_hex(x25519.as_bytes()).to_lowercase(),
+                encode_hex(ml_kem).to_lowercase()
+            ),
+        }
+    }
+}
+
+impl Identity {
+    // this generates a brand new identity
+    pub fn generate() -> Self {
+        Identity::X25519(StaticSecret::random_from_rng(StdRng::from_entropy()))
+    }
+
+    // this generates a brand new hybrid identity, with both an X25519 and an ML-KEM-768 secret key
+    pub fn generate_hybrid() -> Result<Self> {
+        let ml_kem = DecapsulationKey::generate(&ML_KEM_768).map_err(|_| Error::KeyDerivation)?;
+        let ml_kem_public = ml_kem
+            .encapsulation_key()
+            .and_then(|key| key.key_bytes())
+            .map_err(|_| Error::KeyDerivation)?
+            .as_ref()
+            .to_vec();
+
+        Ok(Identity::Hybrid {
+            x25519: StaticSecret::random_from_rng(StdRng::from_entropy()),
+            ml_kem,
+            ml_kem_public,
+        })
+    }
+
+    // this parses an identity, such as the contents of a file created by `dexios keygen`
+    // blank lines and lines starting with # are ignored, so the file may contain comments
+    pub fn parse(encoded: &str) -> Result<Self> {
+        let line = encoded
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.starts_with('#'))
+            .ok_or(Error::InvalidKey("no identity was found"))?;
+
+        if let Some(hex) = line.strip_prefix(HYBRID_IDENTITY_PREFIX) {
+            let bytes = decode_hex(hex)
+                .filter(|bytes| bytes.len() == 32 + ML_KEM_SECRET_KEY_LEN)
+                .map(Secret::new)
+                .ok_or(Error::InvalidKey("the identity is malformed"))?;
+            let (x25519, ml_kem) = bytes.expose_secret().split_at(32);
+
+            return Ok(Identity::Hybrid {
+                x25519: StaticSecret::from(to_array(x25519)),
+                ml_kem: DecapsulationKey::new(&ML_KEM_768, ml_kem)
+                    .map_err(|_| Error::InvalidKey("the identity is malformed"))?,
+                ml_kem_public: ml_kem
+                    [ML_KEM_PUBLIC_KEY_OFFSET..ML_KEM_PUBLIC_KEY_OFFSET + ML_KEM_PUBLIC_KEY_LEN]
+                    .to_vec(),
+            });
+        }
+
+        let hex = line.strip_prefix(IDENTITY_PREFIX).ok_or(Error::InvalidKey(
+            "identities should start with DEXIOS-SECRET-KEY- or DEXIOS-PQ-SECRET-KEY-",
+        ))?;
+        let bytes = decode_hex(hex)
+            .filter(|bytes| bytes.len() == 32)
+            .map(Secret::new)
+            .ok_or(Error::InvalidKey("the identity is malformed"))?;
+        Ok(Identity::X25519(StaticSecret::from(to_array(
+            bytes.expose_secret(),
+        ))))
+    }
+
+    // this returns the public key that files should be encrypted to, for this identity to be able to decrypt them
+    pub fn recipient(&self) -> Recipient {
+        match self {
+            Identity::X25519(secret) => Recipient::X25519(PublicKey::from(secret)),
+            Identity::Hybrid {
+                x25519,
+                ml_kem_public,
+                ..
+            } => Recipient::Hybrid {
+                x25519: PublicKey::from(x25519),
+                ml_kem: ml_kem_public.clone(),
+            },
+        }
+    }
+
+    // this encodes the identity, so it can be written to a file
+    pub fn encode(&self) -> Result<SecretString> {
+        Ok(match self {
+            Identity::X25519(secret) => SecretString::new(format!(
+                "{}{}",
+                IDENTITY_PREFIX,
+                encode_hex(&secret.to_bytes()).to_uppercase()
+            )),
+            Identity::Hybrid { x25519, ml_kem, .. } => {
+                let ml_kem = ml_kem.key_bytes().map_err(|_| Error::KeyDerivation)?;
+                SecretString::new(format!(
+                    "{}{}{}",
+                    HYBRID_IDENTITY_PREFIX,
+                    encode_hex(&x25519.to_bytes()).to_uppercase(),
+                    encode_hex(ml_kem.as_ref()).to_uppercase()
+                ))
+            }
+        })
+    }
+
+    // this attempts to unwrap a master key from an X25519 key slot with this identity
+    // it returns None if the key wasn't wrapped for this identity
+    pub fn unwrap_key(&self, wrapped: &WrappedKey) -> Option<Secret<[u8; 32]>> {
+        let secret = match self {
+            Identity::X25519(secret) => secret,
+            Identity::Hybrid { .. } => return None,
+        };
+
+        let recipient = PublicKey::from(secret);
+        if wrapped.fingerprint != self.recipient().fingerprint() {
+            return None;
+        }
+
+        let ephemeral_public = PublicKey::from(wrapped.ephemeral_public);
+        let shared_secret = secret.diffie_hellman(&ephemeral_public);
+        if !shared_secret.was_contributory() {
+            return None;
+        }
+
+        let wrapping_key =
+            derive_wrapping_key(shared_secret.as_bytes(), &ephemeral_public, &recipient);
+        unwrap_key(&wrapping_key, &wrapped.wrapped_key)
+    }
+
+    // this attempts to unwrap a master key from a hybrid key slot with this identity
+    // it returns None if the key wasn't wrapped for this identity
+    pub fn unwrap_hybrid_key(&self, wrapped: &HybridWrappedKey) -> Option<Secret<[u8; 32]>> {
+        let (x25519, ml_kem) = match self {
+            Identity::Hybrid { x25519, ml_kem, .. } => (x25519, ml_kem),
+            Identity::X25519(_) => return None,
+        };
+
+        if wrapped.fingerprint != self.recipient().fingerprint() {
+            return None;
+        }
+
+        let ephemeral_public = PublicKey::from(wrapped.ephemeral_public);
+        let shared_secret = x25519.diffie_hellman(&ephemeral_public);
+        if !shared_secret.was_contributory() {
+            return None;
+        }
+
+        let ml_kem_secret = ml_kem
+            .decapsulate(Ciphertext::from(wrapped.ciphertext.as_slice()))
+            .ok()?;
+
+        let wrapping_key = derive_hybrid_wrapping_key(
+            shared_secret.as_bytes(),
+            ml_kem_secret.as_ref(),
+            &ephemeral_public,
+            &PublicKey::from(x25519),
+            &wrapped.ciphertext,
+        );
+        unwrap_key(&wrapping_key, &wrapped.wrapped_key)
+    }
+}
+
+// the wrapping key is bound to both public keys, as well as the shared secret
+fn derive_wrapping_key(
+    shared_secret: &[u8; 32],
+    ephemeral_public: &PublicKey,
+    recipient_public: &PublicKey,
+) -> Secret<[u8; 32]> {
+    let mut key_material = Vec::with_capacity(96);
+    key_material.extend_from_slice(shared_secret);
+    key_material.extend_from_slice(ephemeral_public.as_bytes());
+    key_material.extend_from_slice(recipient_public.as_bytes());
+
+    let key_material = Secret::new(key_material);
+    Secret::new(blake3::derive_key(
+        WRAP_KEY_CONTEXT,
+        key_material.expose_secret(),
+    ))
+}
+
+// the hybrid wrapping key combines both shared secrets, and is bound to the X25519 public keys and the ML-KEM-768 ciphertext
+fn derive_hybrid_wrapping_key(
+    shared_secret: &[u8; 32],
+    ml_kem_secret: &[u8],
+    ephemeral_public: &PublicKey,
+    recipient_public: &PublicKey,
+    ciphertext: &[u8],
+) -> Secret<[u8; 32]> {
+    let mut key_material = Vec::with_capacity(128 + ciphertext.len());
+    key_material.extend_from_slice(ml_kem_secret);
+    key_material.extend_from_slice(shared_secret);
+    key_material.extend_from_slice(ephemeral_public.as_bytes());
+    key_material.extend_from_slice(recipient_public.as_bytes());
+    key_material.extend_from_slice(ciphertext);
+
+    let key_material = Secret::new(key_material);
+    Secret::new(blake3::derive_key(
+        HYBRID_WRAP_KEY_CONTEXT,
+        key_material.expose_secret(),
+    ))
+}
+
+// this encodes bytes as lowercase hex, for public keys, identities and key slot fingerprints
+pub fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+// the length has already been checked by the caller
+fn to_array(bytes: &[u8]) -> [u8; 32] {
+    let mut array = [0u8; 32];
+    array.copy_from_slice(bytes);
+    array
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn master_key() -> Secret<[u8; 32]> {
+        Secret::new([1u8; 32])
+    }
+
+    fn wrap_x25519(recipient: &Recipient) -> WrappedKey {
+        match recipient.wrap_key(&master_key()).unwrap() {
+            Keyslot::X25519(wrapped) => wrapped,
+            _ => panic!("X25519 recipients should add X25519 key slots"),
+        }
+    }
+
+    fn wrap_hybrid(recipient: &Recipient) -> HybridWrappedKey {
+        match recipient.wrap_key(&master_key()).unwrap() {
+            Keyslot::Hybrid(wrapped) => wrapped,
+            _ => panic!("hybrid recipients should add hybrid key slots"),
+        }
+    }
+
+    #[test]
+    fn wrapped_keys_round_trip() {
+        let identity = Identity::generate();
+        let recipient = identity.recipient();
+        let wrapped = wrap_x25519(&recipient);
+        assert_eq!(wrapped.fingerprint, recipient.fingerprint());
+
+        let unwrapped = identity.unwrap_key(&wrapped).unwrap();
+        assert_eq!(unwrapped.expose_secret(), master_key().expose_secret());
+
+        // every wrap uses a new ephemeral key, so the same master key never wraps to the same bytes
+        let rewrapped = wrap_x25519(&recipient);
+        assert_ne!(rewrapped.wrapped_key, wrapped.wrapped_key);
+    }
+
+    #[test]
+    fn hybrid_wrapped_keys_round_trip() {
+        let identity = Identity::generate_hybrid().unwrap();
+        let recipient = identity.recipient();
+        let wrapped = wrap_hybrid(&recipient);
+        assert_eq!(wrapped.fingerprint, recipient.fingerprint());
+        assert_eq!(wrapped.ciphertext.len(), ML_KEM_CIPHERTEXT_LEN);
+
+        let unwrapped = identity.unwrap_hybrid_key(&wrapped).unwrap();
+        assert_eq!(unwrapped.expose_secret(), master_key().expose_secret());
+
+        let rewrapped = wrap_hybrid(&recipient);
+        assert_ne!(rewrapped.ciphertext, wrapped.ciphertext);
+        assert_ne!(rewrapped.wrapped_key, wrapped.wrapped_key);
+
+        // each kind of identity only unwraps its own kind of key slot
+        assert!(identity
+            .unwrap_key(&wrap_x25519(&Identity::generate().recipient()))
+            .is_none());
+        assert!(Identity::generate().unwrap_hybrid_key(&wrapped).is_none());
+    }
+
+    #[test]
+    fn other_identities_cant_unwrap() {
+        let wrapped = wrap_x25519(&Identity::generate().recipient());
+        let other = Identity::generate();
+        assert!(other.unwrap_key(&wrapped).is_none());
+
+        // the fingerprint is only a hint, so the key agreement still has to succeed
+        let mut spoofed = wrapped;
+        spoofed.fingerprint = other.recipient().fingerprint();
+        assert!(other.unwrap_key(&spoofed).is_none());
+    }
+
+    #[test]
+    fn other_hybrid_identities_cant_unwrap() {
+        let wrapped = wrap_hybrid(&Identity::generate_hybrid().unwrap().recipient());
+        let other = Identity::generate_hybrid().unwrap();
+        assert!(other.unwrap_hybrid_key(&wrapped).is_none());
+
+        let mut spoofed = wrapped;
+        spoofed.fingerprint = other.recipient().fingerprint();
+        assert!(other.unwrap_hybrid_key(&spoofed).is_none());
+    }
+
+    #[test]
+    fn tampered_wrapped_keys_are_rejected() {
+        let identity = Identity::generate();
+        let wrapped = wrap_x25519(&identity.recipient());
+
+        let mut tampered = wrapped.clone();
+        tampered.wrapped_key[0] ^= 1;
+        assert!(identity.unwrap_key(&tampered).is_none());
+
+        let mut tampered = wrapped;
+        tampered.ephemeral_public[0] ^= 1;
+        assert!(identity.unwrap_key(&tampered).is_none());
+    }
+
+    // both shared secrets go into the wrapping key, so tampering with either half of the key agreement is detected
+    #[test]
+    fn tampered_hybrid_keys_are_rejected() {
+        let identity = Identity::generate_hybrid().unwrap();
+        let wrapped = wrap_hybrid(&identity.recipient());
+
+        let mut tampered = wrapped.clone();
+        tampered.wrapped_key[0] ^= 1;
+        assert!(identity.unwrap_hybrid_key(&tampered).is_none());
+
+        let mut tampered = wrapped.clone();
+        tampered.ephemeral_public[0] ^= 1;
+        assert!(identity.unwrap_hybrid_key(&tampered).is_none());
+
+        for index in [0, ML_KEM_CIPHERTEXT_LEN - 1] {
+            let mut tampered = wrapped.clone();
+            tampered.ciphertext[index] ^= 1;
+            assert!(identity.unwrap_hybrid_key(&tampered).is_none());
+        }
+
+        let mut tampered = wrapped;
+        tampered.ciphertext.pop();
+        assert!(identity.unwrap_hybrid_key(&tampered).is_none());
+    }
+
+    #[test]
+    fn keys_are_encoded_and_parsed() {
+        let identity = Identity::generate();
+        let encoded = identity.encode().unwrap();
+        assert!(encoded.expose_secret().starts_with(IDENTITY_PREFIX));
+
+        // the identity file may contain comments and blank lines
+        let file = format!(
+            "# public key: {}\n\n{}\n",
+            identity.recipient(),
+            encoded.expose_secret()
+        );
+        let parsed = Identity::parse(&file).unwrap();
+        assert_eq!(parsed.recipient(), identity.recipient());
+
+        let encoded = identity.recipient().to_string();
+        assert!(encoded.starts_with(PUBLIC_KEY_PREFIX));
+        assert_eq!(
+            Recipient::parse(&format!(" {}\n", encoded)).unwrap(),
+            identity.recipient()
+        );
+    }
+
+    // the ML-KEM-768 public key isn't stored in the identity file, so it's sliced out of the expanded secret key
+    #[test]
+    fn hybrid_keys_are_encoded_and_parsed() {
+        let identity = Identity::generate_hybrid().unwrap();
+        let encoded = identity.encode().unwrap();
+        assert!(encoded.expose_secret().starts_with(HYBRID_IDENTITY_PREFIX));
+
+        let parsed = Identity::parse(encoded.expose_secret()).unwrap();
+        assert_eq!(parsed.recipient(), identity.recipient());
+        assert_eq!(
+            parsed.encode().unwrap().expose_secret(),
+            encoded.expose_secret()
+        );
+
+        let wrapped = wrap_hybrid(&identity.recipient());
+        let unwrapped = parsed.unwrap_hybrid_key(&wrapped).unwrap();
+        assert_eq!(unwrapped.expose_secret(), master_key().expose_secret());
+
+        let encoded = identity.recipient().to_string();
+        assert!(encoded.starts_with(HYBRID_PUBLIC_KEY_PREFIX));
+        assert_eq!(
+            encoded.len(),
+            HYBRID_PUBLIC_KEY_PREFIX.len() + (32 + ML_KEM_PUBLIC_KEY_LEN) * 2
+        );
+        assert_eq!(Recipient::parse(&encoded).unwrap(), identity.recipient());
+    }
+
+    #[test]
+    fn malformed_keys_are_rejected() {
+        let encoded = Identity::generate()
+            .encode()
+            .unwrap()
+            .expose_secret()
+            .clone();
+        let hex = &encoded[IDENTITY_PREFIX.len()..];
+
+        for malformed in [
+            String::new(),
+            "# just a comment".to_string(),
+            hex.to_string(),
+            encoded[..encoded.len() - 2].to_string(),
+            format!("{}00", encoded),
+            format!("{}ZZ", &encoded[..encoded.len() - 2]),
+            format!("{}é", &encoded[..encoded.len() - 2]),
+        ] {
+            assert!(matches!(
+                Identity::parse(&malformed),
+                Err(Error::InvalidKey(_))
+            ));
+        }
+
+        let encoded = Identity::generate().recipient().to_string();
+        for malformed in [
+            encoded.replace(PUBLIC_KEY_PREFIX, "dexios-key-"),
+            encoded[..encoded.len() - 1].to_string(),
+            format!("{}0", encoded),
+        ] {
+            assert!(matches!(
+                Recipient::parse(&malformed),
+                Err(Error::InvalidKey(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn malformed_hybrid_keys_are_rejected() {
+        let x25519 = Identity::generate()
+            .encode()
+            .unwrap()
+            .expose_secret()
+            .clone();
+        let encoded = Identity::generate_hybrid()
+            .unwrap()
+            .encode()
+            .unwrap()
+            .expose_secret()
+            .clone();
+
+        for malformed in [
+            // an X25519 identity with the hybrid prefix
+            x25519.replace(IDENTITY_PREFIX, HYBRID_IDENTITY_PREFIX),
+            encoded[..encoded.len() - 2].to_string(),
+            format!("{}00", encoded),
+            format!("{}ZZ", &encoded[..encoded.len() - 2]),
+        ] {
+            assert!(matches!(
+                Identity::parse(&malformed),
+                Err(Error::InvalidKey(_))
+            ));
+        }
+
+        let x25519 = Identity::generate().recipient().to_string();
+        let encoded = Identity::generate_hybrid().unwrap().recipient().to_string();
+
+        for malformed in [
+            x25519.replace(PUBLIC_KEY_PREFIX, HYBRID_PUBLIC_KEY_PREFIX),
+            encoded[..encoded.len() - 2].to_string(),
+            format!("{}00", encoded),
+        ] {
+            assert!(matches!(
+                Recipient::parse(&malformed),
+                Err(Error::InvalidKey(_))
+            ));
+        }
+    }
+}
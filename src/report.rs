@@ -0,0 +1,94 @@
+use crate::failure::Failure;
+use serde_json::{json, Map, Value};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+// this file handles the machine-readable output that's enabled with --json
+// each operation records its results here as it goes, and main prints a single json object to stdout once it has finished (or failed)
+// in json mode, stdout is reserved for that object - everything else is written to stderr, and prompts fail instead of blocking
+
+static JSON_MODE: AtomicBool = AtomicBool::new(false);
+static FIELDS: Mutex<Vec<(String, Value)>> = Mutex::new(Vec::new());
+
+pub fn enable_json() {
+    JSON_MODE.store(true, Ordering::Relaxed);
+}
+
+pub fn is_json() -> bool {
+    JSON_MODE.load(Ordering::Relaxed)
+}
+
+// this records a field for the json report
+// recording the same field twice overwrites it
+pub fn record(key: &str, value: impl Into<Value>) {
+    if let Ok(mut fields) = FIELDS.lock() {
+        fields.retain(|(existing, _)| existing != key);
+        fields.push((key.to_string(), value.into()));
+    }
+}
+
+// this records every field of a json object
+pub fn record_all(object: Value) {
+    if let Value::Object(object) = object {
+        for (key, value) in object {
+            record(&key, value);
+        }
+    }
+}
+
+// this removes every recorded field, and returns them as a json object
+// batch mode uses this to collect the fields for each file separately
+pub fn take_fields() -> Value {
+    let mut object = Map::new();
+
+    if let Ok(mut fields) = FIELDS.lock() {
+        for (key, value) in fields.drain(..) {
+            object.insert(key, value);
+        }
+    }
+
+    Value::Object(object)
+}
+
+// this prints the result of an operation (e.g. a hash) for the user
+// in json mode the result is part of the report instead, so stdout is left alone and it's written to stderr
+pub fn print_result(message: &str) {
+    if is_json() {
+        eprintln!("{}", message);
+    } else {
+        println!("{}", message);
+    }
+}
+
+// this prints the json report for the operation, if json mode is enabled
+// failures are reported too, along with the kind of error, so scripts don't need to parse the error message
+pub fn finish(operation: &str, duration: Duration, result: &anyhow::Result<()>) {
+    if !is_json() {
+        return;
+    }
+
+    let mut report = Map::new();
+    report.insert("operation".to_string(), json!(operation));
+    report.insert("success".to_string(), json!(result.is_ok()));
+
+    if let Ok(fields) = FIELDS.lock() {
+        for (key, value) in fields.iter() {
+            report.insert(key.clone(), value.clone());
+        }
+    }
+
+    report.insert("duration".to_string(), json!(duration.as_secs_f64()));
+
+    if let Err(err) = result {
+        report.insert(
+            "error".to_string(),
+            json!({
+                "kind": Failure::of(err).name(),
+                "message": format!("{:#}", err),
+            }),
+        );
+    }
+
+    println!("{}", Value::Object(report));
+}
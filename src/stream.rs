@@ -0,0 +1,1151 @@
+use crate::cipher::{
+    CipherMode, CipherType, DecryptStreamCiphers, EncryptStreamCiphers, StreamCiphers,
+};
+use crate::error::{Error, Result};
+use crate::header::{ContentType, Header};
+use crate::keys::{
+    has_key_check, new_header, new_header_with_content, unlock, DecryptionKey, EncryptionKey,
+};
+use aead::Payload;
+use secrecy::{ExposeSecret, Secret};
+use std::collections::BTreeMap;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::sync::{mpsc, Mutex};
+
+// this encrypts data in stream mode
+// it takes an input reader, an output writer, the key (a password/keyfile, or recipients), the cipher and the block size
+// it builds the header (which generates the 8/20 byte nonce), creates the encryption cipher, writes the header and then reads the input in blocks
+// on each read, it encrypts (with the header as associated data), writes and repeats until EOF
+// the block size is recorded in the header, and it must be between MIN_BLOCK_SIZE and MAX_BLOCK_SIZE (BLOCK_SIZE is a sensible default)
+// it returns the header that was written, along with the master key (so the output can be verified with DecryptionKey::MasterKey)
+pub fn encrypt(
+    input: &mut impl Read,
+    output: &mut impl Write,
+    key: EncryptionKey,
+    cipher_type: CipherType,
+    block_size: usize,
+) -> Result<(Header, Secret<[u8; 32]>)> {
+    let (header, master_key) = new_header(cipher_type, CipherMode::StreamMode, block_size, key)?;
+    let mut streams = EncryptStreamCiphers::initialize(
+        Secret::new(*master_key.expose_secret()),
+        &header.nonce,
+        cipher_type,
+    )?;
+
+    header.write(output)?;
+    let aad = header.aad();
+
+    let mut buffer = vec![0u8; block_size];
+
+    loop {
+        let read_count = read_block(input, &mut buffer)?;
+        if read_count == block_size {
+            let payload = Payload {
+                aad: &aad,
+                msg: buffer.as_slice(),
+            };
+
+            let encrypted_data = streams.encrypt_next(payload).map_err(|_| Error::Encrypt)?;
+
+            output.write_all(&encrypted_data)?;
+        } else {
+            // if we read something less than the block size, and have hit the end of the file
+            let payload = Payload {
+                aad: &aad,
+                msg: &buffer[..read_count],
+            };
+
+            let encrypted_data = streams.encrypt_last(payload).map_err(|_| Error::Encrypt)?;
+
+            output.write_all(&encrypted_data)?;
+            break;
+        }
+    }
+
+    output.flush()?;
+
+    Ok((header, master_key))
+}
+
+// this decrypts data in stream mode
+// it takes an input reader (positioned just after the header), an output writer, the header and the key (a password/keyfile, or an identity)
+// it unlocks the key with the header's key material, creates the decryption cipher and then reads the input in blocks (including the AEAD tag)
+// on each read, it decrypts, writes and repeats until EOF
+// the block size is read from the header
+// a block that fails authentication is reported as CorruptBlock, unless it may have just been the wrong key (see block_error())
+pub fn decrypt(
+    input: &mut impl Read,
+    output: &mut impl Write,
+    header: &Header,
+    key: DecryptionKey,
+) -> Result<()> {
+    let key = unlock(header, key)?;
+    let mut streams = DecryptStreamCiphers::initialize(key, &header.nonce, header.cipher_type)?;
+    let aad = header.aad();
+
+    let encrypted_block_size = header.block_size + 16; // 16 bytes is the length of the AEAD tag
+    let data_start = header.serialize().len() as u64;
+    let mut buffer = vec![0u8; encrypted_block_size];
+    let mut index = 0u64;
+
+    loop {
+        let key_checked = has_key_check(header) || index > 0;
+        let corrupt = || {
+            block_error(
+                key_checked,
+                index,
+                data_start + index * encrypted_block_size as u64,
+            )
+        };
+
+        let read_count = read_block(input, &mut buffer)?;
+        if read_count == encrypted_block_size {
+            let payload = Payload {
+                aad: &aad,
+                msg: buffer.as_slice(),
+            };
+
+            let decrypted_data = streams.decrypt_next(payload).map_err(|_| corrupt())?;
+
+            output.write_all(&decrypted_data)?;
+        } else {
+            // if we read something less than the block size + 16, and have hit the end of the file
+            let payload = Payload {
+                aad: &aad,
+                msg: &buffer[..read_count],
+            };
+
+            let decrypted_data = streams.decrypt_last(payload).map_err(|_| corrupt())?;
+
+            output.write_all(&decrypted_data)?;
+            break;
+        }
+
+        index += 1;
+    }
+
+    output.flush()?;
+
+    Ok(())
+}
+
+// this encrypts data in stream mode, across multiple threads
+// it works the same as encrypt(), and the output is identical, so files can be decrypted by either decrypt() or decrypt_parallel()
+// blocks are encrypted on a pool of `threads` worker threads, while this thread reads the next blocks and writes the finished ones in order (see process_blocks())
+// memory usage is roughly 2 * `threads` * the block size
+pub fn encrypt_parallel(
+    input: &mut impl Read,
+    output: &mut impl Write,
+    key: EncryptionKey,
+    cipher_type: CipherType,
+    block_size: usize,
+    threads: usize,
+) -> Result<(Header, Secret<[u8; 32]>)> {
+    let (header, master_key) = new_header(cipher_type, CipherMode::StreamMode, block_size, key)?;
+    let streams = StreamCiphers::initialize(
+        Secret::new(*master_key.expose_secret()),
+        &header.nonce,
+        cipher_type,
+    )?;
+
+    header.write(output)?;
+    let aad = header.aad();
+
+    process_blocks(
+        input,
+        output,
+        block_size,
+        threads,
+        |position, last_block, buffer| streams.encrypt_block(position, last_block, &aad, buffer),
+    )
+    .map_err(|err| match err {
+        BlockError::Io(err) => err,
+        BlockError::Cipher(_) | BlockError::TooManyBlocks => Error::Encrypt,
+    })?;
+
+    output.flush()?;
+
+    Ok((header, master_key))
+}
+
+// this decrypts data in stream mode, across multiple threads
+// it works the same as decrypt(), and it can decrypt files that were created by either encrypt() or encrypt_parallel()
+// blocks are decrypted on a pool of worker threads, and each one is written in order once it has been authenticated
+// if a block fails authentication, nothing from that block onwards is written
+pub fn decrypt_parallel(
+    input: &mut impl Read,
+    output: &mut impl Write,
+    header: &Header,
+    key: DecryptionKey,
+    threads: usize,
+) -> Result<()> {
+    let key = unlock(header, key)?;
+    let streams = StreamCiphers::initialize(key, &header.nonce, header.cipher_type)?;
+    let aad = header.aad();
+
+    let encrypted_block_size = header.block_size + 16; // 16 bytes is the length of the AEAD tag
+    let data_start = header.serialize().len() as u64;
+
+    process_blocks(
+        input,
+        output,
+        encrypted_block_size,
+        threads,
+        |position, last_block, buffer| streams.decrypt_block(position, last_block, &aad, buffer),
+    )
+    .map_err(|err| match err {
+        BlockError::Io(err) => err,
+        // every block before the one that failed was authenticated, so only the file's first block may have failed because of the wrong key
+        BlockError::Cipher(index) => block_error(
+            has_key_check(header) || index > 0,
+            index,
+            data_start + index * encrypted_block_size as u64,
+        ),
+        BlockError::TooManyBlocks => Error::Decrypt,
+    })?;
+
+    output.flush()?;
+
+    Ok(())
+}
+
+// this checks that data encrypted in stream mode is intact, without writing any of the decrypted data
+// every block is authenticated, along with the header (as associated data) and the final block's flag
+// if a block fails authentication, CorruptBlock is returned with the block's index and its offset within the file
+// the exception is the first block of a file without a key check, as that may just be the wrong key (see block_error())
+// it returns the length of the decrypted data
+pub fn verify(input: &mut impl Read, header: &Header, key: DecryptionKey) -> Result<u64> {
+    let key = unlock(header, key)?;
+    let streams = StreamCiphers::initialize(key, &header.nonce, header.cipher_type)?;
+    let aad = header.aad();
+
+    let header_len = header.serialize().len() as u64;
+    let encrypted_block_size = header.block_size + 16; // 16 bytes is the length of the AEAD tag
+
+    let mut buffer = Vec::with_capacity(encrypted_block_size);
+    let mut index = 0u64;
+    let mut data_len = 0u64;
+
+    loop {
+        buffer.resize(encrypted_block_size, 0);
+        let read_count = read_block(input, &mut buffer)?;
+        buffer.truncate(read_count);
+        let last_block = read_count < encrypted_block_size;
+
+        let position = u32::try_from(index).map_err(|_| Error::Decrypt)?;
+        streams
+            .decrypt_block(position, last_block, &aad, &mut buffer)
+            .map_err(|_| {
+                block_error(
+                    has_key_check(header) || index > 0,
+                    index,
+                    header_len + index * encrypted_block_size as u64,
+                )
+            })?;
+
+        data_len += buffer.len() as u64;
+
+        if last_block {
+            break;
+        }
+
+        index += 1;
+    }
+
+    Ok(data_len)
+}
+
+// this decrypts part of the data in stream mode, without processing anything else
+// the range is in terms of the decrypted data, and the end is exclusive (it's clamped to the length of the data)
+// the input must be positioned just after the header, and its length is used to find the final block
+// it seeks straight to the blocks that cover the range, decrypts only those, and writes the requested bytes
+// the final block is always authenticated too, so a truncated file is still detected
+pub fn decrypt_range<R: Read + Seek>(
+    input: &mut R,
+    output: &mut impl Write,
+    header: &Header,
+    key: DecryptionKey,
+    range: Range<u64>,
+) -> Result<()> {
+    let block_size = header.block_size as u64;
+    let encrypted_block_size = block_size + 16; // 16 bytes is the length of the AEAD tag
+
+    let Layout {
+        data_start,
+        last_block,
+        last_block_len,
+        plaintext_len,
+    } = Layout::read(input, header)?;
+
+    if range.start > range.end || range.start > plaintext_len {
+        return Err(Error::InvalidRange);
+    }
+
+    let end = range.end.min(plaintext_len);
+    if range.start == end {
+        return Ok(());
+    }
+
+    let first_block = range.start / block_size;
+    let final_block = (end - 1) / block_size;
+
+    let key = unlock(header, key)?;
+    let streams = StreamCiphers::initialize(key, &header.nonce, header.cipher_type)?;
+    let aad = header.aad();
+
+    let mut buffer = Vec::with_capacity(header.block_size + 16);
+    let mut key_checked = has_key_check(header);
+
+    // the final block is authenticated first (if it isn't part of the range), so nothing is written if the file has been truncated
+    if final_block != last_block {
+        let offset = data_start + last_block * encrypted_block_size;
+        input.seek(SeekFrom::Start(offset))?;
+        buffer.resize(last_block_len as usize, 0);
+        input.read_exact(&mut buffer)?;
+
+        let position = u32::try_from(last_block).map_err(|_| Error::Decrypt)?;
+        streams
+            .decrypt_block(position, true, &aad, &mut buffer)
+            .map_err(|_| block_error(key_checked, last_block, offset))?;
+        key_checked = true;
+    }
+
+    input.seek(SeekFrom::Start(
+        data_start + first_block * encrypted_block_size,
+    ))?;
+
+    for block in first_block..=final_block {
+        let len = if block == last_block {
+            last_block_len
+        } else {
+            encrypted_block_size
+        };
+        buffer.resize(len as usize, 0);
+        input.read_exact(&mut buffer)?;
+
+        let position = u32::try_from(block).map_err(|_| Error::Decrypt)?;
+        streams
+            .decrypt_block(position, block == last_block, &aad, &mut buffer)
+            .map_err(|_| {
+                block_error(
+                    key_checked,
+                    block,
+                    data_start + block * encrypted_block_size,
+                )
+            })?;
+        key_checked = true;
+
+        let block_start = block * block_size;
+        let from = range.start.saturating_sub(block_start) as usize;
+        let to = (end - block_start).min(buffer.len() as u64) as usize;
+        output.write_all(&buffer[from..to])?;
+    }
+
+    output.flush()?;
+
+    Ok(())
+}
+
+// this encrypts everything that's written to it in stream mode, so data can be encrypted as it's produced (e.g. by an archive writer)
+// the header is written as soon as the writer is created, and each block is encrypted and written once it's full
+// `finish()` must be called once everything has been written, as it encrypts the final block - the output can't be decrypted without it
+// the output is identical to encrypt()'s, so it can be decrypted by any of the decrypt functions
+pub struct EncryptWriter<W: Write> {
+    output: W,
+    header: Header,
+    streams: StreamCiphers,
+    aad: Vec<u8>,
+    buffer: Vec<u8>,
+    position: u32,
+}
+
+impl<W: Write> EncryptWriter<W> {
+    pub fn new(
+        output: W,
+        key: EncryptionKey,
+        cipher_type: CipherType,
+        block_size: usize,
+    ) -> Result<Self> {
+        Self::with_content(
+            output,
+            key,
+            cipher_type,
+            block_size,
+            ContentType::Unspecified,
+        )
+    }
+
+    // this is the same as new(), but it also records what the data contains within the header
+    pub fn with_content(
+        mut output: W,
+        key: EncryptionKey,
+        cipher_type: CipherType,
+        block_size: usize,
+        content: ContentType,
+    ) -> Result<Self> {
+        let (header, key) = new_header_with_content(
+            cipher_type,
+            CipherMode::StreamMode,
+            block_size,
+            key,
+            content,
+        )?;
+        let streams = StreamCiphers::initialize(key, &header.nonce, cipher_type)?;
+
+        header.write(&mut output)?;
+        let aad = header.aad();
+
+        Ok(EncryptWriter {
+            output,
+            header,
+            streams,
+            aad,
+            buffer: Vec::with_capacity(block_size + 16),
+            position: 0,
+        })
+    }
+
+    // this encrypts the final block (which may be empty), and flushes the output
+    // it returns the header that was written, along with the output
+    pub fn finish(mut self) -> Result<(Header, W)> {
+        self.streams
+            .encrypt_block(self.position, true, &self.aad, &mut self.buffer)
+            .map_err(|_| Error::Encrypt)?;
+        self.output.write_all(&self.buffer)?;
+        self.output.flush()?;
+
+        Ok((self.header, self.output))
+    }
+
+    // a full block is never the final block, so it can be encrypted as soon as it's full
+    fn write_block(&mut self) -> Result<()> {
+        self.streams
+            .encrypt_block(self.position, false, &self.aad, &mut self.buffer)
+            .map_err(|_| Error::Encrypt)?;
+        self.output.write_all(&self.buffer)?;
+        self.buffer.clear();
+
+        self.position = next_position(self.position, 1).ok_or(Error::Encrypt)?;
+
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for EncryptWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let count = buf.len().min(self.header.block_size - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..count]);
+
+        if self.buffer.len() == self.header.block_size {
+            self.write_block().map_err(io_error)?;
+        }
+
+        Ok(count)
+    }
+
+    // the buffered data can't be written until the block is full (or the writer is finished)
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.output.flush()
+    }
+}
+
+// this decrypts data in stream mode as it's read, and it can seek to anywhere within the decrypted data
+// so an archive (or anything else that needs to seek) can be read straight from an encrypted file, without writing the plaintext anywhere
+// the input must be positioned just after the header, and only one block is decrypted (and held in memory) at a time
+// every block is authenticated before any of it is returned, and the final block is authenticated up front, so a truncated file is always detected
+pub struct DecryptReader<R: Read + Seek> {
+    input: R,
+    streams: StreamCiphers,
+    aad: Vec<u8>,
+    layout: Layout,
+    block_size: u64,
+    buffer: Vec<u8>,
+    block: Option<u64>, // the index of the block that's currently in the buffer
+    position: u64,
+    key_checked: bool, // see block_error()
+}
+
+impl<R: Read + Seek> DecryptReader<R> {
+    pub fn new(mut input: R, header: &Header, key: DecryptionKey) -> Result<Self> {
+        let layout = Layout::read(&mut input, header)?;
+
+        let key = unlock(header, key)?;
+        let streams = StreamCiphers::initialize(key, &header.nonce, header.cipher_type)?;
+
+        let mut reader = DecryptReader {
+            input,
+            streams,
+            aad: header.aad(),
+            layout,
+            block_size: header.block_size as u64,
+            buffer: Vec::with_capacity(header.block_size + 16),
+            block: None,
+            position: 0,
+            key_checked: has_key_check(header),
+        };
+        reader.load_block(layout.last_block)?;
+
+        Ok(reader)
+    }
+
+    // this returns the length of the decrypted data
+    pub fn len(&self) -> u64 {
+        self.layout.plaintext_len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layout.plaintext_len == 0
+    }
+
+    // this reads and decrypts a block into the buffer, unless it's already there
+    fn load_block(&mut self, block: u64) -> Result<()> {
+        if self.block == Some(block) {
+            return Ok(());
+        }
+        self.block = None;
+
+        let encrypted_block_size = self.block_size + 16; // 16 bytes is the length of the AEAD tag
+        let len = if block == self.layout.last_block {
+            self.layout.last_block_len
+        } else {
+            encrypted_block_size
+        };
+
+        let offset = self.layout.data_start + block * encrypted_block_size;
+        self.input.seek(SeekFrom::Start(offset))?;
+        self.buffer.resize(len as usize, 0);
+        self.input.read_exact(&mut self.buffer)?;
+
+        let position = u32::try_from(block).map_err(|_| Error::Decrypt)?;
+        self.streams
+            .decrypt_block(
+                position,
+                block == self.layout.last_block,
+                &self.aad,
+                &mut self.buffer,
+            )
+            .map_err(|_| block_error(self.key_checked, block, offset))?;
+
+        self.block = Some(block);
+        self.key_checked = true;
+
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Read for DecryptReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position >= self.layout.plaintext_len || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let block = self.position / self.block_size;
+        self.load_block(block).map_err(io_error)?;
+
+        let offset = (self.position - block * self.block_size) as usize;
+        let count = buf.len().min(self.buffer.len() - offset);
+        buf[..count].copy_from_slice(&self.buffer[offset..offset + count]);
+        self.position += count as u64;
+
+        Ok(count)
+    }
+}
+
+impl<R: Read + Seek> Seek for DecryptReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.layout.plaintext_len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+
+        self.position = position.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+
+        Ok(self.position)
+    }
+}
+
+// this describes where the blocks of an encrypted stream are, which is worked out from the length of the input
+#[derive(Clone, Copy)]
+struct Layout {
+    data_start: u64,
+    last_block: u64,
+    last_block_len: u64,
+    plaintext_len: u64,
+}
+
+impl Layout {
+    // the input must be positioned just after the header
+    fn read(input: &mut impl Seek, header: &Header) -> Result<Self> {
+        let encrypted_block_size = header.block_size as u64 + 16; // 16 bytes is the length of the AEAD tag
+
+        let data_start = input.stream_position()?;
+        let data_len = input.seek(SeekFrom::End(0))? - data_start;
+
+        // the final block is always shorter than the others (it may just be the AEAD tag), so the file has been truncated if it isn't
+        let last_block = data_len / encrypted_block_size;
+        let last_block_len = data_len % encrypted_block_size;
+        if last_block_len < 16 {
+            return Err(Error::CorruptBlock {
+                index: last_block,
+                offset: data_start + last_block * encrypted_block_size,
+            });
+        }
+
+        Ok(Layout {
+            data_start,
+            last_block,
+            last_block_len,
+            plaintext_len: data_len - (last_block + 1) * 16,
+        })
+    }
+}
+
+// this decides what a block that failed authentication means
+// once the key has been checked (by a key slot, or by a block that was decrypted successfully), the block must be corrupt
+// otherwise, the key may just be wrong, and there's no way to tell the two apart
+fn block_error(key_checked: bool, index: u64, offset: u64) -> Error {
+    if key_checked {
+        Error::CorruptBlock { index, offset }
+    } else {
+        Error::WrongKeyOrCorrupt
+    }
+}
+
+// errors within Read/Write implementations have to be io errors, so the library error is wrapped within one
+// io errors are passed through as they are
+fn io_error(err: Error) -> std::io::Error {
+    match err {
+        Error::Io(err) => err,
+        err => std::io::Error::new(std::io::ErrorKind::InvalidData, err),
+    }
+}
+
+// this is why process_blocks() stopped early
+enum BlockError {
+    Io(Error),
+    Cipher(u64), // the index of the block that the cipher failed on
+    TooManyBlocks,
+}
+
+// this runs the cipher over every block of the input on a pool of `threads` worker threads, and writes the results to the output in order
+// this thread reads the blocks and writes the results, so reading and writing overlap with the cipher (the reader and writer don't need to be Send)
+// up to 2 * `threads` blocks are in flight at once, and their buffers are reused once they've been written
+// a block that's shorter than block_size (even an empty one) is always the final block
+// if the cipher fails, nothing from that block onwards is written
+fn process_blocks<F>(
+    input: &mut impl Read,
+    output: &mut impl Write,
+    block_size: usize,
+    threads: usize,
+    cipher: F,
+) -> std::result::Result<(), BlockError>
+where
+    F: Fn(u32, bool, &mut Vec<u8>) -> aead::Result<()> + Sync,
+{
+    let threads = threads.max(1);
+    let max_in_flight = threads * 2;
+
+    let (job_sender, job_receiver) = mpsc::sync_channel::<(u32, bool, Vec<u8>)>(max_in_flight);
+    let (result_sender, result_receiver) = mpsc::sync_channel(max_in_flight);
+    let job_receiver = Mutex::new(job_receiver);
+    let cipher = &cipher;
+
+    // the channels are moved into the scope, so they're closed (and the workers stop) as soon as this returns, even if it returns early
+    std::thread::scope(|scope| {
+        let job_sender = job_sender;
+        let result_receiver = result_receiver;
+
+        for _ in 0..threads {
+            let job_receiver = &job_receiver;
+            let result_sender = result_sender.clone();
+
+            scope.spawn(move || loop {
+                let job = job_receiver
+                    .lock()
+                    .map_err(|_| ())
+                    .and_then(|receiver| receiver.recv().map_err(|_| ()));
+                let (position, last_block, mut buffer) = match job {
+                    Ok(job) => job,
+                    Err(_) => break,
+                };
+
+                let result = cipher(position, last_block, &mut buffer);
+                if result_sender.send((position, result, buffer)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(result_sender);
+
+        let mut spare_buffers: Vec<Vec<u8>> = Vec::new();
+        let mut finished = BTreeMap::new();
+        let mut read_count = 0u64;
+        let mut write_count = 0u64;
+        let mut last_block_read = false;
+
+        loop {
+            while !last_block_read && read_count - write_count < max_in_flight as u64 {
+                let position = u32::try_from(read_count).map_err(|_| BlockError::TooManyBlocks)?;
+
+                let mut buffer = spare_buffers
+                    .pop()
+                    .unwrap_or_else(|| Vec::with_capacity(block_size + 16));
+                buffer.resize(block_size, 0);
+                let len = read_block(input, &mut buffer).map_err(BlockError::Io)?;
+                buffer.truncate(len);
+                last_block_read = len < block_size;
+
+                job_sender
+                    .send((position, last_block_read, buffer))
+                    .map_err(|_| BlockError::Cipher(read_count))?;
+                read_count += 1;
+            }
+
+            if write_count == read_count {
+                return Ok(());
+            }
+
+            // the workers only stop early if one of them panicked, which is treated as the cipher failing
+            let (position, result, buffer) = result_receiver
+                .recv()
+                .map_err(|_| BlockError::Cipher(write_count))?;
+            finished.insert(u64::from(position), (result, buffer));
+
+            while let Some((result, buffer)) = finished.remove(&write_count) {
+                result.map_err(|_| BlockError::Cipher(write_count))?;
+                output
+                    .write_all(&buffer)
+                    .map_err(|err| BlockError::Io(Error::Io(err)))?;
+
+                spare_buffers.push(buffer);
+                write_count += 1;
+            }
+        }
+    })
+}
+
+// this returns the position of the block that's `offset` blocks after `position`
+// it returns None if the counter would overflow (the cipher itself rejects positions beyond the LE31 limit)
+fn next_position(position: u32, offset: usize) -> Option<u32> {
+    u32::try_from(offset)
+        .ok()
+        .and_then(|offset| position.checked_add(offset))
+}
+
+// this fills the buffer from the reader, unless EOF is reached first
+// a single read() call may return less than was requested, even when there's more data to come
+// it returns the amount of bytes that were read
+fn read_block(reader: &mut impl Read, buffer: &mut [u8]) -> Result<usize> {
+    let mut read_count = 0;
+
+    while read_count < buffer.len() {
+        match reader.read(&mut buffer[read_count..]) {
+            Ok(0) => break,
+            Ok(count) => read_count += count,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(Error::Io(err)),
+        }
+    }
+
+    Ok(read_count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::HeaderVersion;
+    use crate::test_utils::{data, password, raw_key};
+    use crate::MIN_BLOCK_SIZE;
+    use std::io::Cursor;
+
+    // this encrypts the data with the smallest block size, and returns the header along with the whole file
+    fn encrypt_file(
+        data: &[u8],
+        cipher_type: CipherType,
+        threads: Option<usize>,
+    ) -> (Header, Vec<u8>) {
+        let key = password("hunter2");
+
+        let mut file = Vec::new();
+        let (header, _) = match threads {
+            None => encrypt(&mut &data[..], &mut file, key, cipher_type, MIN_BLOCK_SIZE),
+            Some(threads) => encrypt_parallel(
+                &mut &data[..],
+                &mut file,
+                key,
+                cipher_type,
+                MIN_BLOCK_SIZE,
+                threads,
+            ),
+        }
+        .unwrap();
+
+        (header, file)
+    }
+
+    fn decrypt_file(file: &[u8], threads: Option<usize>) -> Result<Vec<u8>> {
+        let mut input = file;
+        let header = Header::deserialize(&mut input)?;
+        let key = DecryptionKey::Password(raw_key("hunter2"));
+
+        let mut output = Vec::new();
+        match threads {
+            None => decrypt(&mut input, &mut output, &header, key)?,
+            Some(threads) => decrypt_parallel(&mut input, &mut output, &header, key, threads)?,
+        }
+
+        Ok(output)
+    }
+
+    #[test]
+    fn round_trip() {
+        let lens = [
+            0,
+            1,
+            MIN_BLOCK_SIZE - 1,
+            MIN_BLOCK_SIZE,
+            MIN_BLOCK_SIZE * 2 + 100,
+        ];
+
+        for cipher_type in [CipherType::XChaCha20Poly1305, CipherType::AesGcm] {
+            for len in lens {
+                let data = data(len);
+                let (header, file) = encrypt_file(&data, cipher_type, None);
+                assert_eq!(header.version, HeaderVersion::V5);
+                assert_eq!(header.block_size, MIN_BLOCK_SIZE);
+
+                let encrypted_len = file.len() - header.serialize().len();
+                assert_eq!(header.data_len(encrypted_len as u64), Some(len as u64));
+                assert_eq!(header.cipher_mode, CipherMode::StreamMode);
+                assert_eq!(decrypt_file(&file, None).unwrap(), data);
+            }
+        }
+    }
+
+    // files encrypted on many threads are identical to those encrypted on one, so either may decrypt the other
+    #[test]
+    fn parallel_round_trip() {
+        // the longest input has more blocks than can be in flight at once, even with 4 threads
+        for len in [
+            0,
+            MIN_BLOCK_SIZE,
+            MIN_BLOCK_SIZE * 3 + 7,
+            MIN_BLOCK_SIZE * 9 + 7,
+        ] {
+            let data = data(len);
+
+            for threads in [1, 2, 4] {
+                let (_, file) = encrypt_file(&data, CipherType::XChaCha20Poly1305, Some(threads));
+                assert_eq!(decrypt_file(&file, None).unwrap(), data);
+                assert_eq!(decrypt_file(&file, Some(3)).unwrap(), data);
+            }
+
+            let (_, file) = encrypt_file(&data, CipherType::AesGcm, None);
+            assert_eq!(decrypt_file(&file, Some(2)).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn verify_returns_the_data_len() {
+        let data = data(MIN_BLOCK_SIZE * 2 + 5);
+        let (_, file) = encrypt_file(&data, CipherType::XChaCha20Poly1305, None);
+
+        let mut input = file.as_slice();
+        let header = Header::deserialize(&mut input).unwrap();
+        let key = DecryptionKey::Password(raw_key("hunter2"));
+        assert_eq!(verify(&mut input, &header, key).unwrap(), data.len() as u64);
+    }
+
+    #[test]
+    fn tampered_blocks_are_corrupt() {
+        let data = data(MIN_BLOCK_SIZE * 2 + 100);
+        let (header, file) = encrypt_file(&data, CipherType::XChaCha20Poly1305, None);
+        let header_len = header.serialize().len() as u64;
+        let encrypted_block_size = (MIN_BLOCK_SIZE + 16) as u64;
+
+        for index in 0..3u64 {
+            let offset = header_len + index * encrypted_block_size;
+            let mut tampered = file.clone();
+            tampered[offset as usize + 10] ^= 1;
+
+            for threads in [None, Some(2)] {
+                assert!(matches!(
+                    decrypt_file(&tampered, threads),
+                    Err(Error::CorruptBlock { index: i, offset: o }) if i == index && o == offset
+                ));
+            }
+
+            let mut input = &tampered[header_len as usize..];
+            let key = DecryptionKey::Password(raw_key("hunter2"));
+            assert!(matches!(
+                verify(&mut input, &header, key),
+                Err(Error::CorruptBlock { index: i, offset: o }) if i == index && o == offset
+            ));
+        }
+    }
+
+    // blocks are written as soon as they've been authenticated, but nothing from the corrupt block onwards is
+    #[test]
+    fn parallel_decryption_stops_at_the_corrupt_block() {
+        let data = data(MIN_BLOCK_SIZE * 9 + 7);
+        let (header, mut file) = encrypt_file(&data, CipherType::XChaCha20Poly1305, Some(4));
+        let header_len = header.serialize().len();
+        file[header_len + 5 * (MIN_BLOCK_SIZE + 16) + 10] ^= 1;
+
+        let mut input = &file[header_len..];
+        let mut output = Vec::new();
+        let key = DecryptionKey::Password(raw_key("hunter2"));
+        assert!(matches!(
+            decrypt_parallel(&mut input, &mut output, &header, key, 4),
+            Err(Error::CorruptBlock { index: 5, .. })
+        ));
+        assert_eq!(output, data[..MIN_BLOCK_SIZE * 5]);
+    }
+
+    // the final block is flagged as the last one, so removing blocks from the end (or swapping them around) is detected
+    #[test]
+    fn truncated_and_reordered_files_are_corrupt() {
+        let data = data(MIN_BLOCK_SIZE * 2);
+        let (header, file) = encrypt_file(&data, CipherType::XChaCha20Poly1305, None);
+        let header_len = header.serialize().len();
+        let encrypted_block_size = MIN_BLOCK_SIZE + 16;
+
+        // the data fills every block exactly, so the final block is just the AEAD tag - and it's missing here
+        let truncated = &file[..header_len + encrypted_block_size * 2];
+        assert!(matches!(
+            decrypt_file(truncated, None),
+            Err(Error::CorruptBlock { index: 2, .. })
+        ));
+
+        let truncated = &file[..file.len() - 1];
+        assert!(matches!(
+            decrypt_file(truncated, Some(2)),
+            Err(Error::CorruptBlock { .. })
+        ));
+
+        let mut reordered = file.clone();
+        let (first, rest) = reordered[header_len..].split_at_mut(encrypted_block_size);
+        first.swap_with_slice(&mut rest[..encrypted_block_size]);
+        assert!(matches!(
+            decrypt_file(&reordered, None),
+            Err(Error::CorruptBlock { index: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn tampered_header_is_corrupt() {
+        let (header, file) = encrypt_file(&data(100), CipherType::XChaCha20Poly1305, None);
+
+        // the nonce is just before the key slots, and it's authenticated with every block
+        let mut tampered = file;
+        tampered[header.aad().len() - 1] ^= 1;
+        assert!(matches!(
+            decrypt_file(&tampered, None),
+            Err(Error::CorruptBlock { index: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn wrong_key_is_rejected() {
+        let (header, file) = encrypt_file(&data(100), CipherType::XChaCha20Poly1305, None);
+
+        let mut input = &file[header.serialize().len()..];
+        assert!(matches!(
+            decrypt(
+                &mut input,
+                &mut Vec::new(),
+                &header,
+                DecryptionKey::Password(raw_key("hunter3"))
+            ),
+            Err(Error::NoMatchingKeyslot)
+        ));
+    }
+
+    fn decrypt_file_range(file: &[u8], range: Range<u64>) -> (Result<()>, Vec<u8>) {
+        let mut input = Cursor::new(file);
+        let header = Header::deserialize(&mut input).unwrap();
+        let key = DecryptionKey::Password(raw_key("hunter2"));
+
+        let mut output = Vec::new();
+        let result = decrypt_range(&mut input, &mut output, &header, key, range);
+        (result, output)
+    }
+
+    #[test]
+    fn ranges_round_trip() {
+        let len = MIN_BLOCK_SIZE as u64 * 3 + 100;
+        let data = data(len as usize);
+        let (_, file) = encrypt_file(&data, CipherType::XChaCha20Poly1305, None);
+        let block = MIN_BLOCK_SIZE as u64;
+
+        let ranges = [
+            0..1,
+            0..len,
+            10..20,
+            block - 1..block + 1,
+            block..block * 2,
+            block * 3..len,
+            len - 1..len,
+            5..len * 2, // the end is clamped to the length of the data
+        ];
+
+        for range in ranges {
+            let (result, output) = decrypt_file_range(&file, range.clone());
+            result.unwrap();
+
+            let end = range.end.min(len);
+            assert_eq!(output, data[range.start as usize..end as usize]);
+        }
+
+        let (result, output) = decrypt_file_range(&file, len..len);
+        result.unwrap();
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn invalid_ranges_are_rejected() {
+        let (_, file) = encrypt_file(&data(100), CipherType::XChaCha20Poly1305, None);
+
+        #[allow(clippy::reversed_empty_ranges)]
+        for range in [101..200, 50..10] {
+            let (result, output) = decrypt_file_range(&file, range);
+            assert!(matches!(result, Err(Error::InvalidRange)));
+            assert!(output.is_empty());
+        }
+    }
+
+    // the final block is always authenticated, so nothing is written if the file has been truncated or the final block has been modified
+    #[test]
+    fn ranges_detect_truncation_and_tampering() {
+        let data = data(MIN_BLOCK_SIZE * 3 + 100);
+        let (header, file) = encrypt_file(&data, CipherType::XChaCha20Poly1305, None);
+        let header_len = header.serialize().len();
+        let encrypted_block_size = MIN_BLOCK_SIZE + 16;
+
+        let truncated = &file[..header_len + encrypted_block_size * 3];
+        let (result, output) = decrypt_file_range(truncated, 0..10);
+        assert!(matches!(result, Err(Error::CorruptBlock { index: 3, .. })));
+        assert!(output.is_empty());
+
+        let mut tampered = file.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        let (result, output) = decrypt_file_range(&tampered, 0..10);
+        assert!(matches!(result, Err(Error::CorruptBlock { index: 3, .. })));
+        assert!(output.is_empty());
+
+        let mut tampered = file;
+        tampered[header_len + encrypted_block_size + 10] ^= 1;
+        let (result, _) = decrypt_file_range(&tampered, 10..MIN_BLOCK_SIZE as u64 * 2);
+        assert!(matches!(result, Err(Error::CorruptBlock { index: 1, .. })));
+    }
+
+    // the writer's output is the same as encrypt()'s, however the data is split up between writes
+    #[test]
+    fn encrypt_writer_round_trips() {
+        for len in [0, 100, MIN_BLOCK_SIZE, MIN_BLOCK_SIZE * 2 + 100] {
+            let data = data(len);
+
+            let mut writer = EncryptWriter::with_content(
+                Vec::new(),
+                password("hunter2"),
+                CipherType::AesGcm,
+                MIN_BLOCK_SIZE,
+                ContentType::TarArchive,
+            )
+            .unwrap();
+            for chunk in data.chunks(1000) {
+                writer.write_all(chunk).unwrap();
+            }
+            let (header, file) = writer.finish().unwrap();
+            assert_eq!(header.version, HeaderVersion::V6);
+
+            let mut input = file.as_slice();
+            let read = Header::deserialize(&mut input).unwrap();
+            assert_eq!(read.content, ContentType::TarArchive);
+            assert_eq!(decrypt_file(&file, None).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn decrypt_reader_reads_and_seeks() {
+        let data = data(MIN_BLOCK_SIZE * 2 + 100);
+        let (_, file) = encrypt_file(&data, CipherType::XChaCha20Poly1305, None);
+
+        let mut input = Cursor::new(file);
+        let header = Header::deserialize(&mut input).unwrap();
+        let key = DecryptionKey::Password(raw_key("hunter2"));
+        let mut reader = DecryptReader::new(input, &header, key).unwrap();
+        assert_eq!(reader.len(), data.len() as u64);
+
+        let mut decrypted = Vec::new();
+        reader.read_to_end(&mut decrypted).unwrap();
+        assert_eq!(decrypted, data);
+
+        let mut buffer = [0u8; 20];
+        reader
+            .seek(SeekFrom::Start(MIN_BLOCK_SIZE as u64 - 10))
+            .unwrap();
+        reader.read_exact(&mut buffer).unwrap();
+        assert_eq!(buffer, data[MIN_BLOCK_SIZE - 10..MIN_BLOCK_SIZE + 10]);
+
+        reader.seek(SeekFrom::End(-20)).unwrap();
+        reader.read_exact(&mut buffer).unwrap();
+        assert_eq!(buffer, data[data.len() - 20..]);
+        assert_eq!(reader.read(&mut buffer).unwrap(), 0);
+
+        assert!(reader
+            .seek(SeekFrom::Current(-(data.len() as i64) - 1))
+            .is_err());
+    }
+
+    #[test]
+    fn decrypt_reader_detects_tampering() {
+        let data = data(MIN_BLOCK_SIZE * 2 + 100);
+        let (header, file) = encrypt_file(&data, CipherType::XChaCha20Poly1305, None);
+        let header_len = header.serialize().len();
+
+        // the final block is checked as soon as the reader is created
+        let truncated = file[..header_len + (MIN_BLOCK_SIZE + 16) * 2].to_vec();
+        let mut input = Cursor::new(truncated);
+        input.set_position(header_len as u64);
+        let key = DecryptionKey::Password(raw_key("hunter2"));
+        assert!(matches!(
+            DecryptReader::new(input, &header, key),
+            Err(Error::CorruptBlock { index: 2, .. })
+        ));
+
+        let mut tampered = file;
+        tampered[header_len + 10] ^= 1;
+        let mut input = Cursor::new(tampered);
+        input.set_position(header_len as u64);
+        let key = DecryptionKey::Password(raw_key("hunter2"));
+        let mut reader = DecryptReader::new(input, &header, key).unwrap();
+
+        let err = reader.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    // a wrong key can only be told apart from corrupt data once a key slot has been unlocked, or a block has been decrypted
+    #[test]
+    fn block_errors_depend_on_the_key_check() {
+        assert!(matches!(
+            block_error(true, 0, 100),
+            Error::CorruptBlock {
+                index: 0,
+                offset: 100
+            }
+        ));
+        assert!(matches!(
+            block_error(false, 0, 100),
+            Error::WrongKeyOrCorrupt
+        ));
+    }
+}
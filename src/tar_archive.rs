@@ -0,0 +1,425 @@
+use crate::global::{OwnershipMode, SkipMode};
+use crate::prompt::get_answer;
+use anyhow::{Context, Result};
+use bzip2::read::BzDecoder;
+use bzip2::write::BzEncoder;
+use bzip2::Compression;
+use dexios::ProgressReader;
+use std::collections::HashMap;
+use std::fs::{File, Metadata};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use tar::{Archive, Builder, EntryType, Header, HeaderMode};
+
+// this file handles the tar pack format, which keeps the unix metadata that zip archives lose
+// permissions, ownership, timestamps, symlinks, hard links, special files and extended attributes are all stored
+// the archive is compressed with bzip2 (the same as zip archives), and it's written and read front-to-back, so it never needs to seek
+
+// this writes every entry to a bzip2-compressed tar archive, and returns the output once the archive is complete
+// entries are named relative to the input's parent directory, so the input directory is the root of the archive
+// symlinks are stored as symlinks, and are never followed
+pub fn write<W: Write>(
+    input: &str,
+    output: W,
+    entries: &[PathBuf],
+    compression_level: u32,
+) -> Result<W> {
+    let encoder = BzEncoder::new(output, Compression::new(compression_level));
+    let mut tar = Builder::new(encoder);
+    tar.follow_symlinks(false);
+
+    let root = Path::new(input)
+        .canonicalize()
+        .with_context(|| format!("Unable to get the full path of {}", input))?
+        .file_name()
+        .map_or_else(|| PathBuf::from("root"), PathBuf::from);
+
+    // the progress bar covers the data in every file, as that's what takes the time to compress
+    let total_len = entries
+        .iter()
+        .filter_map(|entry| entry.symlink_metadata().ok())
+        .filter(Metadata::is_file)
+        .map(|metadata| metadata.len())
+        .sum();
+    let bar = crate::progress_bar::new(Some(total_len));
+
+    let mut links = HashMap::new();
+
+    for path in std::iter::once(Path::new(input)).chain(entries.iter().map(PathBuf::as_path)) {
+        let name = root.join(path.strip_prefix(input).unwrap_or(path));
+        let metadata = path
+            .symlink_metadata()
+            .with_context(|| format!("Unable to get the metadata of {}", path.display()))?;
+
+        append_xattrs(&mut tar, path)?;
+
+        // every other name for a hard-linked file is stored as a link to the first one, so the data is only stored once
+        if let Some(target) = hard_link_target(&mut links, &metadata, &name) {
+            let mut header = Header::new_gnu();
+            header.set_metadata_in_mode(&metadata, HeaderMode::Complete);
+            header.set_entry_type(EntryType::Link);
+            header.set_size(0);
+            tar.append_link(&mut header, &name, target)
+                .with_context(|| format!("Unable to add {} to the archive", path.display()))?;
+            continue;
+        }
+
+        if metadata.is_file() {
+            crate::progress_bar::println(&bar, &format!("Compressing {}", path.display()));
+
+            let mut header = Header::new_gnu();
+            header.set_metadata_in_mode(&metadata, HeaderMode::Complete);
+
+            let mut file =
+                File::open(path).with_context(|| format!("Unable to open {}", path.display()))?;
+            let start = bar.position();
+            let reader = ProgressReader::new(&mut file, |bytes| bar.set_position(start + bytes));
+            tar.append_data(&mut header, &name, reader)
+                .with_context(|| format!("Unable to add {} to the archive", path.display()))?;
+        } else {
+            tar.append_path_with_name(path, &name)
+                .with_context(|| format!("Unable to add {} to the archive", path.display()))?;
+        }
+    }
+    bar.finish_and_clear();
+
+    tar.into_inner()
+        .context("Unable to finish the archive")?
+        .finish()
+        .context("Unable to finish the archive")
+}
+
+// this extracts every entry from a bzip2-compressed tar archive into the output directory
+// permissions, timestamps and extended attributes are restored, along with the owner and group if requested
+// entries that would end up outside of the output directory are skipped
+pub fn extract(
+    reader: impl Read,
+    output: &str,
+    ownership: OwnershipMode,
+    skip: SkipMode,
+) -> Result<()> {
+    match std::fs::create_dir(output) {
+        Ok(_) => eprintln!("Created output directory: {}", output),
+        Err(_) => eprintln!("Output directory ({}) already exists!", output),
+    }
+
+    let mut archive = Archive::new(BzDecoder::new(reader));
+    archive.set_preserve_permissions(true);
+    archive.set_preserve_mtime(true);
+    archive.set_preserve_ownerships(ownership == OwnershipMode::RestoreOwnership);
+    // these are restored separately, so that attributes which can't be set here (e.g. security.*) don't stop the extraction
+    archive.set_unpack_xattrs(false);
+
+    // directories are extracted last, so their timestamps (and read-only permissions) aren't changed by extracting their contents
+    let mut directories = Vec::new();
+
+    for entry in archive
+        .entries()
+        .context("Unable to read the archive, is it a tar file?")?
+    {
+        let mut entry = entry.context("Unable to read the next entry from the archive")?;
+        let path = entry
+            .path()
+            .context("Unable to read an entry's path")?
+            .into_owned();
+        let full_path = Path::new(output).join(&path);
+
+        let entry_type = entry.header().entry_type();
+        if entry_type == EntryType::Directory {
+            directories.push(entry);
+            continue;
+        }
+
+        // symlink_metadata() is used, so an existing symlink isn't followed
+        if full_path.symlink_metadata().is_ok() && is_inside(output, &path, &full_path)? {
+            let answer = get_answer(
+                &format!(
+                    "{} already exists, would you like to overwrite?",
+                    full_path.display()
+                ),
+                true,
+                skip == SkipMode::HidePrompts,
+            )?;
+            if !answer {
+                eprintln!("Skipping {}", full_path.display());
+                continue;
+            }
+
+            // the tar crate can't replace an existing link (or special file), so it's removed first
+            std::fs::remove_file(&full_path)
+                .with_context(|| format!("Unable to remove {}", full_path.display()))?;
+        }
+
+        eprintln!("Extracting {}", path.display());
+        if is_special(entry_type) {
+            unpack_special(&mut entry, output, &path, ownership)?;
+        } else {
+            unpack(&mut entry, output, &full_path)?;
+        }
+    }
+
+    directories.sort_by(|a, b| b.path_bytes().cmp(&a.path_bytes()));
+    for mut entry in directories {
+        let path = entry
+            .path()
+            .context("Unable to read an entry's path")?
+            .into_owned();
+        let full_path = Path::new(output).join(path);
+        if unpack(&mut entry, output, &full_path)? {
+            restore_mtime(&entry, &full_path)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn is_special(entry_type: EntryType) -> bool {
+    matches!(
+        entry_type,
+        EntryType::Fifo | EntryType::Char | EntryType::Block
+    )
+}
+
+// this returns whether the entry was extracted
+fn unpack<R: Read>(entry: &mut tar::Entry<R>, output: &str, full_path: &Path) -> Result<bool> {
+    let unpacked = entry
+        .unpack_in(output)
+        .with_context(|| format!("Unable to extract {}", full_path.display()))?;
+
+    if unpacked {
+        restore_xattrs(entry, full_path)?;
+    } else {
+        eprintln!(
+            "Skipping {} as it would be outside of the output directory",
+            full_path.display()
+        );
+    }
+
+    Ok(unpacked)
+}
+
+// the tar crate doesn't restore the timestamps of directories
+fn restore_mtime<R: Read>(entry: &tar::Entry<R>, full_path: &Path) -> Result<()> {
+    let mtime = entry
+        .header()
+        .mtime()
+        .context("Unable to read an entry's timestamp")?;
+
+    // the other filetime functions open the file, which would block on a fifo
+    // the access time is set as well, in the same way as the tar crate does it
+    let mtime = filetime::FileTime::from_unix_time(mtime as i64, 0);
+    filetime::set_symlink_file_times(full_path, mtime, mtime)
+        .with_context(|| format!("Unable to set the timestamp of {}", full_path.display()))
+}
+
+// the tar crate extracts fifos and device files as empty regular files, so they're created here instead
+// the path is checked in the same way as unpack_in() does it, so nothing can be created outside of the output directory
+// creating a device file needs root, so a failure is only a warning
+#[cfg(all(unix, not(target_vendor = "apple")))]
+fn unpack_special<R: Read>(
+    entry: &mut tar::Entry<R>,
+    output: &str,
+    path: &Path,
+    ownership: OwnershipMode,
+) -> Result<()> {
+    use rustix::fs::{chown, makedev, mknodat, FileType, Gid, Mode, Uid, CWD};
+    use std::os::unix::fs::PermissionsExt;
+
+    let full_path = Path::new(output).join(path);
+    if !is_inside(output, path, &full_path)? {
+        eprintln!(
+            "Skipping {} as it would be outside of the output directory",
+            full_path.display()
+        );
+        return Ok(());
+    }
+
+    let header = entry.header();
+    let mode = header.mode().context("Unable to read an entry's mode")? & 0o7777;
+    let (file_type, dev) = match header.entry_type() {
+        EntryType::Fifo => (FileType::Fifo, 0),
+        entry_type => {
+            let major = header.device_major().ok().flatten().unwrap_or_default();
+            let minor = header.device_minor().ok().flatten().unwrap_or_default();
+            let file_type = if entry_type == EntryType::Char {
+                FileType::CharacterDevice
+            } else {
+                FileType::BlockDevice
+            };
+            (file_type, makedev(major, minor))
+        }
+    };
+
+    if let Err(err) = mknodat(CWD, &full_path, file_type, Mode::from_raw_mode(mode), dev) {
+        eprintln!("Unable to create {}: {}", full_path.display(), err);
+        return Ok(());
+    }
+
+    // mknod() is affected by the umask, so the permissions are set again
+    std::fs::set_permissions(&full_path, std::fs::Permissions::from_mode(mode))
+        .with_context(|| format!("Unable to set the permissions of {}", full_path.display()))?;
+
+    if ownership == OwnershipMode::RestoreOwnership {
+        let uid = header.uid().context("Unable to read an entry's owner")?;
+        let gid = header.gid().context("Unable to read an entry's group")?;
+        chown(
+            &full_path,
+            Some(Uid::from_raw(uid as u32)),
+            Some(Gid::from_raw(gid as u32)),
+        )
+        .with_context(|| format!("Unable to set the owner of {}", full_path.display()))?;
+    }
+
+    restore_mtime(entry, &full_path)?;
+    restore_xattrs(entry, &full_path)
+}
+
+#[cfg(not(all(unix, not(target_vendor = "apple"))))]
+fn unpack_special<R: Read>(
+    _entry: &mut tar::Entry<R>,
+    output: &str,
+    path: &Path,
+    _ownership: OwnershipMode,
+) -> Result<()> {
+    eprintln!(
+        "Skipping {} as special files can't be created on this platform",
+        Path::new(output).join(path).display()
+    );
+    Ok(())
+}
+
+// the path must be relative without any "..", and the parent directory (which may already exist) must resolve to somewhere within the output directory
+fn is_inside(output: &str, path: &Path, full_path: &Path) -> Result<bool> {
+    use std::path::Component;
+
+    if !path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return Ok(false);
+    }
+
+    let parent = full_path
+        .parent()
+        .context("Unable to get the parent directory of an entry")?;
+    std::fs::create_dir_all(parent)
+        .with_context(|| format!("Unable to create {}", parent.display()))?;
+
+    let output = Path::new(output)
+        .canonicalize()
+        .with_context(|| format!("Unable to get the full path of {}", output))?;
+    let parent = parent
+        .canonicalize()
+        .with_context(|| format!("Unable to get the full path of {}", parent.display()))?;
+
+    Ok(parent.starts_with(output))
+}
+
+#[cfg(unix)]
+fn hard_link_target(
+    links: &mut HashMap<(u64, u64), PathBuf>,
+    metadata: &Metadata,
+    name: &Path,
+) -> Option<PathBuf> {
+    use std::os::unix::fs::MetadataExt;
+
+    if !metadata.is_file() || metadata.nlink() < 2 {
+        return None;
+    }
+
+    match links.entry((metadata.dev(), metadata.ino())) {
+        std::collections::hash_map::Entry::Occupied(entry) => Some(entry.get().clone()),
+        std::collections::hash_map::Entry::Vacant(entry) => {
+            entry.insert(name.to_path_buf());
+            None
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn hard_link_target(
+    _links: &mut HashMap<(u64, u64), PathBuf>,
+    _metadata: &Metadata,
+    _name: &Path,
+) -> Option<PathBuf> {
+    None
+}
+
+// extended attributes are stored as PAX extensions, in the same way as GNU tar and bsdtar
+// attributes can't be read from some filesystems, in which case there just aren't any to store
+#[cfg(unix)]
+fn append_xattrs<W: Write>(tar: &mut Builder<W>, path: &Path) -> Result<()> {
+    let Ok(names) = xattr::list(path) else {
+        return Ok(());
+    };
+
+    let mut extensions = Vec::new();
+    for name in names {
+        let Some(key) = name.to_str() else {
+            eprintln!(
+                "Skipping an extended attribute of {} as its name isn't valid UTF-8",
+                path.display()
+            );
+            continue;
+        };
+
+        if let Ok(Some(value)) = xattr::get(path, &name) {
+            extensions.push((format!("SCHILY.xattr.{}", key), value));
+        }
+    }
+
+    tar.append_pax_extensions(
+        extensions
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_slice())),
+    )
+    .with_context(|| {
+        format!(
+            "Unable to add the extended attributes of {} to the archive",
+            path.display()
+        )
+    })
+}
+
+#[cfg(not(unix))]
+fn append_xattrs<W: Write>(_tar: &mut Builder<W>, _path: &Path) -> Result<()> {
+    Ok(())
+}
+
+// attributes that can't be set (e.g. security.* attributes without root, or on a filesystem that doesn't support them) are skipped with a warning
+#[cfg(unix)]
+fn restore_xattrs<R: Read>(entry: &mut tar::Entry<R>, full_path: &Path) -> Result<()> {
+    let Some(extensions) = entry
+        .pax_extensions()
+        .context("Unable to read an entry's PAX extensions")?
+    else {
+        return Ok(());
+    };
+
+    for extension in extensions {
+        let extension = extension.context("Unable to read an entry's PAX extensions")?;
+        let Some(name) = extension
+            .key()
+            .ok()
+            .and_then(|key| key.strip_prefix("SCHILY.xattr."))
+        else {
+            continue;
+        };
+
+        if let Err(err) = xattr::set(full_path, name, extension.value_bytes()) {
+            eprintln!(
+                "Unable to restore the {} attribute of {}: {}",
+                name,
+                full_path.display(),
+                err
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn restore_xattrs<R: Read>(_entry: &mut tar::Entry<R>, _full_path: &Path) -> Result<()> {
+    Ok(())
+}
@@ -0,0 +1,28 @@
+// this file contains the fixtures that are shared between the library's tests
+
+use crate::kdf::KdfParams;
+use crate::keys::EncryptionKey;
+use secrecy::Secret;
+
+// the lowest cost that argon2id accepts, so the tests stay fast
+pub const KDF_PARAMS: KdfParams = KdfParams {
+    m_cost: 8,
+    t_cost: 1,
+    p_cost: 1,
+};
+
+pub fn raw_key(key: &str) -> Secret<Vec<u8>> {
+    Secret::new(key.as_bytes().to_vec())
+}
+
+pub fn password(key: &str) -> EncryptionKey {
+    EncryptionKey::Password {
+        raw_key: raw_key(key),
+        kdf_params: KDF_PARAMS,
+    }
+}
+
+// this is data that doesn't repeat within a block, so misplaced blocks can't go unnoticed
+pub fn data(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
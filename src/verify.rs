@@ -0,0 +1,60 @@
+use crate::decrypt::read_header;
+use crate::file::open_input;
+use crate::global::Parameters;
+use crate::key::get_decryption_key;
+use crate::progress_bar;
+use crate::report;
+use anyhow::{Context, Ok, Result};
+use dexios::{CipherMode, ProgressReader};
+use std::time::Instant;
+
+// this checks that an encrypted file is intact, by authenticating every block (and the header) without writing anything
+// it needs the key, as the AEAD tags can't be checked without it
+// if the file has been modified, truncated or corrupted, the first bad block is reported (and main exits with the "corrupt" exit code)
+pub fn verify(input: &str, keyfile: &str, memory: bool, params: &Parameters) -> Result<()> {
+    let mut reader = open_input(input)?;
+    let header = read_header(input, &mut reader, memory, params)?;
+
+    let key = get_decryption_key(keyfile, &header, params)?;
+
+    eprintln!(
+        "Verifying {} ({} with {}, {} header, {})",
+        input, header.cipher_mode, header.cipher_type, header.version, header.key_material
+    );
+    let verify_start_time = Instant::now();
+    let result = match header.cipher_mode {
+        CipherMode::MemoryMode => dexios::memory::verify(&mut reader, &header, key),
+        CipherMode::StreamMode => {
+            let bar = progress_bar::for_input(input, header.serialize().len() as u64);
+            let mut progress_reader =
+                ProgressReader::new(&mut reader, |bytes| bar.set_position(bytes));
+            let result = dexios::stream::verify(&mut progress_reader, &header, key);
+            bar.finish_and_clear();
+            result
+        }
+    };
+    let verify_duration = verify_start_time.elapsed();
+
+    report::record("input", input);
+    report::record("cipher", header.cipher_type.to_string());
+    report::record("header_version", header.version.to_string());
+
+    if let Err(dexios::Error::CorruptBlock { index, offset }) = &result {
+        report::record("intact", false);
+        report::record("corrupt_block", *index);
+        report::record("corrupt_offset", *offset);
+    }
+
+    let data_len = result.with_context(|| format!("Verification failed for {}", input))?;
+    report::record("intact", true);
+    report::record("bytes", data_len);
+
+    report::print_result(&format!(
+        "{} is intact ({} bytes of data) [took {:.2}s]",
+        input,
+        data_len,
+        verify_duration.as_secs_f32()
+    ));
+
+    Ok(())
+}
@@ -0,0 +1,430 @@
+use anyhow::{Context, Result};
+use bzip2::write::BzEncoder;
+use bzip2::Compression;
+use std::io::{Read, Write};
+
+// this file writes zip archives front-to-back, without ever seeking
+// the zip crate's writer seeks back to fill in each entry's sizes and crc once it has been written, so it needs a seekable output (e.g. a temporary file)
+// this writer puts them in a data descriptor after each entry instead, so the archive can be fed straight into the stream encryptor
+// every entry uses zip64 fields, so there are no limits on the size of the files or the archive
+// the output can be read by the zip crate's `ZipArchive` (and any other zip tool), as it uses the central directory
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x0403_4b50;
+const DATA_DESCRIPTOR_SIGNATURE: u32 = 0x0807_4b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x0201_4b50;
+const ZIP64_END_SIGNATURE: u32 = 0x0606_4b50;
+const ZIP64_LOCATOR_SIGNATURE: u32 = 0x0706_4b50;
+const END_SIGNATURE: u32 = 0x0605_4b50;
+
+const VERSION: u16 = 46; // 4.6 is needed for bzip2 (and zip64 needs 4.5)
+const VERSION_MADE_BY: u16 = (3 << 8) | VERSION; // 3 is unix, so the permissions are read from the external attributes
+const FLAGS: u16 = (1 << 3) | (1 << 11); // the sizes and crc are in a data descriptor, and the names are utf-8
+const STORED: u16 = 0;
+const BZIP2: u16 = 12;
+const DOS_DATE: u16 = (1 << 5) | 1; // 1980-01-01, the same as the zip crate's default
+const ZIP64_EXTRA_ID: u16 = 0x0001;
+
+// files and directories are all given 0o755 permissions, the same as before
+const FILE_ATTRIBUTES: u32 = (0o100_755 << 16) as u32;
+const DIRECTORY_ATTRIBUTES: u32 = (0o040_755 << 16) as u32 | 0x10; // 0x10 is the MS-DOS directory flag
+
+struct Entry {
+    name: String,
+    method: u16,
+    attributes: u32,
+    crc: u32,
+    compressed_len: u64,
+    len: u64,
+    header_offset: u64,
+}
+
+pub struct ZipWriter<W: Write> {
+    output: CountingWriter<W>,
+    entries: Vec<Entry>,
+    compression_level: u32,
+}
+
+impl<W: Write> ZipWriter<W> {
+    pub fn new(output: W, compression_level: u32) -> Self {
+        ZipWriter {
+            output: CountingWriter {
+                inner: output,
+                count: 0,
+            },
+            entries: Vec::new(),
+            compression_level,
+        }
+    }
+
+    // directory names must end with a '/'
+    pub fn add_directory(&mut self, name: &str) -> Result<()> {
+        let name = format!("{}/", name.trim_end_matches('/'));
+        self.start_entry(&name, STORED, DIRECTORY_ATTRIBUTES)?;
+        self.finish_entry(0, 0, 0)
+    }
+
+    // this compresses everything from the reader into a new entry
+    // `progress` is called with the number of bytes after each read, so a progress bar can be updated
+    pub fn add_file(
+        &mut self,
+        name: &str,
+        reader: &mut impl Read,
+        mut progress: impl FnMut(u64),
+    ) -> Result<()> {
+        self.start_entry(name, BZIP2, FILE_ATTRIBUTES)?;
+        let data_start = self.output.count;
+
+        let mut encoder =
+            BzEncoder::new(&mut self.output, Compression::new(self.compression_level));
+        let mut hasher = crc32fast::Hasher::new();
+        let mut len = 0u64;
+        let mut buffer = vec![0u8; dexios::BLOCK_SIZE];
+
+        loop {
+            let read_count = match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(count) => count,
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err).with_context(|| format!("Unable to read {}", name)),
+            };
+
+            hasher.update(&buffer[..read_count]);
+            encoder
+                .write_all(&buffer[..read_count])
+                .with_context(|| format!("Unable to compress {}", name))?;
+            len += read_count as u64;
+            progress(read_count as u64);
+        }
+
+        encoder
+            .finish()
+            .with_context(|| format!("Unable to compress {}", name))?;
+
+        let compressed_len = self.output.count - data_start;
+        self.finish_entry(hasher.finalize(), compressed_len, len)
+    }
+
+    // this writes the central directory, and returns the output
+    pub fn finish(mut self) -> Result<W> {
+        let directory_start = self.output.count;
+        let mut record = Vec::new();
+
+        for entry in &self.entries {
+            put_u32(&mut record, CENTRAL_HEADER_SIGNATURE);
+            put_u16(&mut record, VERSION_MADE_BY);
+            put_u16(&mut record, VERSION);
+            put_u16(&mut record, FLAGS);
+            put_u16(&mut record, entry.method);
+            put_u16(&mut record, 0); // time
+            put_u16(&mut record, DOS_DATE);
+            put_u32(&mut record, entry.crc);
+            put_u32(&mut record, u32::MAX); // the sizes and offset are in the zip64 extra field
+            put_u32(&mut record, u32::MAX);
+            put_u16(&mut record, name_len(&entry.name)?);
+            put_u16(&mut record, 28); // the length of the zip64 extra field
+            put_u16(&mut record, 0); // comment length
+            put_u16(&mut record, 0); // disk number
+            put_u16(&mut record, 0); // internal attributes
+            put_u32(&mut record, entry.attributes);
+            put_u32(&mut record, u32::MAX);
+            record.extend_from_slice(entry.name.as_bytes());
+            put_u16(&mut record, ZIP64_EXTRA_ID);
+            put_u16(&mut record, 24);
+            put_u64(&mut record, entry.len);
+            put_u64(&mut record, entry.compressed_len);
+            put_u64(&mut record, entry.header_offset);
+        }
+
+        let directory_len = record.len() as u64;
+        let zip64_end_start = directory_start + directory_len;
+        let entries = self.entries.len() as u64;
+
+        put_u32(&mut record, ZIP64_END_SIGNATURE);
+        put_u64(&mut record, 44); // the size of the rest of this record
+        put_u16(&mut record, VERSION_MADE_BY);
+        put_u16(&mut record, VERSION);
+        put_u32(&mut record, 0); // disk number
+        put_u32(&mut record, 0); // the disk that the central directory starts on
+        put_u64(&mut record, entries);
+        put_u64(&mut record, entries);
+        put_u64(&mut record, directory_len);
+        put_u64(&mut record, directory_start);
+
+        put_u32(&mut record, ZIP64_LOCATOR_SIGNATURE);
+        put_u32(&mut record, 0); // the disk that the zip64 end record is on
+        put_u64(&mut record, zip64_end_start);
+        put_u32(&mut record, 1); // the total number of disks
+
+        // these values are all in the zip64 end record instead
+        put_u32(&mut record, END_SIGNATURE);
+        put_u16(&mut record, 0);
+        put_u16(&mut record, 0);
+        put_u16(&mut record, u16::MAX);
+        put_u16(&mut record, u16::MAX);
+        put_u32(&mut record, u32::MAX);
+        put_u32(&mut record, u32::MAX);
+        put_u16(&mut record, 0); // comment length
+
+        self.write(&record)?;
+        self.output.flush().context("Unable to flush the archive")?;
+
+        Ok(self.output.inner)
+    }
+
+    // the crc and sizes aren't known yet, so they're left as 0 (the zip64 extra field is still needed, so readers know the data descriptor holds 64-bit sizes)
+    fn start_entry(&mut self, name: &str, method: u16, attributes: u32) -> Result<()> {
+        self.entries.push(Entry {
+            name: name.to_string(),
+            method,
+            attributes,
+            crc: 0,
+            compressed_len: 0,
+            len: 0,
+            header_offset: self.output.count,
+        });
+
+        let mut record = Vec::new();
+        put_u32(&mut record, LOCAL_HEADER_SIGNATURE);
+        put_u16(&mut record, VERSION);
+        put_u16(&mut record, FLAGS);
+        put_u16(&mut record, method);
+        put_u16(&mut record, 0); // time
+        put_u16(&mut record, DOS_DATE);
+        put_u32(&mut record, 0); // crc
+        put_u32(&mut record, u32::MAX); // the sizes are in the zip64 extra field
+        put_u32(&mut record, u32::MAX);
+        put_u16(&mut record, name_len(name)?);
+        put_u16(&mut record, 20); // the length of the zip64 extra field
+        record.extend_from_slice(name.as_bytes());
+        put_u16(&mut record, ZIP64_EXTRA_ID);
+        put_u16(&mut record, 16);
+        put_u64(&mut record, 0);
+        put_u64(&mut record, 0);
+
+        self.write(&record)
+    }
+
+    fn finish_entry(&mut self, crc: u32, compressed_len: u64, len: u64) -> Result<()> {
+        let entry = self
+            .entries
+            .last_mut()
+            .context("No entry has been started")?;
+        entry.crc = crc;
+        entry.compressed_len = compressed_len;
+        entry.len = len;
+
+        let mut record = Vec::new();
+        put_u32(&mut record, DATA_DESCRIPTOR_SIGNATURE);
+        put_u32(&mut record, crc);
+        put_u64(&mut record, compressed_len);
+        put_u64(&mut record, len);
+
+        self.write(&record)
+    }
+
+    fn write(&mut self, record: &[u8]) -> Result<()> {
+        self.output
+            .write_all(record)
+            .context("Unable to write to the archive")
+    }
+}
+
+// this counts the bytes that are written, as the offsets and compressed sizes are needed for the central directory
+struct CountingWriter<W: Write> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let write_count = self.inner.write(buf)?;
+        self.count += write_count as u64;
+        std::io::Result::Ok(write_count)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn name_len(name: &str) -> Result<u16> {
+    u16::try_from(name.len())
+        .with_context(|| format!("The name is too long for a zip archive: {}", name))
+}
+
+fn put_u16(record: &mut Vec<u8>, value: u16) {
+    record.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(record: &mut Vec<u8>, value: u32) {
+    record.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u64(record: &mut Vec<u8>, value: u64) {
+    record.extend_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use zip::ZipArchive;
+
+    fn read_back(archive: Vec<u8>) -> ZipArchive<Cursor<Vec<u8>>> {
+        ZipArchive::new(Cursor::new(archive)).unwrap()
+    }
+
+    #[test]
+    fn empty_directory_round_trips() {
+        let mut writer = ZipWriter::new(Vec::new(), 6);
+        writer.add_directory("empty").unwrap();
+        let mut archive = read_back(writer.finish().unwrap());
+
+        assert_eq!(archive.len(), 1);
+        let entry = archive.by_index(0).unwrap();
+        assert_eq!(entry.name(), "empty/");
+        assert!(entry.is_dir());
+        assert_eq!(entry.size(), 0);
+    }
+
+    #[test]
+    fn empty_archive_round_trips() {
+        let writer = ZipWriter::new(Vec::new(), 6);
+        let archive = read_back(writer.finish().unwrap());
+
+        assert_eq!(archive.len(), 0);
+    }
+
+    #[test]
+    fn files_round_trip() {
+        let first = b"hello world".to_vec();
+        let second: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+
+        let mut writer = ZipWriter::new(Vec::new(), 6);
+        let mut progress = 0;
+        writer.add_directory("dir/").unwrap();
+        writer
+            .add_file("dir/first.txt", &mut first.as_slice(), |count| {
+                progress += count;
+            })
+            .unwrap();
+        writer
+            .add_file("second.bin", &mut second.as_slice(), |_| ())
+            .unwrap();
+        writer
+            .add_file("empty.txt", &mut [].as_slice(), |_| ())
+            .unwrap();
+        let mut archive = read_back(writer.finish().unwrap());
+
+        assert_eq!(progress, first.len() as u64);
+        assert_eq!(archive.len(), 4);
+        assert_eq!(archive.by_index(0).unwrap().name(), "dir/");
+
+        for (name, expected) in [
+            ("dir/first.txt", &first),
+            ("second.bin", &second),
+            ("empty.txt", &Vec::new()),
+        ] {
+            let mut entry = archive.by_name(name).unwrap();
+            assert_eq!(entry.size(), expected.len() as u64);
+            assert_eq!(entry.unix_mode(), Some(0o100_755));
+
+            // the crc is checked by the zip crate once the whole entry has been read
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data).unwrap();
+            assert_eq!(&data, expected);
+        }
+    }
+
+    #[test]
+    fn non_ascii_names_round_trip() {
+        let names = [
+            "données/",
+            "données/résumé.txt",
+            "日本語.txt",
+            "emoji 🦀.txt",
+        ];
+
+        let mut writer = ZipWriter::new(Vec::new(), 6);
+        writer.add_directory(names[0]).unwrap();
+        for name in &names[1..] {
+            writer.add_file(name, &mut name.as_bytes(), |_| ()).unwrap();
+        }
+        let mut archive = read_back(writer.finish().unwrap());
+
+        assert_eq!(archive.len(), names.len());
+        for (i, name) in names.iter().enumerate() {
+            let mut entry = archive.by_index(i).unwrap();
+            assert_eq!(entry.name(), *name);
+            assert_eq!(entry.enclosed_name(), Some(std::path::Path::new(name)));
+
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data).unwrap();
+            if !entry.is_dir() {
+                assert_eq!(data, name.as_bytes());
+            }
+        }
+    }
+
+    // every entry uses the zip64 fields, so the 32-bit sizes and offsets are always 0xffffffff
+    #[test]
+    fn entries_use_zip64_fields() {
+        let mut writer = ZipWriter::new(Vec::new(), 6);
+        writer
+            .add_file("file.txt", &mut b"data".as_slice(), |_| ())
+            .unwrap();
+        let archive = writer.finish().unwrap();
+
+        let central = archive
+            .windows(4)
+            .position(|window| window == CENTRAL_HEADER_SIGNATURE.to_le_bytes())
+            .unwrap();
+        assert_eq!(archive[central + 20..central + 28], [0xff; 8]);
+        assert_eq!(archive[central + 42..central + 46], [0xff; 4]);
+        assert!(archive
+            .windows(4)
+            .any(|window| window == ZIP64_END_SIGNATURE.to_le_bytes()));
+
+        let mut archive = read_back(archive);
+        let mut entry = archive.by_index(0).unwrap();
+        assert_eq!(entry.size(), 4);
+        assert_eq!(entry.header_start(), 0);
+
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"data");
+    }
+
+    // this compresses (and then decompresses) more than 4GiB, so it's slow - run it with `cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn file_larger_than_4gib_round_trips() {
+        let len = u64::from(u32::MAX) + 4096;
+
+        let mut writer = ZipWriter::new(Vec::new(), 1);
+        writer
+            .add_file("large.bin", &mut std::io::repeat(0).take(len), |_| ())
+            .unwrap();
+        writer
+            .add_file("after.txt", &mut b"after".as_slice(), |_| ())
+            .unwrap();
+        let mut archive = read_back(writer.finish().unwrap());
+
+        {
+            let mut entry = archive.by_name("large.bin").unwrap();
+            assert_eq!(entry.size(), len);
+            assert_eq!(
+                std::io::copy(&mut entry, &mut std::io::sink()).unwrap(),
+                len
+            );
+        }
+
+        let mut data = Vec::new();
+        archive
+            .by_name("after.txt")
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, b"after");
+    }
+}